@@ -0,0 +1,82 @@
+//! Measures how the size of the read buffer influences the time it takes to
+//! receive a request with a large body.
+//!
+//! Run the benchmark by executing `cargo bench --bench read_buffer`.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio_fastcgi::{Requests, RequestResult};
+
+/// Size of the request body that is uploaded for every iteration.
+const UPLOAD_SIZE: usize = 4 * 1024 * 1024;
+
+/// Maximum content length of one record.
+const MAX_CONTENT_LENGTH: usize = 65535;
+
+/// Appends a FastCGI record with the passed type and content to `out`.
+fn push_record(out: &mut Vec<u8>, record_type: u8, content: &[u8]) {
+	out.extend_from_slice(&[1, record_type, 0, 1]);
+	out.extend_from_slice(&(content.len() as u16).to_be_bytes());
+	out.extend_from_slice(&[0, 0]);
+	out.extend_from_slice(content);
+}
+
+/// Returns the records of a responder request with a body of `UPLOAD_SIZE`
+/// bytes.
+fn upload_request() -> Vec<u8> {
+	let mut input = Vec::with_capacity(UPLOAD_SIZE + UPLOAD_SIZE / MAX_CONTENT_LENGTH * 8 + 64);
+
+	push_record(&mut input, 1, &[0, 1, 0, 0, 0, 0, 0, 0]);
+	push_record(&mut input, 4, &[]);
+	for chunk in vec![0x55u8; UPLOAD_SIZE].chunks(MAX_CONTENT_LENGTH) {
+		push_record(&mut input, 5, chunk);
+	}
+	push_record(&mut input, 5, &[]);
+
+	input
+}
+
+/// Sends `input` via a local TCP connection and processes the request with a
+/// read buffer of `buffer_size` bytes.
+///
+/// A real socket is used to include the cost of the read calls into the
+/// measurement.
+async fn receive(listener: &TcpListener, input: &[u8], buffer_size: usize) {
+	let (mut client, (server, _)) = tokio::try_join!(TcpStream::connect(listener.local_addr().unwrap()), listener.accept()).unwrap();
+
+	let sender = async move {
+		client.write_all(input).await.unwrap();
+		client.shutdown().await.unwrap();
+	};
+
+	let receiver = async move {
+		let mut requests = Requests::new(server, tokio::io::sink(), 1, 1).read_buffer_size(buffer_size);
+
+		while let Some(request) = requests.next().await.unwrap() {
+			request.process(|_request| async move { RequestResult::Complete(0) }).await.unwrap();
+		}
+	};
+
+	tokio::join!(sender, receiver);
+}
+
+fn read_buffer_size(c: &mut Criterion) {
+	let runtime = Runtime::new().unwrap();
+	let input = upload_request();
+	let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+
+	let mut group = c.benchmark_group("read_buffer_size");
+	group.throughput(Throughput::Bytes(input.len() as u64));
+
+	for buffer_size in [1024, 4 * 1024, 16 * 1024, 64 * 1024, 256 * 1024] {
+		group.bench_with_input(BenchmarkId::from_parameter(buffer_size), &buffer_size, |b, &buffer_size| {
+			b.to_async(&runtime).iter(|| receive(&listener, &input, buffer_size));
+		});
+	}
+
+	group.finish();
+}
+
+criterion_group!(benches, read_buffer_size);
+criterion_main!(benches);
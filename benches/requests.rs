@@ -0,0 +1,86 @@
+//! Measures the overhead of assembling requests.
+//!
+//! The most common deployment sends one request per connection. This is
+//! compared against a connection that multiplexes two requests. The records are
+//! read from memory to measure the book keeping instead of the I/O.
+//!
+//! Run the benchmark by executing `cargo bench --bench requests`.
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use tokio::runtime::Runtime;
+use tokio_fastcgi::{Requests, RequestResult};
+
+/// Flag for FCGI_BeginRequestBody
+const FCGI_KEEP_CONN: u8 = 0x01;
+
+/// Parameters sent with every request.
+const PARAMS: &[u8] = b"\x0E\x04REQUEST_METHODPOST\x0B\x0CREQUEST_URI/api/v1/item\x0C\x10CONTENT_TYPEapplication/json";
+
+/// Body sent with every request.
+const BODY: &[u8] = b"{\"name\": \"benchmark\"}";
+
+/// Appends a FastCGI record with the passed type and content to `out`.
+fn push_record(out: &mut Vec<u8>, record_type: u8, request_id: u16, content: &[u8]) {
+	out.extend_from_slice(&[1, record_type]);
+	out.extend_from_slice(&request_id.to_be_bytes());
+	out.extend_from_slice(&(content.len() as u16).to_be_bytes());
+	out.extend_from_slice(&[0, 0]);
+	out.extend_from_slice(content);
+}
+
+/// Returns the records of one responder request without the keep connection
+/// flag.
+fn single_request() -> Vec<u8> {
+	let mut input = Vec::new();
+
+	push_record(&mut input, 1, 1, &[0, 1, 0, 0, 0, 0, 0, 0]);
+	push_record(&mut input, 4, 1, PARAMS);
+	push_record(&mut input, 4, 1, &[]);
+	push_record(&mut input, 5, 1, BODY);
+	push_record(&mut input, 5, 1, &[]);
+
+	input
+}
+
+/// Returns the records of two interleaved responder requests.
+fn multiplexed_requests() -> Vec<u8> {
+	let mut input = Vec::new();
+
+	for request_id in [1, 2] {
+		push_record(&mut input, 1, request_id, &[0, 1, FCGI_KEEP_CONN, 0, 0, 0, 0, 0]);
+	}
+	for (record_type, content) in [(4, PARAMS), (4, &[][..]), (5, BODY), (5, &[][..])] {
+		for request_id in [1, 2] {
+			push_record(&mut input, record_type, request_id, content);
+		}
+	}
+
+	input
+}
+
+/// Serves all requests within `input`.
+async fn serve(input: &[u8]) {
+	let mut requests = Requests::new(input, tokio::io::sink(), 1, 2);
+
+	while let Some(request) = requests.next().await.unwrap() {
+		request.process(|_request| async move { RequestResult::Complete(0) }).await.unwrap();
+	}
+}
+
+fn requests(c: &mut Criterion) {
+	let runtime = Runtime::new().unwrap();
+
+	let mut group = c.benchmark_group("requests");
+
+	let input = single_request();
+	group.throughput(Throughput::Elements(1));
+	group.bench_function("single", |b| b.to_async(&runtime).iter(|| serve(&input)));
+
+	let input = multiplexed_requests();
+	group.throughput(Throughput::Elements(2));
+	group.bench_function("multiplexed", |b| b.to_async(&runtime).iter(|| serve(&input)));
+
+	group.finish();
+}
+
+criterion_group!(benches, requests);
+criterion_main!(benches);
@@ -0,0 +1,114 @@
+use std::sync::Arc;
+use tokio::io::AsyncWrite;
+use tokio::net::TcpListener;
+use tokio_fastcgi::{Request, Requests, RequestResult, Role};
+
+// This example implements a FastCGI authorizer. The web-server asks the
+// authorizer for every request to a protected location. If the authorizer
+// answers with `200 OK`, the request is passed on and the `Variable-` headers
+// of the answer are added to its parameters. Any other answer is sent to the
+// client instead.
+//
+// nginx does not support the authorizer role. Apache supports it via
+// mod_authnz_fcgi:
+//
+//   AuthnzFcgiDefineProvider authz TokenAuthz fcgi://127.0.0.1:8080/
+//   <Location "/protected/">
+//     AuthType None
+//     Require TokenAuthz
+//   </Location>
+//
+// lighttpd supports it via mod_fastcgi:
+//
+//   fastcgi.server = ( "/protected/" => ((
+//     "host" => "127.0.0.1",
+//     "port" => 8080,
+//     "mode" => "authorizer",
+//     "docroot" => "/var/www/protected"
+//   )))
+//
+// Run the tests of this example by executing `cargo test --example authorizer`.
+
+/// Users and their API tokens.
+const USERS: &[(&str, &str)] = &[("alice", "secret-token-1"), ("bob", "secret-token-2")];
+
+/// Grants access if the `Authorization` header carries the token of a known
+/// user. The name of the user is passed on as `REMOTE_USER`.
+async fn authorizer<W: AsyncWrite + Unpin>(request: Arc<Request<W>>) -> RequestResult {
+	if request.role != Role::Authorizer {
+		return RequestResult::UnknownRole;
+	}
+
+	let token = request.header("Authorization")
+		.and_then(|value| std::str::from_utf8(value).ok())
+		.and_then(|value| value.strip_prefix("Bearer "));
+
+	match USERS.iter().find(|(_, user_token)| Some(*user_token) == token) {
+		Some((user, _)) => {
+			request.set_status(200);
+			request.set_variable("REMOTE_USER", user);
+
+			RequestResult::Complete(0)
+		},
+		None => request.respond_with(403, &[("Content-Type", "text/plain")], b"Access denied").await.unwrap_or(RequestResult::Complete(1))
+	}
+}
+
+#[tokio::main]
+async fn main() {
+	let addr = "127.0.0.1:8080";
+	let listener = TcpListener::bind(addr).await.unwrap();
+
+	loop {
+		match listener.accept().await {
+			Err(err) => {
+				println!("Establishing connection failed: {}", err);
+				break;
+			},
+			Ok((mut stream, _)) => {
+				tokio::spawn(async move {
+					// Only authorizer requests reach the processor.
+					let mut requests = Requests::from_split_socket(stream.split(), 10, 10)
+						.accepted_roles(&[Role::Authorizer]);
+
+					while let Ok(Some(request)) = requests.next().await {
+						if let Err(err) = request.process(authorizer).await {
+							println!("Processing request failed: {}", err);
+						}
+					}
+				});
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn grants_access() {
+		let (request, output) = Request::test_builder()
+			.role(Role::Authorizer)
+			.param("HTTP_AUTHORIZATION", "Bearer secret-token-2")
+			.build();
+
+		request.process(authorizer).await.unwrap();
+
+		assert_eq!(output.stdout(), b"Status: 200 OK\r\nVariable-REMOTE_USER: bob\r\n\r\n");
+		assert_eq!(output.end_request(), Some((0, 0)));
+	}
+
+	#[tokio::test]
+	async fn denies_access() {
+		let (request, output) = Request::test_builder()
+			.role(Role::Authorizer)
+			.param("HTTP_AUTHORIZATION", "Bearer guessed-token")
+			.build();
+
+		request.process(authorizer).await.unwrap();
+
+		assert_eq!(output.stdout(), b"Status: 403 Forbidden\r\nContent-Type: text/plain\r\nContent-Length: 13\r\n\r\nAccess denied");
+		assert_eq!(output.end_request(), Some((0, 0)));
+	}
+}
@@ -0,0 +1,51 @@
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::service::service_fn;
+use std::convert::Infallible;
+use tokio::net::TcpListener;
+use tokio_fastcgi::{HyperRequestBody, Requests, RequestResult};
+
+// This example serves a hyper service via FastCGI. The service is a plain
+// `service_fn` that could be passed to a hyper server as well.
+//
+// Start the example by running `cargo run --features hyper --example hyper`.
+
+/// Answers every request with the method and the URI it was called with.
+async fn hello(request: http::Request<HyperRequestBody>) -> Result<http::Response<Full<Bytes>>, Infallible> {
+	let body = format!("Hello from hyper! You called {} {}\n", request.method(), request.uri());
+
+	Ok(http::Response::builder()
+		.header("Content-Type", "text/plain")
+		.body(Full::new(Bytes::from(body)))
+		.unwrap())
+}
+
+#[tokio::main]
+async fn main() {
+	let addr = "127.0.0.1:8080";
+	let listener = TcpListener::bind(addr).await.unwrap();
+
+	loop {
+		match listener.accept().await {
+			Err(err) => {
+				println!("Establishing connection failed: {}", err);
+				break;
+			},
+			Ok((mut stream, _)) => {
+				tokio::spawn(async move {
+					let service = service_fn(hello);
+					let mut requests = Requests::from_split_socket(stream.split(), 10, 10);
+
+					while let Ok(Some(request)) = requests.next().await {
+						let service = &service;
+						if let Err(err) = request.process(|request| async move {
+							request.serve_hyper(service).await.unwrap_or(RequestResult::Complete(1))
+						}).await {
+							println!("Processing request failed: {}", err);
+						}
+					}
+				});
+			}
+		}
+	}
+}
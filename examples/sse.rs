@@ -0,0 +1,83 @@
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_fastcgi::{Requests, RequestResult};
+
+// This example streams server-sent events to the client. Every second a new
+// event is generated and flushed to the web-server. The web-server must not
+// buffer the response for the events to reach the client immediately.
+//
+// For nginx, response buffering can be disabled via `fastcgi_buffering`:
+//
+// ``` conf
+// location /events {
+//   fastcgi_pass 127.0.0.1:8080;
+//   fastcgi_buffering off;
+//   include fastcgi_params;
+// }
+// ```
+//
+// For Apache, the `flushpackets` option of `mod_proxy` must be set:
+//
+// ``` conf
+// <Location /events>
+//   ProxyPass "fcgi://127.0.0.1:8080/" flushpackets=on
+// </Location>
+// ```
+//
+// Start the example by running `cargo run --example sse` and connect to it via
+// `curl -N http://localhost/events`.
+
+/// Number of events to send before the response is completed.
+const EVENT_COUNT: u32 = 10;
+
+#[tokio::main]
+async fn main() {
+	let addr = "127.0.0.1:8080";
+	let listener = TcpListener::bind(addr).await.unwrap();
+
+	loop {
+		let connection = listener.accept().await;
+		// Accept new connections
+		match connection {
+			Err(err) => {
+				println!("Establishing connection failed: {}", err);
+				break;
+			},
+			Ok((mut stream, address)) => {
+				println!("Connection from {}", address);
+
+				tokio::spawn(async move {
+					let mut requests = Requests::from_split_socket(stream.split(), 10, 10);
+
+					while let Ok(Some(request)) = requests.next().await {
+						if let Err(err) = request.process(|request| async move {
+							// The headers are sent right away. This makes the web-server start the
+							// response to the client.
+							let mut stream = match request.stream(200, &[("Content-Type", "text/event-stream"), ("Cache-Control", "no-cache")]).await {
+								Ok(stream) => stream,
+								Err(_) => return RequestResult::Complete(1)
+							};
+
+							for event in 0..EVENT_COUNT {
+								// Every event is flushed on its own to deliver it to the web-server
+								// while the next one is still being generated.
+								stream.write(format!("id: {}\n", event).as_bytes());
+								stream.write(format!("data: Event number {}\n\n", event).as_bytes());
+								if stream.flush().await.is_err() {
+									return RequestResult::Complete(1);
+								}
+
+								tokio::time::sleep(Duration::from_secs(1)).await;
+							}
+
+							stream.finish().await.unwrap_or(RequestResult::Complete(1))
+						}).await {
+							// This is the error handler that is called if the process call returns an error.
+							println!("Processing request failed: {}", err);
+						}
+					}
+				});
+			}
+		}
+	}
+}
@@ -0,0 +1,126 @@
+use std::io::Read;
+use std::sync::Arc;
+use tokio::io::AsyncWrite;
+use tokio::net::TcpListener;
+use tokio_fastcgi::{Request, Requests, RequestResult};
+
+// This example shows how a request processor can be unit tested without
+// running a web-server. The processor is a generic function that works with
+// any writer. Within the tests it is called with a request that was built via
+// `Request::test_builder`. To test the handling of the FastCGI records, the
+// processor can also be run on an in memory connection created by
+// `Requests::new_duplex`.
+//
+// Run the tests of this example by executing `cargo test --example unittest`.
+
+/// Greets the user passed via the `name` query parameter. If the request is a
+/// POST request, the body is echoed back.
+async fn greeter<W: AsyncWrite + Unpin>(request: Arc<Request<W>>) -> RequestResult {
+	let name = request.get_str_param("QUERY_STRING")
+		.and_then(|query| query.strip_prefix("name="))
+		.unwrap_or("stranger")
+		.to_string();
+
+	let mut body = format!("Hello {}!", name);
+
+	if request.get_str_param("REQUEST_METHOD") == Some("POST") {
+		let mut message = String::new();
+		if request.get_stdin().read_to_string(&mut message).is_err() {
+			return request.respond_with(400, &[], b"Invalid body").await.unwrap_or(RequestResult::Complete(1));
+		}
+
+		body.push_str(&format!(" You said: {}", message));
+	}
+
+	request.respond_with(200, &[("Content-Type", "text/plain")], body.as_bytes()).await.unwrap_or(RequestResult::Complete(1))
+}
+
+#[tokio::main]
+async fn main() {
+	let addr = "127.0.0.1:8080";
+	let listener = TcpListener::bind(addr).await.unwrap();
+
+	loop {
+		match listener.accept().await {
+			Err(err) => {
+				println!("Establishing connection failed: {}", err);
+				break;
+			},
+			Ok((mut stream, _)) => {
+				tokio::spawn(async move {
+					let mut requests = Requests::from_split_socket(stream.split(), 10, 10);
+
+					while let Ok(Some(request)) = requests.next().await {
+						if let Err(err) = request.process(greeter).await {
+							println!("Processing request failed: {}", err);
+						}
+					}
+				});
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+	#[tokio::test]
+	async fn greets_the_user() {
+		let (request, output) = Request::test_builder()
+			.param("REQUEST_METHOD", "GET")
+			.param("QUERY_STRING", "name=Ferris")
+			.build();
+
+		request.process(greeter).await.unwrap();
+
+		assert_eq!(output.stdout(), b"Status: 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 13\r\n\r\nHello Ferris!");
+		assert_eq!(output.end_request(), Some((0, 0)));
+	}
+
+	/// Returns a FastCGI record of the given type for request 1.
+	fn record(record_type: u8, content: &[u8]) -> Vec<u8> {
+		let mut record = vec![1, record_type, 0, 1];
+		record.extend_from_slice(&(content.len() as u16).to_be_bytes());
+		record.extend_from_slice(&[0, 0]);
+		record.extend_from_slice(content);
+		record
+	}
+
+	#[tokio::test]
+	async fn serves_a_connection() {
+		// Drive the processor via a real FastCGI connection held in memory.
+		let (mut requests, mut client) = Requests::new_duplex(64 * 1024);
+
+		client.write_all(&record(1, &[0, 1, 0, 0, 0, 0, 0, 0])).await.unwrap();
+		client.write_all(&record(4, b"\x0C\x0BQUERY_STRINGname=Ferris")).await.unwrap();
+		client.write_all(&record(4, &[])).await.unwrap();
+		client.write_all(&record(5, &[])).await.unwrap();
+
+		while let Some(request) = requests.next().await.unwrap() {
+			request.process(greeter).await.unwrap();
+		}
+		drop(requests);
+
+		let mut response = Vec::new();
+		client.read_to_end(&mut response).await.unwrap();
+
+		let expected = b"Status: 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 13\r\n\r\nHello Ferris!";
+		assert_eq!(&response[..8], &[1, 6, 0, 1, 0, expected.len() as u8, 0, 0]);
+		assert_eq!(&response[8..8 + expected.len()], expected);
+	}
+
+	#[tokio::test]
+	async fn echoes_the_body() {
+		let (request, output) = Request::test_builder()
+			.param("REQUEST_METHOD", "POST")
+			.stdin(b"Nice to meet you")
+			.build();
+
+		request.process(greeter).await.unwrap();
+
+		assert_eq!(output.stdout(), b"Status: 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 42\r\n\r\nHello stranger! You said: Nice to meet you");
+		assert!(output.stderr().is_empty());
+	}
+}
@@ -0,0 +1,185 @@
+//! Bridge to write output from synchronous code.
+//!
+//! Blocking code like a template engine often expects a [`std::io::Write`]
+//! implementation. The [`BlockingWriter`] returned by
+//! [`OutStream::blocking_writer`] provides one on top of an [`OutStream`].
+use std::io::Write;
+use tokio::io::AsyncWrite;
+use tokio::runtime::Handle;
+
+use log::warn;
+
+use crate::OutStream;
+
+/// Number of bytes collected before a record is sent to the web-server.
+const BUFFER_SIZE: usize = 8 * 1024;
+
+/// Writes to an [`OutStream`] from a synchronous context.
+///
+/// The writer collects the written data and sends it to the web-server in
+/// records of up to 8 KiB. Calling [`flush`](Write::flush) sends the collected
+/// data immediately and flushes the underlying stream. The writer must be
+/// flushed before it is dropped. Dropping never blocks. Data that was not
+/// flushed is discarded and a warning is logged. A writer without collected
+/// data can be dropped anywhere, for example when the processor returns early.
+///
+/// ## Runtime requirements
+///
+/// Every write that sends a record blocks the current thread until the record
+/// was written. This must never happen on a thread that drives asynchronous
+/// tasks. The writer must only be used within
+/// [`tokio::task::spawn_blocking`] or `tokio::task::block_in_place`.
+/// Using it directly within an async function panics. `block_in_place` is only
+/// available on the multi-threaded runtime.
+///
+/// ## Example
+///
+/// ```rust
+/// # use std::io::Write;
+/// # use tokio::io::{empty, sink};
+/// # use tokio_fastcgi::{Requests, RequestResult};
+/// # #[tokio::main]
+/// # async fn main() {
+/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+/// request.process(|request| async move {
+///   let mut writer = request.get_stdout().blocking_writer();
+///
+///   let result = tokio::task::spawn_blocking(move || {
+///     // Call some synchronous code that writes to `writer`.
+///     writeln!(writer, "Content-Type: text/plain\r\n")?;
+///     writeln!(writer, "Hello from a blocking task")?;
+///     writer.flush()
+///   }).await;
+///
+///   match result {
+///     Ok(Ok(())) => RequestResult::Complete(0),
+///     _ => RequestResult::Complete(1)
+///   }
+/// });
+/// # } }
+/// ```
+pub struct BlockingWriter<W: AsyncWrite + Unpin> {
+	stream: OutStream<W>,
+	handle: Handle,
+	buffer: Vec<u8>
+}
+
+impl <W: AsyncWrite + Unpin> BlockingWriter<W> {
+	/// Sends the collected data to the web-server.
+	fn send_buffer(&mut self) -> std::io::Result<()> {
+		if !self.buffer.is_empty() {
+			let stream = &mut self.stream;
+			self.handle.block_on(stream.write(&self.buffer)).map_err(std::io::Error::from)?;
+			self.buffer.clear();
+		}
+
+		Ok(())
+	}
+}
+
+impl <W: AsyncWrite + Unpin> Write for BlockingWriter<W> {
+	fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+		if self.buffer.len() >= BUFFER_SIZE {
+			self.send_buffer()?;
+		}
+
+		let length = data.len().min(BUFFER_SIZE - self.buffer.len());
+		self.buffer.extend_from_slice(&data[..length]);
+
+		Ok(length)
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		self.send_buffer()?;
+
+		let stream = &self.stream;
+		self.handle.block_on(stream.flush())
+	}
+}
+
+impl <W: AsyncWrite + Unpin> Drop for BlockingWriter<W> {
+	fn drop(&mut self) {
+		// Drop may run within an asynchronous context. Blocking there is not possible.
+		if !self.buffer.is_empty() {
+			warn!("FastCGI: BlockingWriter dropped without flushing it. {} bytes were discarded.", self.buffer.len());
+		}
+	}
+}
+
+impl <W: AsyncWrite + Unpin> OutStream<W> {
+	/// Returns a [`BlockingWriter`] that allows writing to this stream from
+	/// synchronous code.
+	///
+	/// This function must be called within the Tokio runtime. The returned
+	/// writer must only be used within a blocking context. See
+	/// [`BlockingWriter`] for details.
+	pub fn blocking_writer(self) -> BlockingWriter<W> {
+		BlockingWriter {
+			stream: self,
+			handle: Handle::current(),
+			buffer: Vec::with_capacity(BUFFER_SIZE)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Request, RequestResult};
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn blocking_writer() {
+		let (request, output) = Request::test_builder().build();
+
+		let body: Vec<u8> = (0..20000u32).map(|i| i as u8).collect();
+		let expected = body.clone();
+
+		request.process(|request| async move {
+			let mut writer = request.get_stdout().blocking_writer();
+
+			tokio::task::block_in_place(move || {
+				for chunk in body.chunks(1000) {
+					writer.write_all(chunk).unwrap();
+				}
+				writer.flush().unwrap();
+			});
+
+			RequestResult::Complete(0)
+		}).await.unwrap();
+
+		// The data is sent in records of 8 KiB. The rest is sent on flush.
+		let stdout_records: Vec<usize> = output.records().into_iter()
+			.filter(|(record_type, _, _)| *record_type == 6)
+			.map(|(_, _, content)| content.len())
+			.collect();
+		assert_eq!(stdout_records, vec![8192, 8192, 3616, 0]);
+		assert_eq!(output.stdout(), expected);
+		assert_eq!(output.end_request(), Some((0, 0)));
+	}
+
+	#[tokio::test]
+	async fn blocking_writer_dropped_without_flush() {
+		let (request, output) = Request::test_builder().build();
+
+		request.process(|request| async move {
+			// An unused writer is dropped without blocking.
+			drop(request.get_stdout().blocking_writer());
+
+			// Data that was not flushed is discarded, even within a blocking context.
+			let mut writer = request.get_stdout().blocking_writer();
+			tokio::task::spawn_blocking(move || {
+				writer.write_all(b"Lost").unwrap();
+			}).await.unwrap();
+
+			let mut writer = request.get_stdout().blocking_writer();
+			writer.write_all(b"Lost").unwrap();
+			drop(writer);
+
+			RequestResult::Complete(0)
+		}).await.unwrap();
+
+		assert_eq!(output.stdout(), b"");
+		assert_eq!(output.end_request(), Some((0, 0)));
+	}
+}
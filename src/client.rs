@@ -0,0 +1,359 @@
+//! Client side of the FastCGI protocol.
+//!
+//! The [`Client`] talks to a FastCGI application the way a web-server does.
+//! It is used to query the limits of an upstream application and to send
+//! requests to it.
+use std::collections::{HashMap, VecDeque};
+use log::{debug, trace};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::{write_length, Error, Request, RequestId, Role, StdReqType, StdRespType, SysReqType, SysRespType, DEFAULT_READ_BUFFER_SIZE, FCGI_KEEP_CONN, RECORD_HEADER_SIZE};
+
+/// Maximum content length of a single record.
+const MAX_CONTENT_LENGTH: usize = u16::MAX as usize;
+
+/// Response of the FastCGI application to a request sent via
+/// [`Client::send_request`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientResponse {
+	/// Id the request was sent with.
+	pub request_id: u16,
+	/// Everything the application sent via `StdOut`.
+	pub stdout: Vec<u8>,
+	/// Everything the application sent via `StdErr`.
+	pub stderr: Vec<u8>,
+	/// Application status of the `EndRequest` record.
+	pub app_status: u32,
+	/// Protocol status of the `EndRequest` record. `0` if the request was
+	/// completed.
+	pub protocol_status: u8
+}
+
+/// Connection to a FastCGI application.
+///
+/// The client sends its records via the write half and receives the answers
+/// of the application via the read half of a connection.
+///
+/// ## Example
+///
+/// ```rust
+/// # use tokio_fastcgi::{Client, Requests};
+/// # #[tokio::main]
+/// # async fn main() {
+/// # let (mut requests, connection) = Requests::new_duplex(1024);
+/// # tokio::spawn(async move { requests.next().await });
+/// let mut client = Client::from_split_socket(tokio::io::split(connection));
+///
+/// let values = client.get_values(&["FCGI_MPXS_CONNS"]).await.unwrap();
+/// let multiplex = values.get("FCGI_MPXS_CONNS").map(|value| value == b"1").unwrap_or(false);
+/// # assert!(multiplex);
+/// # }
+/// ```
+pub struct Client<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> {
+	reader: BufReader<R>,
+	writer: W,
+	max_requests: usize,
+	free_ids: Vec<RequestId>,
+	next_id: u32,
+	in_flight: HashMap<RequestId, ClientResponse>,
+	completed: VecDeque<ClientResponse>
+}
+
+impl <R: AsyncRead + Unpin, W: AsyncWrite + Unpin> Client<R, W> {
+	/// Creates a new client that sends records to `wr` and reads the answers
+	/// of the FastCGI application from `rd`.
+	pub fn new(rd: R, wr: W) -> Self {
+		Self {
+			reader: BufReader::with_capacity(DEFAULT_READ_BUFFER_SIZE, rd),
+			writer: wr,
+			max_requests: 1,
+			free_ids: Vec::new(),
+			next_id: 1,
+			in_flight: HashMap::new(),
+			completed: VecDeque::new()
+		}
+	}
+
+	/// Same as [`new`](Client::new) but takes a tuple containing the read and
+	/// write side of the socket.
+	pub fn from_split_socket(split_socket: (R, W)) -> Self {
+		Self::new(split_socket.0, split_socket.1)
+	}
+
+	/// Sets the number of requests that may be in flight on this connection at
+	/// the same time.
+	///
+	/// By default only one request is sent at a time, because the application
+	/// may not support multiplexing. [`get_values`](Client::get_values) sets
+	/// this limit to the values the application advertises.
+	pub fn max_requests(mut self, max_requests: usize) -> Self {
+		self.max_requests = max_requests;
+		self
+	}
+
+	/// Queries configuration values of the FastCGI application via an
+	/// `FCGI_GET_VALUES` record.
+	///
+	/// The FastCGI specification defines the values `FCGI_MAX_CONNS`,
+	/// `FCGI_MAX_REQS` and `FCGI_MPXS_CONNS`. The application only answers the
+	/// values it knows. Unknown names are missing from the returned map. If
+	/// the application does not support `FCGI_GET_VALUES` at all, an empty map
+	/// is returned.
+	///
+	/// If the application answers `FCGI_MAX_REQS` or `FCGI_MPXS_CONNS`, the
+	/// number of requests that may be in flight at the same time is adjusted
+	/// (see [`max_requests`](Client::max_requests)).
+	///
+	/// If the names do not fit into one record, an I/O-error of kind
+	/// [`InvalidInput`](std::io::ErrorKind::InvalidInput) is returned. If the
+	/// application answers with any other record, a
+	/// [`SequenceError`](Error::SequenceError) is returned.
+	pub async fn get_values(&mut self, names: &[&str]) -> Result<HashMap<String, Vec<u8>>, Error> {
+		let mut content = Vec::with_capacity(names.iter().map(|name| name.len() + 2).sum());
+		for name in names {
+			write_length(&mut content, name.len());
+			write_length(&mut content, 0);
+			content.extend_from_slice(name.as_bytes());
+		}
+
+		self.write_record(SysReqType::GetValues.into(), 0, &content).await?;
+
+		// Records of requests in flight may arrive before the answer.
+		let (record_type, content) = loop {
+			let (record_type, request_id, content) = self.read_record().await?;
+			if request_id == 0 {
+				break (record_type, content);
+			}
+
+			self.route_record(record_type, request_id, content)?;
+		};
+
+		let mut values = HashMap::new();
+
+		if record_type == u8::from(SysRespType::GetValuesResult) {
+			Request::<W>::add_nv_pairs(&mut values, None, &content, &|name| String::from_utf8_lossy(name).into_owned())?;
+		} else if record_type == u8::from(SysRespType::UnknownType) && content.first() == Some(&SysReqType::GetValues.into()) {
+			trace!("FastCGI: Application does not support FCGI_GET_VALUES.");
+		} else {
+			return Err(Error::SequenceError);
+		}
+
+		let value = |name| values.get(name).and_then(|value: &Vec<u8>| std::str::from_utf8(value).ok()).and_then(|value| value.parse::<usize>().ok());
+		if let Some(max_requests) = value("FCGI_MAX_REQS") {
+			self.max_requests = max_requests;
+		}
+		if value("FCGI_MPXS_CONNS") == Some(0) {
+			self.max_requests = self.max_requests.min(1);
+		}
+
+		Ok(values)
+	}
+
+	/// Sends a responder request to the application and returns the id it was
+	/// sent with.
+	///
+	/// The id is allocated automatically. Ids of finished requests are reused.
+	/// The request consists of the parameters `params` and the body `stdin`.
+	/// It is sent completely before this function returns. The connection is
+	/// kept open after the request. The answer is fetched via
+	/// [`next_response`](Client::next_response).
+	///
+	/// If the maximum number of requests is already in flight (see
+	/// [`max_requests`](Client::max_requests)), nothing is sent and
+	/// [`RequestIdsExhausted`](Error::RequestIdsExhausted) is returned. If a
+	/// parameter does not fit into one record, an I/O-error of kind
+	/// [`InvalidInput`](std::io::ErrorKind::InvalidInput) is returned.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio_fastcgi::{Client, Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let (mut requests, connection) = Requests::new_duplex(1024);
+	/// # tokio::spawn(async move {
+	/// #   while let Some(request) = requests.next().await.unwrap() {
+	/// #     request.process(|request| async move { request.respond_with(200, &[], b"Hello").await.unwrap() }).await.unwrap();
+	/// #   }
+	/// # });
+	/// let mut client = Client::from_split_socket(tokio::io::split(connection));
+	///
+	/// let request_id = client.send_request(&[("REQUEST_METHOD", b"GET")], &[]).await.unwrap();
+	///
+	/// let response = client.next_response().await.unwrap().unwrap();
+	/// assert_eq!(response.request_id, request_id);
+	/// # assert!(response.stdout.ends_with(b"Hello"));
+	/// # }
+	/// ```
+	pub async fn send_request(&mut self, params: &[(&str, &[u8])], stdin: &[u8]) -> Result<u16, Error> {
+		// Every name-value pair must be contained in one record.
+		let mut params_records = vec![Vec::new()];
+		for (name, value) in params {
+			let mut pair = Vec::with_capacity(name.len() + value.len() + 8);
+			write_length(&mut pair, name.len());
+			write_length(&mut pair, value.len());
+			pair.extend_from_slice(name.as_bytes());
+			pair.extend_from_slice(value);
+
+			if pair.len() > MAX_CONTENT_LENGTH {
+				return Err(Error::from(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Parameter does not fit into one record")));
+			}
+
+			// Unwrap is safe here because the vector is never empty.
+			if params_records.last().unwrap().len() + pair.len() > MAX_CONTENT_LENGTH {
+				params_records.push(Vec::new());
+			}
+			params_records.last_mut().unwrap().extend_from_slice(&pair);
+		}
+
+		let request_id = self.allocate_id()?;
+		trace!("FastCGI: Client sending request {}.", request_id);
+
+		if let Err(err) = self.write_request(request_id, &params_records, stdin).await {
+			// The application never answers a request that was not sent completely.
+			self.release_id(request_id);
+			return Err(err);
+		}
+
+		Ok(request_id)
+	}
+
+	/// Sends the records of the request `request_id`.
+	async fn write_request(&mut self, request_id: RequestId, params_records: &[Vec<u8>], stdin: &[u8]) -> Result<(), Error> {
+		let mut begin_request = Role::Responder.number().to_be_bytes().to_vec();
+		begin_request.push(FCGI_KEEP_CONN);
+		begin_request.extend_from_slice(&[0u8; 5]);
+		self.write_record(StdReqType::BeginRequest.into(), request_id, &begin_request).await?;
+
+		for content in params_records.iter().filter(|content| !content.is_empty()) {
+			self.write_record(StdReqType::Params.into(), request_id, content).await?;
+		}
+		self.write_record(StdReqType::Params.into(), request_id, &[]).await?;
+
+		for chunk in stdin.chunks(MAX_CONTENT_LENGTH) {
+			self.write_record(StdReqType::StdIn.into(), request_id, chunk).await?;
+		}
+		self.write_record(StdReqType::StdIn.into(), request_id, &[]).await?;
+
+		Ok(())
+	}
+
+	/// Waits for the next request to be finished by the application and returns
+	/// its response.
+	///
+	/// The responses are returned in the order the application finishes the
+	/// requests. This is not necessarily the order they were sent in. Use
+	/// [`ClientResponse::request_id`] to match them. The id of a returned
+	/// request is free to be reused.
+	///
+	/// If no request is in flight, `None` is returned. If the application
+	/// sends a record for an unknown request, a
+	/// [`SequenceError`](Error::SequenceError) is returned.
+	pub async fn next_response(&mut self) -> Result<Option<ClientResponse>, Error> {
+		loop {
+			if let Some(response) = self.completed.pop_front() {
+				return Ok(Some(response));
+			}
+
+			if self.in_flight.is_empty() {
+				return Ok(None);
+			}
+
+			let (record_type, request_id, content) = self.read_record().await?;
+			self.route_record(record_type, request_id, content)?;
+		}
+	}
+
+	/// Returns a free request id and marks it as in flight.
+	fn allocate_id(&mut self) -> Result<RequestId, Error> {
+		if self.in_flight.len() >= self.max_requests {
+			return Err(Error::RequestIdsExhausted);
+		}
+
+		let request_id = match self.free_ids.pop() {
+			Some(request_id) => request_id,
+			None => {
+				let request_id = RequestId::try_from(self.next_id).map_err(|_| Error::RequestIdsExhausted)?;
+				self.next_id += 1;
+				request_id
+			}
+		};
+
+		self.in_flight.insert(request_id, ClientResponse { request_id, ..Default::default() });
+
+		Ok(request_id)
+	}
+
+	/// Frees the id of a request that is no longer in flight.
+	fn release_id(&mut self, request_id: RequestId) {
+		self.in_flight.remove(&request_id);
+		self.free_ids.push(request_id);
+	}
+
+	/// Adds a record received for a request in flight to its response. If the
+	/// request is finished, its response is queued and its id is freed.
+	fn route_record(&mut self, record_type: u8, request_id: RequestId, content: Vec<u8>) -> Result<(), Error> {
+		let response = self.in_flight.get_mut(&request_id).ok_or(Error::SequenceError)?;
+
+		if record_type == u8::from(StdRespType::StdOut) {
+			response.stdout.extend_from_slice(&content);
+		} else if record_type == u8::from(StdRespType::StdErr) {
+			response.stderr.extend_from_slice(&content);
+		} else if record_type == u8::from(StdRespType::EndRequest) && content.len() >= 5 {
+			response.app_status = u32::from_be_bytes([content[0], content[1], content[2], content[3]]);
+			response.protocol_status = content[4];
+
+			debug!("FastCGI: Client request {} finished with status {}.", request_id, response.app_status);
+
+			// Unwrap is safe here because the request was found above.
+			self.completed.push_back(self.in_flight.remove(&request_id).unwrap());
+			self.free_ids.push(request_id);
+		} else {
+			return Err(Error::SequenceError);
+		}
+
+		Ok(())
+	}
+
+	/// Sends one record with the passed content to the application.
+	async fn write_record(&mut self, record_type: u8, request_id: RequestId, content: &[u8]) -> Result<(), Error> {
+		let content_length = u16::try_from(content.len())
+			.map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Record content is too long"))?;
+
+		let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + content.len());
+		record.extend_from_slice(&[1, record_type]);
+		record.extend_from_slice(&request_id.to_be_bytes());
+		record.extend_from_slice(&content_length.to_be_bytes());
+		record.extend_from_slice(&[0, 0]);
+		record.extend_from_slice(content);
+
+		trace!("FastCGI: Client out record {{T:{}, ID: {}, L:{}}}", record_type, request_id, record.len());
+
+		self.writer.write_all(&record).await?;
+		self.writer.flush().await?;
+
+		Ok(())
+	}
+
+	/// Reads the next record sent by the application and returns its type,
+	/// request id and content.
+	async fn read_record(&mut self) -> Result<(u8, RequestId, Vec<u8>), Error> {
+		let mut header = [0u8; RECORD_HEADER_SIZE];
+		self.reader.read_exact(&mut header).await?;
+
+		if header[0] != 1 {
+			return Err(Error::InvalidRecordVersion);
+		}
+
+		let request_id = u16::from_be_bytes([header[2], header[3]]);
+		let mut content = vec![0; u16::from_be_bytes([header[4], header[5]]) as usize];
+		self.reader.read_exact(&mut content).await?;
+
+		let mut padding = vec![0; header[6] as usize];
+		self.reader.read_exact(&mut padding).await?;
+
+		trace!("FastCGI: Client in record {{T:{}, ID: {}, L:{}}}", header[1], request_id, RECORD_HEADER_SIZE + content.len() + padding.len());
+
+		Ok((header[1], request_id, content))
+	}
+}
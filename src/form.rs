@@ -0,0 +1,194 @@
+//! Parser for HTML form submissions.
+//!
+//! Browsers send the fields of a form posted without a file upload as
+//! `application/x-www-form-urlencoded` body. The helpers within this module
+//! decode such a body into its name value pairs.
+use std::io::Read;
+use tokio::io::AsyncWrite;
+
+use crate::{Error, Request};
+
+/// Media type of an URL-encoded form body.
+const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+/// Maximum size of a form body accepted by [`Request::form_body`].
+pub const MAX_FORM_BODY_SIZE: usize = 1024 * 1024;
+
+/// Decodes one URL-encoded name or value of a form.
+///
+/// `+` is decoded into a space and `%XX` into the byte with the hexadecimal
+/// value `XX`. Percent signs that are not followed by two hexadecimal digits
+/// are kept. Bytes that are not valid UTF-8 are replaced.
+fn decode_component(component: &[u8]) -> String {
+	let hex_value = |digit: u8| (digit as char).to_digit(16).map(|value| value as u8);
+
+	let mut decoded = Vec::with_capacity(component.len());
+	let mut index = 0;
+	while index < component.len() {
+		match component[index] {
+			b'+' => decoded.push(b' '),
+			b'%' => match (component.get(index + 1).copied().and_then(hex_value), component.get(index + 2).copied().and_then(hex_value)) {
+				(Some(high), Some(low)) => {
+					decoded.push(high << 4 | low);
+					index += 2;
+				},
+				_ => decoded.push(b'%')
+			},
+			byte => decoded.push(byte)
+		}
+		index += 1;
+	}
+
+	String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Splits an URL-encoded form into its decoded name value pairs. Empty pairs
+/// are skipped. A pair without `=` gets an empty value.
+fn parse_form(body: &[u8]) -> Vec<(String, String)> {
+	body.split(|byte| *byte == b'&')
+		.filter(|pair| !pair.is_empty())
+		.map(|pair| {
+			let mut parts = pair.splitn(2, |byte| *byte == b'=');
+			let name = decode_component(parts.next().unwrap_or_default());
+			let value = decode_component(parts.next().unwrap_or_default());
+
+			(name, value)
+		})
+		.collect()
+}
+
+impl <W: AsyncWrite + Unpin> Request<W> {
+	/// Reads the body of the request as URL-encoded form.
+	///
+	/// The `CONTENT_TYPE` parameter must be `application/x-www-form-urlencoded`.
+	/// Otherwise [`UnsupportedContentType`](Error::UnsupportedContentType) is
+	/// returned. The body is read from `StdIn` and split into its name value
+	/// pairs. Names and values are percent-decoded. The pairs are returned in
+	/// the order they were sent. Names that occur more than once (like the
+	/// values of a multi-select) are returned more than once.
+	///
+	/// Bodies bigger than [`MAX_FORM_BODY_SIZE`] are rejected with
+	/// [`BodyTooLarge`](Error::BodyTooLarge). Use
+	/// [`form_body_limited`](Request::form_body_limited) to choose another
+	/// limit. Like all other functions reading the body, this consumes `StdIn`.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   match request.form_body().await {
+	///     Ok(fields) => {
+	///       for (name, value) in fields {
+	///         println!("{} = {}", name, value);
+	///       }
+	///       RequestResult::Complete(0)
+	///     },
+	///     Err(_) => request.respond_with(400, &[], &[]).await.unwrap_or(RequestResult::Complete(1))
+	///   }
+	/// });
+	/// # } }
+	/// ```
+	pub async fn form_body(&self) -> Result<Vec<(String, String)>, Error> {
+		self.form_body_limited(MAX_FORM_BODY_SIZE).await
+	}
+
+	/// Reads the body of the request as URL-encoded form and rejects bodies
+	/// bigger than `limit` bytes.
+	///
+	/// Works like [`form_body`](Request::form_body) but with a limit chosen by
+	/// the caller. Bodies bigger than `limit` are rejected with
+	/// [`BodyTooLarge`](Error::BodyTooLarge).
+	pub async fn form_body_limited(&self, limit: usize) -> Result<Vec<(String, String)>, Error> {
+		let content_type = self.get_str_param("CONTENT_TYPE").unwrap_or_default();
+		let media_type = content_type.split(';').next().unwrap_or_default().trim();
+		if !media_type.eq_ignore_ascii_case(FORM_CONTENT_TYPE) {
+			return Err(Error::UnsupportedContentType(content_type.to_string()));
+		}
+
+		// A streamed body is read after it was received completely.
+		self.wait_stdin_complete().await;
+
+		// Read one byte more than allowed to detect bodies that are too large.
+		let mut body = Vec::new();
+		Read::take(&mut *self.get_stdin(), limit as u64 + 1).read_to_end(&mut body)?;
+		if body.len() > limit {
+			return Err(Error::BodyTooLarge(limit));
+		}
+
+		Ok(parse_form(&body))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn decode() {
+		assert_eq!(decode_component(b"Hello+World%21"), "Hello World!");
+		assert_eq!(decode_component(b"%C3%A4%c3%b6"), "äö");
+		assert_eq!(decode_component(b"100%"), "100%");
+		assert_eq!(decode_component(b"%zz%4"), "%zz%4");
+
+		assert_eq!(parse_form(b"a=1&&b=&c&a=%3D"), vec![
+			(String::from("a"), String::from("1")),
+			(String::from("b"), String::new()),
+			(String::from("c"), String::new()),
+			(String::from("a"), String::from("="))
+		]);
+	}
+
+	#[tokio::test]
+	async fn form_body() {
+		let (request, _output) = Request::test_builder()
+			.param("CONTENT_TYPE", "application/x-www-form-urlencoded; charset=UTF-8")
+			.stdin(b"name=Jane+Doe&city=M%C3%BCnchen&tags=a&tags=b")
+			.build();
+
+		assert_eq!(request.form_body().await.unwrap(), vec![
+			(String::from("name"), String::from("Jane Doe")),
+			(String::from("city"), String::from("München")),
+			(String::from("tags"), String::from("a")),
+			(String::from("tags"), String::from("b"))
+		]);
+	}
+
+	#[tokio::test]
+	async fn form_body_errors() {
+		// JSON is not a form.
+		let (request, _output) = Request::test_builder()
+			.param("CONTENT_TYPE", "application/json")
+			.stdin(b"{}")
+			.build();
+		assert!(matches!(request.form_body().await, Err(Error::UnsupportedContentType(content_type)) if content_type == "application/json"));
+
+		// A missing content type is rejected, too.
+		let (request, _output) = Request::test_builder().stdin(b"a=1").build();
+		assert!(matches!(request.form_body().await, Err(Error::UnsupportedContentType(_))));
+
+		let (request, _output) = Request::test_builder()
+			.param("CONTENT_TYPE", "application/x-www-form-urlencoded")
+			.stdin(vec![b'a'; MAX_FORM_BODY_SIZE + 1])
+			.build();
+		assert!(matches!(request.form_body().await, Err(Error::BodyTooLarge(MAX_FORM_BODY_SIZE))));
+
+		// The limit can be chosen by the caller.
+		let (request, _output) = Request::test_builder()
+			.param("CONTENT_TYPE", "application/x-www-form-urlencoded")
+			.stdin(b"a=12")
+			.build();
+		assert!(matches!(request.form_body_limited(3).await, Err(Error::BodyTooLarge(3))));
+
+		let (request, _output) = Request::test_builder()
+			.param("CONTENT_TYPE", "application/x-www-form-urlencoded")
+			.stdin(b"a=12")
+			.build();
+		assert_eq!(request.form_body_limited(4).await.unwrap(), vec![(String::from("a"), String::from("12"))]);
+	}
+}
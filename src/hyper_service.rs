@@ -0,0 +1,387 @@
+//! Adapter to serve a [`hyper`] service via FastCGI.
+//!
+//! This module is only available if the `hyper` feature is enabled. It
+//! translates a FastCGI [`Request`] into an [`http::Request`], passes it to a
+//! [`hyper::service::Service`] and writes the returned [`http::Response`] back
+//! to the web-server.
+use bytes::{Buf, Bytes};
+use http::{HeaderName, HeaderValue, Method, Uri, Version};
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::BodyExt;
+use hyper::service::Service;
+use log::{trace, warn};
+use std::io::Read;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::AsyncWrite;
+
+use crate::stdin::StdinChannel;
+use crate::{Error, Request, RequestResult};
+
+/// Body of the requests passed to a hyper service.
+///
+/// The body implements [`Body`] over the `StdIn` stream of the request. By
+/// default the body of a FastCGI request is received completely before the
+/// request is processed. It is passed to the service as one chunk with an
+/// exact size hint then. If [`Requests::stream_stdin`](crate::Requests::stream_stdin)
+/// is enabled, the chunks are passed to the service while they are received.
+/// Services must not rely on the size of the chunks.
+#[derive(Debug, Default)]
+pub struct HyperRequestBody {
+	data: Option<Bytes>,
+	channel: Option<Arc<StdinChannel>>
+}
+
+impl HyperRequestBody {
+	fn new(data: Bytes) -> Self {
+		Self {
+			data: Some(data).filter(|data| !data.is_empty()),
+			channel: None
+		}
+	}
+
+	/// Creates a body that passes the rest of a streamed `StdIn` stream on.
+	fn streamed(channel: Arc<StdinChannel>) -> Self {
+		Self {
+			data: None,
+			channel: Some(channel)
+		}
+	}
+}
+
+impl Body for HyperRequestBody {
+	type Data = Bytes;
+	type Error = std::io::Error;
+
+	fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+		let this = self.get_mut();
+
+		match (this.data.take(), &this.channel) {
+			(Some(data), _) => Poll::Ready(Some(Ok(Frame::data(data)))),
+			(None, Some(channel)) => channel.poll_chunk(cx).map(|chunk| chunk.map(|chunk| chunk.map(|chunk| Frame::data(Bytes::from(chunk))))),
+			(None, None) => Poll::Ready(None)
+		}
+	}
+
+	fn is_end_stream(&self) -> bool {
+		self.data.is_none() && self.channel.is_none()
+	}
+
+	fn size_hint(&self) -> SizeHint {
+		match self.channel {
+			Some(_) => SizeHint::default(),
+			None => SizeHint::with_exact(self.data.as_ref().map_or(0, |data| data.len() as u64))
+		}
+	}
+}
+
+/// Error type that all service and body errors must be convertible into.
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Converts the name of a CGI parameter carrying an HTTP header into the name
+/// of the header. Returns `None` if the parameter does not carry a header.
+///
+/// The name is compared case insensitively. This keeps working if the names
+/// of the parameters are not converted to lower case by a custom
+/// [`param_name_normalizer`](crate::Requests::param_name_normalizer).
+fn header_name(param: &str) -> Option<String> {
+	let param = param.to_ascii_lowercase();
+
+	match param.as_str() {
+		"content_type" => Some(String::from("content-type")),
+		"content_length" => Some(String::from("content-length")),
+		_ => param.strip_prefix("http_").map(|name| name.replace('_', "-"))
+	}
+}
+
+impl <W: AsyncWrite + Unpin> Request<W> {
+	/// Converts this FastCGI request into an [`http::Request`].
+	///
+	/// The method is taken from the `REQUEST_METHOD` parameter. The URI is taken
+	/// from `REQUEST_URI`. If the web-server does not send this parameter, the
+	/// URI is reconstructed from `SCRIPT_NAME`, `PATH_INFO` and `QUERY_STRING`.
+	/// All `HTTP_*` parameters as well as `CONTENT_TYPE` and `CONTENT_LENGTH`
+	/// are converted back into HTTP headers. The body contains the complete
+	/// `StdIn` stream of the request. If
+	/// [`Requests::stream_stdin`](crate::Requests::stream_stdin) is enabled,
+	/// the body is passed on while it is received.
+	///
+	/// An error is returned if the method, the URI or one of the headers is not
+	/// valid.
+	pub fn to_http_request(&self) -> Result<http::Request<HyperRequestBody>, http::Error> {
+		let method = self.get_param("REQUEST_METHOD").map_or(Ok(Method::GET), |method| Method::from_bytes(method))?;
+
+		let uri = match self.get_param("REQUEST_URI") {
+			Some(uri) => Uri::try_from(&uri[..])?,
+			None => {
+				let mut uri = String::new();
+				uri.push_str(self.get_str_param("SCRIPT_NAME").unwrap_or_default());
+				uri.push_str(self.get_str_param("PATH_INFO").unwrap_or_default());
+				if uri.is_empty() {
+					uri.push('/');
+				}
+				if let Some(query) = self.get_str_param("QUERY_STRING").filter(|query| !query.is_empty()) {
+					uri.push('?');
+					uri.push_str(query);
+				}
+
+				Uri::try_from(uri)?
+			}
+		};
+
+		let version = match self.get_str_param("SERVER_PROTOCOL") {
+			Some("HTTP/0.9") => Version::HTTP_09,
+			Some("HTTP/1.0") => Version::HTTP_10,
+			Some("HTTP/2") | Some("HTTP/2.0") => Version::HTTP_2,
+			Some("HTTP/3") | Some("HTTP/3.0") => Version::HTTP_3,
+			_ => Version::HTTP_11
+		};
+
+		let mut builder = http::Request::builder()
+			.method(method)
+			.uri(uri)
+			.version(version);
+
+		if let Some(params) = self.params_iter_ordered() {
+			for (name, value) in params {
+				if let Some(header) = header_name(name) {
+					builder = builder.header(HeaderName::from_bytes(header.as_bytes())?, HeaderValue::from_bytes(value)?);
+				}
+			}
+		}
+
+		let mut stdin = self.get_stdin();
+
+		// A streamed body is passed on while it is received.
+		if let Some(channel) = self.stdin_channel.as_ref().filter(|_| !stdin.is_done()) {
+			return builder.body(HyperRequestBody::streamed(channel.clone()));
+		}
+
+		// If the body is not valid, it is passed on as far as it was received.
+		let mut body = Vec::new();
+		if let Err(err) = stdin.read_to_end(&mut body) {
+			warn!("FastCGI: Reading the body of request {} failed: {}", self.get_request_id(), err);
+		}
+
+		builder.body(HyperRequestBody::new(Bytes::from(body)))
+	}
+
+	/// Processes this request with a [`hyper::service::Service`].
+	///
+	/// The request is converted via [`to_http_request`](Request::to_http_request)
+	/// and passed to `service`. The status and the headers of the returned
+	/// response are sent first. If the size of the response body is known and
+	/// the service did not set a `Content-Length` header, it is added. Every
+	/// chunk of the body is flushed to the web-server as soon as the service
+	/// produces it. This allows streaming responses.
+	///
+	/// If the request can not be converted, a `400 Bad Request` response is
+	/// sent. If the service returns an error, a `500 Internal Server Error`
+	/// response is sent. If the body of the response fails after the headers
+	/// were sent, the response is cut short and
+	/// [`Complete(1)`](RequestResult::Complete) is returned. Otherwise the
+	/// result is derived from the status of the response via
+	/// [`RequestResult::from_http_status`].
+	///
+	/// ## Unsupported features
+	///
+	/// * Upgrades (like WebSockets) are not possible via FastCGI. A
+	///   `101 Switching Protocols` response is passed to the web-server, but the
+	///   connection is never handed over to the service.
+	/// * Trailers of the response body are discarded. CGI has no way to
+	///   transport them.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{HyperRequestBody, Requests, RequestResult};
+	/// # use http_body_util::Full;
+	/// # use bytes::Bytes;
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// let service = hyper::service::service_fn(|request: http::Request<HyperRequestBody>| async move {
+	///   Ok::<_, std::convert::Infallible>(http::Response::new(Full::new(Bytes::from(format!("Hello {}", request.uri())))))
+	/// });
+	///
+	/// let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// while let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	///   request.process(|request| async move {
+	///     request.serve_hyper(&service).await.unwrap_or(RequestResult::Complete(1))
+	///   }).await.expect("Request could not be processed.");
+	/// }
+	/// # }
+	/// ```
+	pub async fn serve_hyper<S, B>(&self, service: &S) -> Result<RequestResult, Error>
+	where
+		S: Service<http::Request<HyperRequestBody>, Response = http::Response<B>>,
+		S::Error: Into<BoxError>,
+		B: Body,
+		B::Error: Into<BoxError>
+	{
+		let request = match self.to_http_request() {
+			Ok(request) => request,
+			Err(err) => {
+				warn!("FastCGI: Request {} can not be converted into an HTTP request: {}", self.get_request_id(), err);
+				return self.respond_with(400, &[], &[]).await;
+			}
+		};
+
+		let response = match service.call(request).await {
+			Ok(response) => response,
+			Err(err) => {
+				warn!("FastCGI: Service failed to process request {}: {}", self.get_request_id(), err.into());
+				return self.respond_with(500, &[], &[]).await;
+			}
+		};
+
+		let (parts, body) = response.into_parts();
+
+		let line_ending = self.orw.line_ending.as_str();
+
+		// The status of the response replaces the one set via set_status.
+		let mut head = self.orw.start_stdout(Some(parts.status.as_u16())).ok_or(Error::ResponseAlreadyStarted)?.into_bytes();
+		for (name, value) in &parts.headers {
+			head.extend_from_slice(name.as_str().as_bytes());
+			head.extend_from_slice(b": ");
+			head.extend_from_slice(value.as_bytes());
+			head.extend_from_slice(line_ending.as_bytes());
+		}
+
+		if let Some(length) = body.size_hint().exact() {
+			if !parts.headers.contains_key(http::header::CONTENT_LENGTH) && !parts.headers.contains_key(http::header::TRANSFER_ENCODING) {
+				head.extend_from_slice(format!("Content-Length: {}{}", length, line_ending).as_bytes());
+			}
+		}
+		head.extend_from_slice(line_ending.as_bytes());
+
+		let mut stdout = self.get_stdout();
+		stdout.write(&head).await?;
+
+		let mut body = std::pin::pin!(body);
+		while let Some(frame) = body.frame().await {
+			match frame {
+				Ok(frame) => {
+					if let Ok(mut data) = frame.into_data() {
+						while data.has_remaining() {
+							let length = stdout.write(data.chunk()).await?;
+							data.advance(length);
+						}
+						stdout.flush().await?;
+					} else {
+						trace!("FastCGI: Discarding trailers of request {}.", self.get_request_id());
+					}
+				},
+				Err(err) => {
+					warn!("FastCGI: Body of the response to request {} failed: {}", self.get_request_id(), err.into());
+					return Ok(RequestResult::Complete(1));
+				}
+			}
+		}
+
+		Ok(RequestResult::from_http_status(parts.status.as_u16()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use http_body_util::Full;
+	use std::convert::Infallible;
+
+	#[tokio::test]
+	async fn request_conversion() {
+		let (request, _output) = Request::test_builder()
+			.param("REQUEST_METHOD", "POST")
+			.param("REQUEST_URI", "/api/items?limit=10")
+			.param("SERVER_PROTOCOL", "HTTP/1.0")
+			.param("CONTENT_TYPE", "application/json")
+			.param("HTTP_X_FORWARDED_FOR", "10.0.0.1")
+			.param("SERVER_PORT", "80")
+			.stdin(b"{}")
+			.build();
+
+		let http_request = request.to_http_request().unwrap();
+		assert_eq!(http_request.method(), Method::POST);
+		assert_eq!(http_request.uri(), "/api/items?limit=10");
+		assert_eq!(http_request.version(), Version::HTTP_10);
+		assert_eq!(http_request.headers().len(), 2);
+		assert_eq!(http_request.headers()["content-type"], "application/json");
+		assert_eq!(http_request.headers()["x-forwarded-for"], "10.0.0.1");
+		assert_eq!(http_request.body().size_hint().exact(), Some(2));
+		assert_eq!(http_request.into_body().collect().await.unwrap().to_bytes(), Bytes::from_static(b"{}"));
+
+		// Without REQUEST_URI the URI is reconstructed from the CGI parameters.
+		let (request, _output) = Request::test_builder()
+			.param("SCRIPT_NAME", "/app")
+			.param("PATH_INFO", "/items")
+			.param("QUERY_STRING", "a=b")
+			.build();
+
+		let http_request = request.to_http_request().unwrap();
+		assert_eq!(http_request.method(), Method::GET);
+		assert_eq!(http_request.uri(), "/app/items?a=b");
+		assert!(http_request.body().is_end_stream());
+	}
+
+	#[tokio::test]
+	async fn streamed_body() {
+		let channel = Arc::new(StdinChannel::default());
+		let mut body = HyperRequestBody::streamed(channel.clone());
+		assert!(!body.is_end_stream());
+		assert_eq!(body.size_hint().exact(), None);
+
+		// The chunks are passed on as soon as they were received.
+		channel.push(b"First");
+		assert_eq!(body.frame().await.unwrap().unwrap().into_data().unwrap(), Bytes::from_static(b"First"));
+
+		let collect = tokio::spawn(async move { body.collect().await.map(|body| body.to_bytes()) });
+		channel.push(b" second");
+		channel.push(&[]);
+		assert_eq!(collect.await.unwrap().unwrap(), Bytes::from_static(b" second"));
+
+		// A body that can not be received completely fails.
+		let channel = Arc::new(StdinChannel::default());
+		let body = HyperRequestBody::streamed(channel.clone());
+		channel.fail(std::io::ErrorKind::ConnectionAborted);
+		assert_eq!(body.collect().await.unwrap_err().kind(), std::io::ErrorKind::ConnectionAborted);
+	}
+
+	#[tokio::test]
+	async fn serve() {
+		let service = hyper::service::service_fn(|request: http::Request<HyperRequestBody>| async move {
+			let response = http::Response::builder()
+				.status(201)
+				.header("Content-Type", "text/plain")
+				.body(Full::new(Bytes::from(format!("{} {}", request.method(), request.uri()))))
+				.unwrap();
+
+			Ok::<_, Infallible>(response)
+		});
+
+		let (request, output) = Request::test_builder()
+			.param("REQUEST_METHOD", "PUT")
+			.param("REQUEST_URI", "/item")
+			.build();
+
+		request.process(|request| async move { request.serve_hyper(&service).await.unwrap() }).await.unwrap();
+
+		assert_eq!(output.stdout(), b"Status: 201 Created\r\ncontent-type: text/plain\r\nContent-Length: 9\r\n\r\nPUT /item");
+		assert_eq!(output.end_request(), Some((0, 0)));
+	}
+
+	#[tokio::test]
+	async fn service_error() {
+		let service = hyper::service::service_fn(|_request: http::Request<HyperRequestBody>| async move {
+			Err::<http::Response<Full<Bytes>>, _>(std::io::Error::other("Backend failed"))
+		});
+
+		let (request, output) = Request::test_builder().build();
+
+		request.process(|request| async move { request.serve_hyper(&service).await.unwrap() }).await.unwrap();
+
+		assert_eq!(output.stdout(), b"Status: 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n");
+		assert_eq!(output.end_request(), Some((1, 0)));
+	}
+}
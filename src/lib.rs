@@ -4,17 +4,47 @@
 //! ```no_run
 #![doc = include_str!("../examples/simple.rs")]
 //! ```
-use log::{trace, warn};
+use log::{debug, trace, warn};
+use std::borrow::Cow;
 use std::fmt::Debug;
 use std::marker::Unpin;
 use std::io::{Cursor, Read, Write};
-use std::collections::{HashMap, hash_map::Entry};
-use std::sync::Arc;
-use tokio::io::{AsyncRead, AsyncWrite, AsyncReadExt, AsyncWriteExt};
-use tokio::sync::{Mutex, MutexGuard};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::{Mutex, MutexGuard, Notify, OwnedSemaphorePermit, Semaphore};
 use std::convert::TryFrom;
 use byteorder::{BigEndian, ReadBytesExt};
 use std::future::Future;
+use std::time::Duration;
+
+mod blocking;
+mod client;
+mod form;
+mod limiter;
+mod params;
+mod response;
+mod stdin;
+mod testing;
+#[cfg(feature = "hyper")]
+mod hyper_service;
+
+pub use blocking::BlockingWriter;
+pub use client::{Client, ClientResponse};
+pub use form::MAX_FORM_BODY_SIZE;
+pub use limiter::ConnectionLimiter;
+pub use params::ParamsView;
+pub use response::{LineEnding, ResponseStream};
+use response::{ContentLengthCheck, ContentTypeCheck, HeaderCounter, ResponseConfig, StatusCheck};
+use params::normalize_param_name;
+pub use stdin::StdinReader;
+pub use testing::{CapturedOutput, RequestBuilder};
+use stdin::{StdinChannel, StdinSender};
+#[cfg(feature = "hyper")]
+pub use hyper_service::HyperRequestBody;
 
 /// The size of the record header is 8 bytes.
 const RECORD_HEADER_SIZE: usize = 8;
@@ -22,6 +52,17 @@ const RECORD_HEADER_SIZE: usize = 8;
 /// Flag for FCGI_BeginRequestBody
 const FCGI_KEEP_CONN: u8 = 0x01;
 
+/// Protocol status of an `EndRequest` record rejecting a request that was
+/// started next to a request without `FCGI_KEEP_CONN`.
+const FCGI_CANT_MPX_CONN: u8 = 1;
+
+/// Default size of the buffer used to read from the web-server connection.
+const DEFAULT_READ_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Amount of data the [`AsyncWrite`] implementation of [`OutStream`] collects
+/// before it is sent to the web-server.
+const ASYNC_WRITE_BUFFER_SIZE: usize = 16 * 1024;
+
 /// Static panic message for a failed lock.
 const ERR_LOCK_FAILED: &str = "A request must not be processed by multiple threads.";
 
@@ -34,6 +75,18 @@ type ParamsIterator<'i> = dyn Iterator<Item=(&'i str, &'i [u8])> + 'i;
 /// Types for the parameter iterator with string conversion
 type StrParamsIterator<'i> = dyn Iterator<Item=(&'i str, Option<&'i str>)> + 'i;
 
+/// Type of the function that normalizes the names of the parameters
+type ParamNameNormalizer = dyn Fn(&[u8]) -> Cow<'_, [u8]> + Send + Sync;
+
+/// Type of the callback that is notified about slow requests.
+type SlowRequestCallback = dyn Fn(&SlowRequest) + Send + Sync;
+
+/// Type of the callback that is notified about completed requests.
+type CompleteCallback = dyn Fn(&RequestSummary) + Send + Sync;
+
+/// Type of the hook that can reject requests as soon as they begin.
+type BeginCallback = dyn Fn(&BeginRequestInfo) -> Option<RequestResult> + Send + Sync;
+
 /// Type returned by [`get_stdin`](Request::get_stdin) and [`get_data`](Request::get_data).
 /// It makes passing around the streams easier.
 pub type OwnedInStream<'a> = MutexGuard<'a, InStream>;
@@ -223,6 +276,14 @@ impl Role {
 			_ => None
 		}
 	}
+
+	fn number(&self) -> u16 {
+		match self {
+			Role::Responder => 1,
+			Role::Authorizer => 2,
+			Role::Filter => 3
+		}
+	}
 }
 
 
@@ -231,7 +292,11 @@ impl Role {
 /// This enum is returned by the [`process`](Request::process) method of the
 ///[`Request`] struct.  The meaning of the values is defined by the FastCGI
 /// specification.
-#[derive(Copy, Clone)]
+///
+/// If the request is answered with an HTTP status code,
+/// [`from_http_status`](RequestResult::from_http_status) returns the matching
+/// result.
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum RequestResult {
 	/// The request completed successfully. The returned status value is defined by
 	/// the [role](Role) of the FastCGI application.
@@ -260,6 +325,23 @@ impl RequestResult {
 	}
 }
 
+impl Default for RequestResult {
+	/// Returns [`Complete(0)`](RequestResult::Complete).
+	fn default() -> Self {
+		RequestResult::Complete(0)
+	}
+}
+
+impl From<()> for RequestResult {
+	/// Converts the unit type into [`Complete(0)`](RequestResult::Complete).
+	///
+	/// This allows a processor that does not need to report a result to
+	/// return nothing.
+	fn from(_: ()) -> Self {
+		RequestResult::default()
+	}
+}
+
 impl From<RequestResult> for u8 {
 	/// Allow the RequestResult to be converted into a u8.
 	/// This method returns the magic number that must be used as the
@@ -274,7 +356,11 @@ impl From<RequestResult> for u8 {
 }
 
 /// Errors that can be returned by calls to [`process`](Request::process).
+///
+/// New variants may be added in minor releases. Matches on this enum need a
+/// wildcard arm.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
 	/// The input stream was already closed and can not be reused. This indicates
 	/// an error within the call sequence, like calling `process` twice or the
@@ -306,7 +392,122 @@ pub enum Error {
 	/// was interrupted. Some I/O errors are handled by `tokio-fastcgi`. If the
 	/// web-server closes the FastCGI connection after all requests have been
 	/// processed no error is returned and the EOF error is just swallowed.
-	IoError(std::io::Error)
+	IoError(std::io::Error),
+
+	/// A record was only partially sent to the web-server. This happens if the
+	/// future writing the record was dropped (for example by a timeout) or the
+	/// write failed. Any further record would corrupt the connection.
+	/// Therefore all writes to this connection fail with this error.
+	WriterDesync,
+
+	/// The `Content-Length` header of the response does not match the number of
+	/// body bytes written to `StdOut`. Contains the declared and the written
+	/// length. This error is only returned by [`process`](Request::process) if
+	/// [`Requests::check_content_length`] is enabled. The request was
+	/// finished anyway.
+	ContentLengthMismatch(u64, u64),
+
+	/// The `StdIn` stream of the request with the contained id was not received
+	/// within the time set via [`Requests::stdin_read_timeout`]. The request
+	/// was ended and is not returned by [`Requests::next`].
+	StdinTimeout(RequestId),
+
+	/// A [`Client`] can not send another request, because the maximum number
+	/// of requests is already in flight. Wait for a response via
+	/// [`Client::next_response`] to free a request id.
+	RequestIdsExhausted,
+
+	/// A write to `StdOut` was refused because the response would contain more
+	/// headers than allowed via [`Requests::max_response_headers`].
+	TooManyResponseHeaders,
+
+	/// The body of the request was not read, because its content type is not
+	/// supported by the called function. Contains the value of the
+	/// `CONTENT_TYPE` parameter.
+	UnsupportedContentType(String),
+
+	/// The body of the request is bigger than the contained number of bytes
+	/// accepted by the called function.
+	BodyTooLarge(usize),
+
+	/// The `StdIn` or `Data` stream of the request with the contained id got
+	/// bigger than allowed via [`Requests::max_body_size`]. The request was
+	/// ended and is not returned by [`Requests::next`].
+	BodySizeExceeded(RequestId),
+
+	/// A header passed to a function sending a response, like
+	/// [`Request::respond_with`], has a name that is not a valid HTTP token or
+	/// a value that contains a line break. Contains the name of the header.
+	/// Nothing was sent.
+	InvalidResponseHeader(String),
+
+	/// A function sending the headers of a response, like
+	/// [`Request::respond_with`], was called after data was already written to
+	/// `StdOut`. Nothing was sent.
+	ResponseAlreadyStarted
+}
+
+impl Error {
+	/// Checks if this error ends the connection to the web-server.
+	///
+	/// Fatal errors leave the connection in an undefined state. The FastCGI
+	/// records can not be read reliably any more and the connection must be
+	/// closed. This is the case for the following errors:
+	///
+	/// * [`IoError`](Error::IoError)
+	/// * [`InvalidRecordVersion`](Error::InvalidRecordVersion)
+	/// * [`SequenceError`](Error::SequenceError)
+	/// * [`StreamAlreadyDone`](Error::StreamAlreadyDone)
+	/// * [`InvalidRoleNumber`](Error::InvalidRoleNumber)
+	/// * [`WriterDesync`](Error::WriterDesync)
+	///
+	/// All other errors only affect a single request. They can be logged and the
+	/// next request can be fetched from the connection.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// let mut requests = Requests::new(empty(), sink(), 1, 1);
+	///
+	/// loop {
+	///   match requests.next().await {
+	///     Ok(Some(request)) => { /* Process the request */ },
+	///     Ok(None) => break,
+	///     Err(err) if err.is_fatal() => {
+	///       println!("Connection failed: {}", err);
+	///       break;
+	///     },
+	///     Err(err) => println!("Request failed: {}", err)
+	///   }
+	/// }
+	/// # }
+	/// ```
+	pub fn is_fatal(&self) -> bool {
+		match self {
+			Error::IoError(_) |
+			Error::InvalidRecordVersion |
+			Error::SequenceError |
+			Error::StreamAlreadyDone |
+			Error::InvalidRoleNumber |
+			Error::WriterDesync => true,
+
+			Error::StreamAlreadyClosed |
+			Error::UnknownRecordType(_, _) |
+			Error::ContentLengthMismatch(_, _) |
+			Error::StdinTimeout(_) |
+			Error::RequestIdsExhausted |
+			Error::TooManyResponseHeaders |
+			Error::UnsupportedContentType(_) |
+			Error::BodyTooLarge(_) |
+			Error::BodySizeExceeded(_) |
+			Error::InvalidResponseHeader(_) |
+			Error::ResponseAlreadyStarted => false
+		}
+	}
 }
 
 impl std::fmt::Display for Error {
@@ -318,7 +519,17 @@ impl std::fmt::Display for Error {
 			Error::InvalidRecordVersion => write!(f, "Only record version 1 supported"),
 			Error::InvalidRoleNumber => write!(f, "Unkown role pass from server"),
 			Error::UnknownRecordType(request_id, type_id) => write!(f, "Unkown record type {} in request {} received", type_id, request_id),
-			Error::IoError(error) => write!(f, "I/O error: {}", error)
+			Error::IoError(error) => write!(f, "I/O error: {}", error),
+			Error::WriterDesync => write!(f, "Record was only partially written"),
+			Error::ContentLengthMismatch(declared, written) => write!(f, "Content-Length of {} bytes declared but {} bytes written", declared, written),
+			Error::StdinTimeout(request_id) => write!(f, "StdIn of request {} not received in time", request_id),
+			Error::RequestIdsExhausted => write!(f, "No free request id"),
+			Error::TooManyResponseHeaders => write!(f, "Response contains too many headers"),
+			Error::UnsupportedContentType(content_type) => write!(f, "Content type {:?} not supported", content_type),
+			Error::BodyTooLarge(limit) => write!(f, "Body is bigger than {} bytes", limit),
+			Error::BodySizeExceeded(request_id) => write!(f, "Body of request {} exceeds the size limit", request_id),
+			Error::InvalidResponseHeader(name) => write!(f, "Response header {:?} is not valid", name),
+			Error::ResponseAlreadyStarted => write!(f, "Response was already started")
 		}
 	}
 }
@@ -338,6 +549,38 @@ impl From<std::io::Error> for Error {
 	}
 }
 
+impl From<Error> for std::io::Error {
+	/// Converts the error into an I/O-error. This allows using `?` within
+	/// functions returning [`std::io::Result`].
+	///
+	/// [`IoError`](Error::IoError) is unwrapped. All other errors are wrapped
+	/// into an I/O-error of a matching [`ErrorKind`](std::io::ErrorKind). The
+	/// original error can be retrieved via [`std::io::Error::into_inner`].
+	fn from(error: Error) -> Self {
+		let kind = match error {
+			Error::IoError(io_error) => return io_error,
+			Error::StreamAlreadyDone |
+			Error::UnsupportedContentType(_) |
+			Error::BodyTooLarge(_) |
+			Error::BodySizeExceeded(_) |
+			Error::SequenceError |
+			Error::InvalidRecordVersion |
+			Error::InvalidRoleNumber |
+			Error::UnknownRecordType(_, _) |
+			Error::ContentLengthMismatch(_, _) => std::io::ErrorKind::InvalidData,
+			Error::StreamAlreadyClosed |
+			Error::WriterDesync => std::io::ErrorKind::BrokenPipe,
+			Error::StdinTimeout(_) => std::io::ErrorKind::TimedOut,
+			Error::TooManyResponseHeaders |
+			Error::InvalidResponseHeader(_) |
+			Error::ResponseAlreadyStarted => std::io::ErrorKind::InvalidInput,
+			Error::RequestIdsExhausted => std::io::ErrorKind::Other
+		};
+
+		std::io::Error::new(kind, error)
+	}
+}
+
 /// Represents a record received by the web-server.
 struct Record {
 	record_type: RequestType,
@@ -346,18 +589,36 @@ struct Record {
 }
 
 impl Record {
-	async fn new<R: AsyncRead + Unpin>(rd: &mut R) -> Result<Self, Error> {
-		let mut header_buffer = [0; RECORD_HEADER_SIZE];
-
-		rd.read_exact(&mut header_buffer).await?;
-
-		let mut header_slice = &header_buffer[..];
+	/// Reads the next record from `rd`.
+	///
+	/// Waiting for the next record is not limited. As soon as the first byte of
+	/// the record was received, the rest of the record must arrive within
+	/// `timeout`. Otherwise an I/O-error of kind
+	/// [`TimedOut`](std::io::ErrorKind::TimedOut) is returned.
+	async fn new<R: AsyncRead + Unpin>(rd: &mut R, timeout: Option<Duration>) -> Result<Self, Error> {
+		let version = rd.read_u8().await?;
+
+		match timeout {
+			Some(timeout) => tokio::time::timeout(timeout, Self::read_remaining(rd, version)).await
+				.unwrap_or_else(|_| Err(Error::from(std::io::Error::new(std::io::ErrorKind::TimedOut, "Record was not received in time")))),
+			None => Self::read_remaining(rd, version).await
+		}
+	}
 
+	/// Reads the rest of a record whose first byte (the version) was already
+	/// read.
+	async fn read_remaining<R: AsyncRead + Unpin>(rd: &mut R, version: u8) -> Result<Self, Error> {
 		// Check the FastCGI version
-		if byteorder::ReadBytesExt::read_u8(&mut header_slice).unwrap() != 1 {
+		if version != 1 {
 			return Err(Error::InvalidRecordVersion);
 		}
 
+		let mut header_buffer = [0; RECORD_HEADER_SIZE - 1];
+
+		rd.read_exact(&mut header_buffer).await?;
+
+		let mut header_slice = &header_buffer[..];
+
 		// Parse the remaining header fields
 		// Unwrap the record_type field not yet. An error on the record_type can be handled
 		// and we must read the remaining data to keep the I/O stream in sync.
@@ -409,35 +670,56 @@ impl Record {
 	}
 }
 
+/// Writes the length of a name or value of a name-value pair.
+///
+/// This is the counterpart of [`Request::read_length`].
+fn write_length(out: &mut Vec<u8>, length: usize) {
+	if length < 0x80 {
+		out.push(length as u8);
+	} else {
+		out.extend_from_slice(&(length as u32 | 0x8000_0000).to_be_bytes());
+	}
+}
+
 /// Implements a data stream from the web-server to the FastCGI application.
 ///
 /// All data is buffered in memory before being returned to the FastCGI
-/// application. Therefore only a synchronous interface is implemented.
+/// application. Therefore only a synchronous interface is implemented. A
+/// `StdIn` stream that is streamed to the processor is read via
+/// [`Request::stdin_reader`] instead.
 ///
 /// The data of the stream can be accessed via the methods of the [`Read`
 /// trait](Read).
 #[derive(Debug)]
 pub struct InStream {
 	data: Vec<u8>,
-	read_pos: Option<usize>
+	read_pos: Option<usize>,
+	error: Option<std::io::ErrorKind>
 }
 
 impl Read for InStream {
 	/// Read implementation for Stream.
 	///
-	/// *Beware*: Calling read or read_exact on a stream that is not done will panic!
+	/// Calling read or read_exact on a stream that is not done fails with
+	/// [`WouldBlock`](std::io::ErrorKind::WouldBlock). If the stream could not
+	/// be received completely, reading fails after the received data was read.
 	fn read(&mut self, out: &mut [u8]) -> std::result::Result<usize, std::io::Error> {
-		let read_pos = self.read_pos.unwrap();
+		let read_pos = self.ready_pos()?;
 		let c = std::io::Read::read(&mut &self.data[read_pos..], out)?;
 		self.read_pos = Some(read_pos + c);
-		Ok(c)
+
+		match self.error {
+			Some(kind) if c == 0 && !out.is_empty() => Err(stdin::incomplete(kind)),
+			_ => Ok(c)
+		}
 	}
 
 	/// Read_exact implementation for Stream.
 	///
-	/// *Beware*: Calling read or read_exact on a stream that is not done will panic!
+	/// Calling read or read_exact on a stream that is not done fails with
+	/// [`WouldBlock`](std::io::ErrorKind::WouldBlock).
 	fn read_exact(&mut self, out: &mut [u8]) -> std::result::Result<(), std::io::Error> {
-		let read_pos = self.read_pos.unwrap();
+		let read_pos = self.ready_pos()?;
 		std::io::Read::read_exact(&mut &self.data[read_pos..], out)?;
 		self.read_pos = Some(read_pos + out.len());
 		Ok(())
@@ -451,10 +733,24 @@ impl InStream{
 	fn new(already_done: bool) -> Self {
 		InStream {
 			data: Vec::new(),
-			read_pos: if already_done { Some(0) } else { None }
+			read_pos: if already_done { Some(0) } else { None },
+			error: None
 		}
 	}
 
+	/// Returns the read position or an error if the stream is not done yet.
+	fn ready_pos(&self) -> std::io::Result<usize> {
+		self.read_pos.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::WouldBlock, "Stream was not received completely yet"))
+	}
+
+	/// Marks a streamed stream as done. `data` is the part that was not read
+	/// from the stream yet and `error` the error the stream failed with.
+	fn complete(&mut self, data: Vec<u8>, error: Option<std::io::ErrorKind>) {
+		self.data = data;
+		self.read_pos = Some(0);
+		self.error = error;
+	}
+
 	/// Appends the passed data slice to the internal vector.
 	///
 	/// If the slice is empty, this is interpreted as an EOF marker and marks this
@@ -481,6 +777,33 @@ impl InStream{
 	fn is_done(&self) -> bool {
 		self.read_pos.is_some()
 	}
+
+	/// Moves the data that was not read yet out of this stream. Afterwards the
+	/// stream is at EOF.
+	///
+	/// If nothing was read yet, the buffer is returned without copying.
+	fn take_remaining(&mut self) -> Vec<u8> {
+		let read_pos = self.read_pos.unwrap();
+		let mut data = std::mem::take(&mut self.data);
+		data.drain(..read_pos);
+
+		self.read_pos = Some(0);
+
+		data
+	}
+
+	/// Discards the data that was not read yet and releases its memory.
+	///
+	/// Returns the number of bytes discarded.
+	fn discard(&mut self) -> usize {
+		let read_pos = self.read_pos.unwrap();
+		let discarded = self.data.len() - read_pos;
+
+		self.data = Vec::new();
+		self.read_pos = Some(0);
+
+		discarded
+	}
 }
 
 /// Represents a FastCGI request that can be handled by the application.
@@ -497,33 +820,104 @@ pub struct Request <W: AsyncWrite + Unpin> {
 	/// If the FastCGI application can not comply to this role the callback
 	/// passed to [`process`](Request::process) should return
 	/// [`RequestResult::UnknownRole`].
+	///
+	/// Requests with a role number this library does not know are only
+	/// returned if [`Requests::allow_unknown_roles`] is set. They are handled
+	/// like responders and this field is set to [`Role::Responder`]. Use
+	/// [`try_role`](Request::try_role) to detect them.
 	pub role: Role,
+	role_number: u16,
 	keep_connection: bool,
 	request_id: RequestId,
+	begin_reserved: [u8; 5],
 	params: HashMap<String, Vec<u8>>,
+	params_order: Vec<String>,
 	params_done: bool,
+	param_name_normalizer: Option<Arc<ParamNameNormalizer>>,
 	orw: Arc<OutRecordWriter<W>>,
 	stdin: Mutex<InStream>,
-	data: Mutex<InStream>
+	stdin_channel: Option<Arc<StdinChannel>>,
+	data: Mutex<InStream>,
+	data_taken: AtomicBool,
+	trailers: std::sync::Mutex<Vec<(String, String)>>,
+	record_count: usize,
+	stdin_deadline: Option<tokio::time::Instant>,
+	begin_instant: tokio::time::Instant,
+	slow_request: Option<(Duration, Option<Arc<SlowRequestCallback>>)>,
+	complete_callback: Option<Arc<CompleteCallback>>,
+	abort_signal: Arc<AbortSignal>,
+	concurrency_permit: Option<OwnedSemaphorePermit>
+}
+
+/// A request returned by [`Requests::next`] as seen by its connection.
+struct ProcessingRequest<W: AsyncWrite> {
+	abort_signal: Weak<AbortSignal>,
+	orw: Weak<OutRecordWriter<W>>
+}
+
+/// Signals an `AbortRequest` record received while a request is processed.
+#[derive(Debug, Default)]
+struct AbortSignal {
+	aborted: AtomicBool,
+	notify: Notify
+}
+
+impl AbortSignal {
+	fn abort(&self) {
+		self.aborted.store(true, Ordering::Release);
+		self.notify.notify_waiters();
+	}
 }
 
 impl <W: AsyncWrite + Unpin> Request<W> {
-	fn new(record: &Record, writer: Arc<Mutex<W>>) -> Result<Self, Error> {
+	fn new(record: &Record, writer: Arc<SharedWriter<W>>, param_name_normalizer: Option<Arc<ParamNameNormalizer>>, response_config: ResponseConfig, allow_unknown_roles: bool) -> Result<Self, Error> {
 		let mut content = record.get_content();
 
 		if let Category::Std(StdReqType::BeginRequest) = record.record_type {
-			if let Some(role) = Role::from_number(byteorder::ReadBytesExt::read_u16::<BigEndian>(&mut content).unwrap()) { //We're reading from am memory buffer. So there is something deeply wrong if this fails.
+			let role_number = byteorder::ReadBytesExt::read_u16::<BigEndian>(&mut content).unwrap(); //We're reading from am memory buffer. So there is something deeply wrong if this fails.
+
+			// Unknown roles get the streams of a responder. The processor can detect them via try_role.
+			let role = match Role::from_number(role_number) {
+				Some(role) => Some(role),
+				None if allow_unknown_roles => {
+					debug!("FastCGI: Accepting request {} with unknown role {}.", record.request_id, role_number);
+					Some(Role::Responder)
+				},
+				None => None
+			};
+
+			if let Some(role) = role {
 				let keep_connection = (byteorder::ReadBytesExt::read_u8(&mut content)? & FCGI_KEEP_CONN) == FCGI_KEEP_CONN;
 
+				// The reserved bytes are kept for diagnostic purposes only. If the web-server
+				// sent less than 5 bytes, the missing ones are left at zero.
+				let mut begin_reserved = [0u8; 5];
+				let reserved_length = content.len().min(begin_reserved.len());
+				begin_reserved[..reserved_length].copy_from_slice(&content[..reserved_length]);
+
 				Ok(Self {
 					params: HashMap::new(),
+					params_order: Vec::new(),
 					params_done: false,
-					orw: Arc::from(OutRecordWriter::new(writer, record.request_id)),
+					param_name_normalizer,
+					orw: Arc::from(OutRecordWriter::new(writer, record.request_id).with_config(response_config)),
 					stdin: Mutex::from(InStream::new(role == Role::Authorizer)), // Authorizers do not get an stdin stream
+					stdin_channel: None,
 					data: Mutex::from(InStream::new(role != Role::Filter)),      // Only filters get a data stream
+					data_taken: AtomicBool::new(false),
 					role,
+					role_number,
 					keep_connection,
-					request_id: record.request_id
+					request_id: record.request_id,
+					begin_reserved,
+					trailers: std::sync::Mutex::new(Vec::new()),
+					record_count: 1,
+					stdin_deadline: None,
+					begin_instant: tokio::time::Instant::now(),
+					slow_request: None,
+					complete_callback: None,
+					abort_signal: Arc::default(),
+					concurrency_permit: None
 				})
 			} else {
 				Err(Error::InvalidRoleNumber)
@@ -533,6 +927,11 @@ impl <W: AsyncWrite + Unpin> Request<W> {
 		}
 	}
 
+	/// Reads the length of a name or value of a name-value pair.
+	///
+	/// Lengths up to 127 bytes are encoded as one byte. Longer lengths are
+	/// encoded as four bytes in big endian order. The high bit of the first byte
+	/// is set to mark the 4-byte encoding and is not part of the 31-bit length.
 	fn read_length<T: Read>(src: &mut T) -> Result<u32, std::io::Error> {
 		let length: u32 = u32::from(src.read_u8()?);
 
@@ -546,28 +945,41 @@ impl <W: AsyncWrite + Unpin> Request<W> {
 		}
 	}
 
-	fn add_nv_pairs(params: &mut HashMap<String, Vec<u8>>, src: &[u8], lowercase_keys: bool) -> Result<(), std::io::Error>{
+	/// Parses the name-value pairs within `src` and adds them to `params`.
+	///
+	/// The names of the pairs are converted into keys by `make_key`. If `order`
+	/// is passed, the keys of all pairs that were not yet part of `params` are
+	/// appended to it. This preserves the order in which the pairs were
+	/// received.
+	fn add_nv_pairs(params: &mut HashMap<String, Vec<u8>>, mut order: Option<&mut Vec<String>>, src: &[u8], make_key: &dyn Fn(&[u8]) -> String) -> Result<(), std::io::Error>{
 		let mut src_slice = src;
 
 		while !src_slice.is_empty() {
 			let name_length = Request::<W>::read_length(&mut src_slice)?;
 			let value_length = Request::<W>::read_length(&mut src_slice)?;
 
+			// Check the lengths before allocating the buffers. A broken length could
+			// otherwise allocate up to 4 GiB of memory.
+			if (name_length as u64 + value_length as u64) > src_slice.len() as u64 {
+				return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+			}
+
 			let mut name_buffer = vec![0; name_length as usize];
 			let mut value_buffer = vec![0; value_length as usize];
 
 			std::io::Read::read_exact(&mut src_slice, &mut name_buffer)?;
 			std::io::Read::read_exact(&mut src_slice, &mut value_buffer)?;
 
-			let key = String::from_utf8_lossy(&name_buffer);
-			let key = if lowercase_keys {
-				key.to_ascii_lowercase()
-			} else {
-				key.into_owned()
-			};
+			let key = make_key(&name_buffer);
 
 			trace!("FastCGI: NV-Pair[\"{}\"]=\"{}\"", key, String::from_utf8_lossy(&value_buffer));
 
+			if let Some(order) = order.as_deref_mut() {
+				if !params.contains_key(&key) {
+					order.push(key.clone());
+				}
+			}
+
 			params.insert(key, value_buffer);
 		}
 
@@ -602,7 +1014,7 @@ impl <W: AsyncWrite + Unpin> Request<W> {
 	/// ```
 	pub fn get_param(&self, name: &str) -> Option<&Vec<u8>> {
 		if self.params_done {
-			self.params.get(&name.to_ascii_lowercase())
+			self.params.get(&normalize_param_name(self.param_name_normalizer.as_deref(), name.as_bytes()))
 		} else {
 			None
 		}
@@ -635,18 +1047,134 @@ impl <W: AsyncWrite + Unpin> Request<W> {
 	/// # } }
 	/// ```
 	pub fn get_str_param(&self, name: &str) -> Option<&str> {
-		if self.params_done {
-			match self.params.get(&name.to_ascii_lowercase()).map(|v| std::str::from_utf8(v)) {
-				None => None,
-				Some(Ok(value)) => Some(value),
-				Some(Err(_)) => {
-					warn!("FastCGI: Parameter {} is not valid utf8.", name);
-					None
-				}
-			}
-		} else {
-			None
-		}
+		self.params().get_str(name)
+	}
+
+	/// Returns a view of all parameters of this request.
+	///
+	/// The [`ParamsView`] bundles the lookup and the iteration of the
+	/// parameters in one value that can be passed to the functions of the
+	/// processor. Its methods work like the accessors of this struct.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{ParamsView, Requests, RequestResult};
+	/// fn is_head(params: ParamsView) -> bool {
+	///   params.get_str("REQUEST_METHOD") == Some("HEAD")
+	/// }
+	///
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   let params = request.params();
+	///   println!("{} parameters received. HEAD request: {}", params.len(), is_head(params));
+	///
+	///   RequestResult::Complete(0)
+	/// });
+	/// # } }
+	/// ```
+	pub fn params(&self) -> ParamsView<'_> {
+		ParamsView::new(self.params_done.then_some(&self.params), &self.params_order, self.param_name_normalizer.as_deref())
+	}
+
+	/// Returns the value of the HTTP header `http_name` sent by the client.
+	///
+	/// The web-server passes the headers of the HTTP request as parameters. The
+	/// name of the parameter is derived from the name of the header by
+	/// converting it to upper case, replacing `-` with `_` and adding the
+	/// `HTTP_` prefix. `User-Agent` becomes `HTTP_USER_AGENT`. The headers
+	/// `Content-Type` and `Content-Length` are passed without the prefix as
+	/// `CONTENT_TYPE` and `CONTENT_LENGTH`. This function does the translation
+	/// and looks up the parameter.
+	///
+	/// If the header was not sent, `None` is returned.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   if let Some(user_agent) = request.header("User-Agent") {
+	///     println!("User agent: {}", String::from_utf8_lossy(user_agent));
+	///   }
+	///
+	///   RequestResult::Complete(0)
+	/// });
+	/// # } }
+	/// ```
+	pub fn header(&self, http_name: &str) -> Option<&[u8]> {
+		let param_name = http_name.to_ascii_uppercase().replace('-', "_");
+
+		let param_name = match param_name.as_str() {
+			"CONTENT_TYPE" | "CONTENT_LENGTH" => param_name,
+			_ => format!("HTTP_{}", param_name)
+		};
+
+		self.get_param(&param_name).map(|value| &value[..])
+	}
+
+	/// Returns `true` if the client sent the header `Expect: 100-continue`.
+	///
+	/// FastCGI has no record to acknowledge a pending body. Web-servers either
+	/// answer `100 Continue` themselves or forward the body as soon as the
+	/// application accepts it on the connection. [`Requests::next`] keeps
+	/// reading the connection until the body is received completely, so the
+	/// body is always pulled through without any action by the application.
+	/// When the request is returned, the body is already available via
+	/// [`get_stdin`](Request::get_stdin).
+	///
+	/// This function only tells handlers whether the client waited for
+	/// permission to send the body. The body is received before the request is
+	/// returned, even if the handler rejects it afterwards.
+	pub fn expects_continue(&self) -> bool {
+		self.header("Expect").is_some_and(|expect| expect.eq_ignore_ascii_case(b"100-continue"))
+	}
+
+	/// Returns the value of the `CONTENT_LENGTH` parameter.
+	///
+	/// The web-server sets this parameter to the length of the request body
+	/// declared by the client. If the parameter is missing or is not a valid
+	/// number `None` is returned.
+	pub fn content_length(&self) -> Option<u64> {
+		self.get_str_param("CONTENT_LENGTH").and_then(|length| length.trim().parse().ok())
+	}
+
+	/// Returns the `QUERY_STRING` parameter exactly as it was sent by the
+	/// web-server.
+	///
+	/// The query string is not decoded. Percent-encoded characters and `+` are
+	/// kept as they are. This allows forwarding or hashing the query without
+	/// artifacts of a decode and encode round-trip. If the parameter is missing
+	/// or is not valid UTF-8 `None` is returned.
+	pub fn raw_uri_query(&self) -> Option<&str> {
+		self.get_str_param("QUERY_STRING")
+	}
+
+	/// Checks if the client sent the request via HTTPS.
+	///
+	/// Web-servers signal this in different ways. This function returns `true`
+	/// if one of the following parameters is set:
+	///
+	/// * `HTTPS` is `on` or `1` (nginx, Apache, lighttpd). Other values, like
+	///   the `off` sent by IIS, are ignored.
+	/// * `REQUEST_SCHEME` is `https` (nginx, Apache).
+	/// * `SERVER_PORT_SECURE` is `1` (IIS).
+	///
+	/// The comparison is case insensitive. Headers sent by a reverse proxy in
+	/// front of the web-server, like `X-Forwarded-Proto`, are not trusted.
+	pub fn is_secure(&self) -> bool {
+		let is = |name, values: &[&str]| self.get_str_param(name).is_some_and(|value| values.iter().any(|expected| value.trim().eq_ignore_ascii_case(expected)));
+
+		is("HTTPS", &["on", "1"]) || is("REQUEST_SCHEME", &["https"]) || is("SERVER_PORT_SECURE", &["1"])
 	}
 
 	/// Returns an iterator over all parameters.
@@ -675,7 +1203,7 @@ impl <W: AsyncWrite + Unpin> Request<W> {
 	/// });
 	/// # } }
 	/// ```
-	pub fn params_iter(&self) -> Option<Box<ParamsIterator>> {
+	pub fn params_iter(&self) -> Option<Box<ParamsIterator<'_>>> {
 		if self.params_done {
 			Some(Box::new(self.params.iter().map(|v| {
 				(v.0.as_str(), &v.1[..])
@@ -685,6 +1213,48 @@ impl <W: AsyncWrite + Unpin> Request<W> {
 		}
 	}
 
+	/// Returns an iterator over all parameters in the order they were received.
+	///
+	/// Works like [params_iter](Request::params_iter) but returns the parameters
+	/// in the order the web-server sent them. This is useful to log the
+	/// environment of a request faithfully. If a parameter was sent more than
+	/// once, it is returned at the position of its first occurrence with the
+	/// value of its last occurrence.
+	///
+	/// To preserve the order, the name of every parameter is stored a second
+	/// time. This roughly doubles the memory consumed by the parameter names.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   if let Some(params) = request.params_iter_ordered() {
+	///     // Log the environment in the order it was received
+	///     for param in params {
+	///       println!("{}={}", param.0, String::from_utf8_lossy(param.1));
+	///     }
+	///   }
+	///
+	///   RequestResult::Complete(0)
+	/// });
+	/// # } }
+	/// ```
+	pub fn params_iter_ordered(&self) -> Option<Box<ParamsIterator<'_>>> {
+		if self.params_done {
+			Some(Box::new(self.params_order.iter().map(move |name| {
+				(name.as_str(), &self.params[name][..])
+			})))
+		} else {
+			None
+		}
+	}
+
 	/// Returns an iterator over all parameters that tries to convert the parameter
 	/// values into strings.
 	///
@@ -713,7 +1283,7 @@ impl <W: AsyncWrite + Unpin> Request<W> {
 	/// });
 	/// # } }
 	/// ```
-	pub fn str_params_iter(&self) -> Option<Box<StrParamsIterator>> {
+	pub fn str_params_iter(&self) -> Option<Box<StrParamsIterator<'_>>> {
 		if self.params_done {
 			Some(Box::new(self.params.iter().map(|v| {
 				(v.0.as_str(), std::str::from_utf8(v.1).ok())
@@ -725,8 +1295,26 @@ impl <W: AsyncWrite + Unpin> Request<W> {
 
 	/// Checks if this record is ready for processing by the client application.
 	/// A record is ready if the stdin, the data and the params stream are done (EOF).
+	/// A streamed stdin stream does not need to be done.
 	fn check_ready(&mut self) -> bool {
-		self.get_stdin().is_done() && self.get_data().is_done() && self.params_done
+		(self.stdin_channel.is_some() || self.get_stdin().is_done()) && self.get_data().is_done() && self.params_done
+	}
+
+	/// Returns true if the stream `record` belongs to would hold more than
+	/// `limit` bytes after adding the content of `record`. Records that are not
+	/// buffered never exceed the limit.
+	fn exceeds_body_size(&mut self, record: &Record, limit: usize) -> bool {
+		if let (Category::Std(StdReqType::StdIn), Some(channel)) = (record.record_type, &self.stdin_channel) {
+			return channel.received() + record.get_content().len() > limit;
+		}
+
+		let stream = match record.record_type {
+			Category::Std(StdReqType::StdIn) => self.stdin.get_mut(),
+			Category::Std(StdReqType::Data) if self.role == Role::Filter => self.data.get_mut(),
+			_ => return false
+		};
+
+		stream.data.len() + record.get_content().len() > limit
 	}
 
 	/// Updates the state of the Request instance.
@@ -741,6 +1329,8 @@ impl <W: AsyncWrite + Unpin> Request<W> {
 		}
 
 		if let Category::Std(record_type) = record.record_type {
+			self.record_count += 1;
+
 			match record_type {
 				StdReqType::BeginRequest => {
 					return Err(Error::SequenceError);
@@ -752,16 +1342,26 @@ impl <W: AsyncWrite + Unpin> Request<W> {
 					} else {
 						if self.params_done { warn!("FastCGI: Protocol error. Params received after params stream was marked as done."); }
 
-						Self::add_nv_pairs(&mut self.params, record.get_content(), true)?;
+						let normalizer = self.param_name_normalizer.as_deref();
+						Self::add_nv_pairs(&mut self.params, Some(&mut self.params_order), record.get_content(), &|name| normalize_param_name(normalizer, name))?;
 					}
 				},
 
 				StdReqType::StdIn => {
-					self.get_stdin().append(record.get_content())?;
+					match &self.stdin_channel {
+						Some(channel) => channel.push(record.get_content()),
+						None => self.get_stdin().append(record.get_content())?
+					}
 				},
 
 				StdReqType::Data => {
-					self.get_data().append(record.get_content())?;
+					// Only filters get a data stream. Some web-servers send data records for
+					// other roles anyway. They are dropped to keep the request in sync.
+					if self.role == Role::Filter {
+						self.get_data().append(record.get_content())?;
+					} else {
+						debug!("FastCGI: Ignoring data record for request {} with role {:?}.", self.request_id, self.role);
+					}
 				}
 			};
 
@@ -779,81 +1379,355 @@ impl <W: AsyncWrite + Unpin> Request<W> {
 		self.request_id
 	}
 
-	/// Allows the process closure to write to StdOut.
-	///
-	/// Returns an `OutStream` instance that will send `StdOut` records back to
-	/// the web-server.
+	/// Returns the role requested by the web-server.
 	///
+	/// If the web-server sent a role number this library does not know, the
+	/// number is returned as the error. This can only happen if
+	/// [`Requests::allow_unknown_roles`] is set. Otherwise requests with
+	/// unknown roles make [`Requests::next`] fail with
+	/// [`InvalidRoleNumber`](Error::InvalidRoleNumber).
 	///
 	/// ## Example
 	///
 	/// ```rust
 	/// # use tokio::io::{empty, sink};
-	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # use tokio_fastcgi::{Requests, RequestResult, Role};
 	/// # #[tokio::main]
 	/// # async fn main() {
-	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1).allow_unknown_roles(true);
 	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
 	/// request.process(|request| async move {
-	///   let mut stdout = request.get_stdout();
-	///
-	///   assert!(stdout.write(b"Hello World").await.is_ok());
-	///
-	///   RequestResult::Complete(0)
+	///   match request.try_role() {
+	///     Ok(Role::Responder) => RequestResult::Complete(0),
+	///     Ok(_) => RequestResult::UnknownRole,
+	///     Err(role_number) => {
+	///       println!("Role {} is not supported.", role_number);
+	///       RequestResult::UnknownRole
+	///     }
+	///   }
 	/// });
 	/// # } }
 	/// ```
-	pub fn get_stdout(&self) -> OutStream<W> {
-		OutStream::new(Category::Std(StdRespType::StdOut), self.orw.clone())
+	pub fn try_role(&self) -> Result<Role, u16> {
+		Role::from_number(self.role_number).ok_or(self.role_number)
 	}
 
-	/// Allows the process closure to write to StdErr.
+	/// Returns the phase this request is in.
 	///
-	/// Returns an `OutStream` instance that will send `StdErr` records back to
-	/// the web-server. What is done with the data that is sent to StdErr depends
-	/// on the web-server.
+	/// Requests returned by [`Requests::next`] are always in the phase
+	/// [`Processing`](Phase::Processing) until they are ended via
+	/// [`abort`](Request::abort) or until the processor passed to
+	/// [`process`](Request::process) returns. The phases of the requests that
+	/// are still being received can be queried via
+	/// [`Requests::pending_requests`], the phases of the requests that are
+	/// processed via [`Requests::processing_requests`].
+	pub fn phase(&self) -> Phase {
+		if self.orw.phase() != Phase::Processing {
+			self.orw.phase()
+		} else if !self.params_done {
+			Phase::AwaitingParams
+		} else if self.stdin_channel.is_none() && !self.stdin.try_lock().map_or(true, |stdin| stdin.is_done()) {
+			// If the stream is locked, the processor is reading it. It must be complete then.
+			Phase::AwaitingStdin
+		} else if !self.data.try_lock().map_or(true, |data| data.is_done()) {
+			Phase::AwaitingData
+		} else {
+			Phase::Processing
+		}
+	}
+
+	/// Returns the reserved bytes of the `BeginRequest` record as they were
+	/// received from the web-server.
+	///
+	/// The FastCGI specification requires these bytes to be zero. They are
+	/// normally ignored but can help to diagnose a misbehaving web-server.
+	pub fn begin_reserved(&self) -> [u8; 5] {
+		self.begin_reserved
+	}
+
+	/// Returns the point in time the `BeginRequest` record of this request was
+	/// received.
+	///
+	/// The time is taken from the clock of tokio. It can therefore be paused
+	/// and advanced within tests.
+	pub fn begin_instant(&self) -> tokio::time::Instant {
+		self.begin_instant
+	}
+
+	/// Returns the time since the `BeginRequest` record of this request was
+	/// received.
+	///
+	/// This includes the time to receive the parameters and the body. Call it at
+	/// the end of the processor to log the latency of the request.
 	///
 	/// ## Example
 	///
 	/// ```rust
-	/// # use tokio::io::{empty, sink, AsyncWriteExt};
+	/// # use tokio::io::{empty, sink};
 	/// # use tokio_fastcgi::{Requests, RequestResult};
 	/// # #[tokio::main]
 	/// # async fn main() {
 	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
 	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
 	/// request.process(|request| async move {
-	///   let mut stderr = request.get_stderr();
-	///
-	///   assert!(stderr.write(b"Hello World").await.is_ok());
+	///   // Process the request
 	///
+	///   println!("Request {} served in {:?}", request.get_request_id(), request.elapsed());
 	///   RequestResult::Complete(0)
 	/// });
 	/// # } }
 	/// ```
-	pub fn get_stderr(&self) -> OutStream<W> {
-		OutStream::new(Category::Std(StdRespType::StdErr), self.orw.clone())
+	pub fn elapsed(&self) -> Duration {
+		self.begin_instant.elapsed()
 	}
 
-	/// Allows the process closure to read from StdIn.
+	/// Returns `true` if the web-server aborted this request while it was
+	/// processed.
 	///
-	/// Returns an `InStream` instance that will read the data passed as StdIn
-	/// by the web-server.
+	/// See [`aborted`](Request::aborted).
+	pub fn is_aborted(&self) -> bool {
+		self.abort_signal.aborted.load(Ordering::Acquire)
+	}
+
+	/// Waits until the web-server aborts this request.
+	///
+	/// The web-server sends an `FCGI_ABORT_REQUEST` record if the client went
+	/// away. A request that is aborted before it was received completely is
+	/// never returned by [`Requests::next`]. If the abort arrives while the
+	/// request is processed, the returned future resolves. It is resolved
+	/// immediately if the request was already aborted. The processor should
+	/// stop its work and return. The result it returns is still sent to the
+	/// web-server.
+	///
+	/// The abort is received by [`Requests::next`]. It must be called while
+	/// the request is processed, like it is done if the requests are processed
+	/// in their own tasks (see [`spawn_processor`](Request::spawn_processor)).
+	/// Aborts for other requests of the same connection do not affect this
+	/// request.
 	///
 	/// ## Example
 	///
 	/// ```rust
+	/// # use std::time::Duration;
 	/// # use tokio::io::{empty, sink};
-	/// # use std::io::Read;
 	/// # use tokio_fastcgi::{Requests, RequestResult};
 	/// # #[tokio::main]
 	/// # async fn main() {
 	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
 	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
-	/// request.process(|request| async move {
-	///   let mut stdin = request.get_stdin();
-	///
-	///   let mut buffer = Vec::with_capacity(10);
+	/// request.spawn_processor(|request| async move {
+	///   tokio::select! {
+	///     _ = tokio::time::sleep(Duration::from_secs(5)) => {
+	///       // Some expensive work
+	///       RequestResult::Complete(0)
+	///     },
+	///     _ = request.aborted() => RequestResult::Complete(1)
+	///   }
+	/// });
+	/// # } }
+	/// ```
+	pub async fn aborted(&self) {
+		// The future is registered before the flag is checked. An abort in between is not missed.
+		let notified = self.abort_signal.notify.notified();
+		if !self.is_aborted() {
+			notified.await;
+		}
+	}
+
+	/// Reports this request if it took longer than the threshold set via
+	/// [`Requests::slow_request_threshold`].
+	fn report_slow_request(&self) {
+		if let Some((threshold, callback)) = &self.slow_request {
+			let elapsed = self.elapsed();
+			if elapsed <= *threshold {
+				return;
+			}
+
+			let slow_request = SlowRequest {
+				request_id: self.request_id,
+				method: self.get_str_param("REQUEST_METHOD").map(String::from),
+				uri: self.get_str_param("REQUEST_URI").map(String::from),
+				elapsed
+			};
+
+			match callback {
+				Some(callback) => callback(&slow_request),
+				None => warn!("FastCGI: Request {} ({} {}) took {:?}.", slow_request.request_id,
+					slow_request.method.as_deref().unwrap_or("-"), slow_request.uri.as_deref().unwrap_or("-"), elapsed)
+			}
+		}
+	}
+
+	/// Reports this request to the hook set via [`Requests::on_complete`].
+	fn report_complete(&self, result: RequestResult) {
+		if let Some(callback) = &self.complete_callback {
+			callback(&RequestSummary {
+				request_id: self.request_id,
+				method: self.get_str_param("REQUEST_METHOD").map(String::from),
+				uri: self.get_str_param("REQUEST_URI").map(String::from),
+				status: self.orw.status(),
+				result,
+				bytes_out: self.orw.bytes_out.load(Ordering::Acquire),
+				elapsed: self.elapsed()
+			});
+		}
+	}
+
+	/// Returns the number of records the web-server sent for this request.
+	///
+	/// The `BeginRequest` record and all `Params`, `StdIn` and `Data` records
+	/// are counted, including the empty records terminating the streams. A high
+	/// count for a small request shows that the web-server fragments the
+	/// streams into many tiny records. An `AbortRequest` record is not counted
+	/// because it ends the request.
+	pub fn record_count(&self) -> usize {
+		self.record_count
+	}
+
+	/// Allows the process closure to write to StdOut.
+	///
+	/// Returns an `OutStream` instance that will send `StdOut` records back to
+	/// the web-server.
+	///
+	/// ## Sending the headers early
+	///
+	/// If computing the body of a response takes a while, the status line and
+	/// the headers can be sent first. Writing them and calling
+	/// [`flush`](OutStream::flush) hands them to the web-server right away,
+	/// before the body is produced. This allows the web-server to start the
+	/// response to the client and reduces the time to the first byte. Once the
+	/// headers are sent, the status of the response can not be changed anymore.
+	///
+	/// ## Using the stream as `AsyncWrite`
+	///
+	/// The returned stream implements [`AsyncWrite`] only if the writer of the
+	/// connection is `Send` and `'static`. This is not the case for the
+	/// borrowed halves returned by `TcpStream::split`, that are passed to
+	/// [`Requests::from_split_socket`]. Functions like [`tokio::io::copy`] can
+	/// not write to the stream then. Create the `Requests` instance from the
+	/// owned halves returned by `into_split` instead.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   let mut stdout = request.get_stdout();
+	///
+	///   // Deliver the headers before the body is computed.
+	///   assert!(stdout.write(b"Status: 200 OK\r\nContent-Type: text/plain\r\n\r\n").await.is_ok());
+	///   assert!(stdout.flush().await.is_ok());
+	///
+	///   assert!(stdout.write(b"Hello World").await.is_ok());
+	///
+	///   RequestResult::Complete(0)
+	/// });
+	/// # } }
+	/// ```
+	pub fn get_stdout(&self) -> OutStream<W> {
+		OutStream::new(Category::Std(StdRespType::StdOut), self.orw.clone())
+	}
+
+	/// Allows the process closure to write to StdErr.
+	///
+	/// Returns an `OutStream` instance that will send `StdErr` records back to
+	/// the web-server. What is done with the data that is sent to StdErr depends
+	/// on the web-server.
+	///
+	/// Like for [`get_stdout`](Request::get_stdout), the returned stream only
+	/// implements [`AsyncWrite`] if the writer is `Send` and `'static`.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink, AsyncWriteExt};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   let mut stderr = request.get_stderr();
+	///
+	///   assert!(stderr.write(b"Hello World").await.is_ok());
+	///
+	///   RequestResult::Complete(0)
+	/// });
+	/// # } }
+	/// ```
+	pub fn get_stderr(&self) -> OutStream<W> {
+		OutStream::new(Category::Std(StdRespType::StdErr), self.orw.clone())
+	}
+
+	/// Sends a record of type `record_type` with the content `content` for
+	/// this request and flushes it to the web-server.
+	///
+	/// This is an escape hatch for protocol extensions and experiments. The
+	/// record is framed with the id of this request but its type and content
+	/// are not checked. Misuse can desync the stream. A record of a type the
+	/// web-server does not expect or an `EndRequest` record sent before the
+	/// request is finished can make the web-server drop the connection. Use
+	/// [`get_stdout`](Request::get_stdout) and
+	/// [`get_stderr`](Request::get_stderr) for regular output.
+	///
+	/// If `content` does not fit into one record (65535 bytes), an I/O-error
+	/// of kind [`InvalidInput`](std::io::ErrorKind::InvalidInput) is returned
+	/// and nothing is sent.
+	pub async fn write_raw_record(&self, record_type: u8, content: &[u8]) -> Result<(), Error> {
+		if content.len() > u16::MAX as usize {
+			return Err(Error::from(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Record content is too long")));
+		}
+
+		trace!("FastCGI: Out raw record {{T:{}, ID: {}, L:{}}}", record_type, self.request_id, RECORD_HEADER_SIZE + content.len());
+
+		self.orw.write_record(record_type, content).await?;
+		self.orw.flush().await?;
+
+		Ok(())
+	}
+
+	/// Allows the process closure to read from StdIn.
+	///
+	/// Returns an `InStream` instance that will read the data passed as StdIn
+	/// by the web-server.
+	///
+	/// A request is only returned by [`Requests::next`] after its StdIn stream
+	/// was received completely. It does not matter how many records the
+	/// web-server used to transfer the data or how long it took. Reading from
+	/// the returned stream never blocks and always returns the complete body.
+	///
+	/// If streaming is enabled via [`Requests::stream_stdin`], the body may
+	/// still be received while the request is processed. Call
+	/// [`wait_stdin_complete`](Request::wait_stdin_complete) before reading
+	/// from the returned stream or read it via
+	/// [`stdin_reader`](Request::stdin_reader). Reading from a stream that is
+	/// not complete yet fails with
+	/// [`WouldBlock`](std::io::ErrorKind::WouldBlock).
+	///
+	/// The body is pulled from the web-server while [`Requests::next`] waits
+	/// for it. No explicit signal is needed to make the web-server send it. This
+	/// includes clients that send `Expect: 100-continue`. See
+	/// [`expects_continue`](Request::expects_continue) for details.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use std::io::Read;
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   let mut stdin = request.get_stdin();
+	///
+	///   let mut buffer = Vec::with_capacity(10);
 	///   assert!(stdin.read(&mut buffer).is_ok());
 	///
 	///   assert_eq!(buffer.len(), 10);
@@ -862,10 +1736,105 @@ impl <W: AsyncWrite + Unpin> Request<W> {
 	/// });
 	/// # } }
 	/// ```
-	pub fn get_stdin(&self) -> OwnedInStream {
+	pub fn get_stdin(&self) -> OwnedInStream<'_> {
 		self.stdin.try_lock().expect(ERR_LOCK_FAILED)
 	}
 
+	/// Waits until the StdIn stream of this request was received completely.
+	///
+	/// Requests are only returned by [`Requests::next`] after their StdIn
+	/// stream was completely received. Therefore the returned future is
+	/// always ready immediately. The function exists for processors that want
+	/// to state explicitly that they depend on the complete body. After it
+	/// returned, [`get_stdin`](Request::get_stdin) is guaranteed to return the
+	/// whole body.
+	///
+	/// If streaming is enabled via [`Requests::stream_stdin`], the future waits
+	/// for the remaining StdIn records. Afterwards the part of the body that was
+	/// not read via [`stdin_reader`](Request::stdin_reader) yet can be read
+	/// via [`get_stdin`](Request::get_stdin). If the body could not be received
+	/// completely, reading fails after the received data was returned.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use std::io::Read;
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   request.wait_stdin_complete().await;
+	///
+	///   let mut body = Vec::new();
+	///   assert!(request.get_stdin().read_to_end(&mut body).is_ok());
+	///
+	///   RequestResult::Complete(0)
+	/// });
+	/// # } }
+	/// ```
+	pub async fn wait_stdin_complete(&self) {
+		if let Some(channel) = &self.stdin_channel {
+			channel.wait_done().await;
+
+			// If the stream is locked, the caller is reading it via stdin_reader. It reads the rest from the channel.
+			if let Ok(mut stdin) = self.stdin.try_lock() {
+				if !stdin.is_done() {
+					let (data, error) = channel.take();
+					stdin.complete(data, error);
+				}
+			}
+		} else {
+			// If the stream is locked, the caller is reading it. It must be complete then.
+			debug_assert!(self.stdin.try_lock().map_or(true, |stdin| stdin.is_done()), "Request was returned before StdIn was complete.");
+		}
+	}
+
+	/// Discards the part of the StdIn stream that was not read yet.
+	///
+	/// Processors that ignore the body of a request (for example because they
+	/// reject it with `405 Method Not Allowed`) can call this function to state
+	/// that the body is not needed and to release the memory it occupies. The
+	/// number of discarded bytes is returned.
+	///
+	/// The FastCGI connection never gets out of sync if the body is not read.
+	/// Requests are only returned by [`Requests::next`] after their StdIn
+	/// stream was received completely. Therefore the remaining StdIn records
+	/// were already consumed when the processor is called. Connections with
+	/// `FCGI_KEEP_CONN` can serve the next request without draining the body.
+	/// If streaming is enabled via [`Requests::stream_stdin`], the function
+	/// waits until the body was received.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   if request.get_str_param("REQUEST_METHOD") != Some("GET") {
+	///     request.drain_stdin().await;
+	///     return request.respond_with(405, &[("Allow", "GET")], &[]).await.unwrap_or(RequestResult::Complete(1));
+	///   }
+	///
+	///   RequestResult::Complete(0)
+	/// });
+	/// # } }
+	/// ```
+	pub async fn drain_stdin(&self) -> usize {
+		self.wait_stdin_complete().await;
+
+		let discarded = self.get_stdin().discard();
+		trace!("FastCGI: Discarded {} bytes of StdIn of request {}.", discarded, self.request_id);
+
+		discarded
+	}
+
 	/// Allows the process closure to read from the Data stream.
 	///
 	/// Returns an `InStream` instance that will read the data passed as a Data
@@ -893,10 +1862,100 @@ impl <W: AsyncWrite + Unpin> Request<W> {
 	/// });
 	/// # } }
 	/// ```
-	pub fn get_data(&self) -> OwnedInStream {
+	pub fn get_data(&self) -> OwnedInStream<'_> {
 		self.data.try_lock().expect(ERR_LOCK_FAILED)
 	}
 
+	/// Moves the buffered Data stream out of this request.
+	///
+	/// Filters receive the file to filter via the Data stream. This function
+	/// hands the file over to another subsystem without copying it. The
+	/// data already read via [`get_data`](Request::get_data) is not included.
+	///
+	/// This is a one-shot operation. Afterwards reads from
+	/// [`get_data`](Request::get_data) return EOF and further calls return
+	/// `None`. If this request is not a [`Filter`](Role::Filter), `None` is
+	/// returned, too.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   if let Some(file) = request.take_data() {
+	///     let converted = tokio::task::spawn_blocking(move || file.to_ascii_uppercase()).await.unwrap();
+	///     request.get_stdout().write(&converted).await.unwrap();
+	///   }
+	///
+	///   RequestResult::Complete(0)
+	/// });
+	/// # } }
+	/// ```
+	pub fn take_data(&self) -> Option<Vec<u8>> {
+		if self.role != Role::Filter || self.data_taken.swap(true, Ordering::AcqRel) {
+			None
+		} else {
+			Some(self.get_data().take_remaining())
+		}
+	}
+
+	/// Ends the request before the processor returns.
+	///
+	/// This allows the application to terminate a request on its own, for
+	/// example if a downstream dependency failed and no meaningful response can
+	/// be generated. Contrary to an abort sent by the web-server, the request is
+	/// ended by the application. The `StdOut` and `StdErr` streams are closed
+	/// and an `EndRequest` record with the passed `result` is sent. Streams
+	/// that were already closed are not terminated again. All pending output
+	/// is flushed to the web-server.
+	///
+	///
+	/// After calling this method, all writes to the output streams of the
+	/// request fail with [`StreamAlreadyClosed`](Error::StreamAlreadyClosed).
+	/// The [`RequestResult`] returned by the processor is discarded. If the
+	/// request was already ended, [`StreamAlreadyClosed`](Error::StreamAlreadyClosed)
+	/// is returned.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   let database_available = false;
+	///
+	///   if !database_available {
+	///     request.abort(RequestResult::Complete(1)).await.expect("Request could not be aborted.");
+	///   }
+	///
+	///   RequestResult::Complete(0)
+	/// });
+	/// # } }
+	/// ```
+	pub async fn abort(&self, result: RequestResult) -> Result<(), Error> {
+		if self.orw.finish() {
+			return Err(Error::StreamAlreadyClosed);
+		}
+
+		// The streams can not be closed via OutStream::close, because they refuse
+		// all writes as soon as the request is marked as finished.
+		self.orw.terminate_stream(Category::Std(StdRespType::StdOut)).await?;
+		self.orw.terminate_stream(Category::Std(StdRespType::StdErr)).await?;
+		self.orw.write_finish(result).await?;
+		self.orw.flush().await?;
+
+		Ok(())
+	}
+
 	/// Processes a FastCGI request.
 	///
 	/// As soon as a request is completely received it is returned by
@@ -920,6 +1979,37 @@ impl <W: AsyncWrite + Unpin> Request<W> {
 	/// - [`RequestResult::Overloaded`]
 	/// - [`RequestResult::UnknownRole`]
 	///
+	/// The callback can return any type that converts into a [`RequestResult`].
+	/// A callback that returns `()` completes the request with
+	/// [`Complete(0)`](RequestResult::Complete).
+	///
+	/// ## Records sent when the request is finished
+	///
+	/// The records sent after the callback returned only depend on the result
+	/// and on whether the callback wrote data to `StdOut` or `StdErr`:
+	///
+	/// - [`Complete`](RequestResult::Complete): An empty `StdOut` and an empty
+	///   `StdErr` record terminate the streams. They are sent even if nothing
+	///   was written. Then the `EndRequest` record is sent.
+	/// - [`Overloaded`](RequestResult::Overloaded) and
+	///   [`UnknownRole`](RequestResult::UnknownRole) without output: Only the
+	///   `EndRequest` record is sent. This is the same answer a request gets if
+	///   it is rejected by [`Requests`] itself. A status set via
+	///   [`set_status`](Request::set_status) and trailers are discarded.
+	/// - [`Overloaded`](RequestResult::Overloaded) and
+	///   [`UnknownRole`](RequestResult::UnknownRole) with output: The streams
+	///   are terminated like for `Complete`.
+	///
+	/// If the request was ended via [`abort`](Request::abort), nothing is sent.
+	///
+	/// ## Concurrent processing
+	///
+	/// All requests of a connection share the write half of the connection. Every
+	/// record is written while holding a lock on the writer. Therefore the future
+	/// returned by `process` can be spawned as a separate task while the next
+	/// request is fetched from the connection. The records of the different
+	/// requests get interleaved but are never torn apart.
+	///
 	/// ## Example
 	///
 	/// ```rust
@@ -942,47 +2032,331 @@ impl <W: AsyncWrite + Unpin> Request<W> {
 	/// }
 	/// # }
 	/// ```
-	pub async fn process<F: Future<Output = RequestResult>, C: FnOnce(Arc<Self>) -> F>(self, callback: C) -> Result<(), Error> {
+	pub async fn process<T: Into<RequestResult>, F: Future<Output = T>, C: FnOnce(Arc<Self>) -> F>(self, callback: C) -> Result<(), Error> {
 		let rc_self = Arc::from(self);
 
-		let result = callback(rc_self.clone()).await;
+		let result: RequestResult = callback(rc_self.clone()).await.into();
+		rc_self.report_slow_request();
 
 		if let Ok(this) = Arc::try_unwrap(rc_self) {
-			this.get_stdout().close().await?;
-			this.get_stderr().close().await?;
+			let outcome = this.end(result).await;
+			this.report_complete(result);
 
-			this.orw.write_finish(result).await?;
+			outcome
 		} else {
 			panic!("StdErr or StdOut leaked out of process.")
 		}
+	}
+
+	/// Sends the remaining output of this request and ends it with `result`.
+	async fn end(&self, result: RequestResult) -> Result<(), Error> {
+		// The request was already ended via abort. The result is discarded.
+		if self.orw.is_finished() {
+			return Ok(());
+		}
+
+		self.orw.flushing.store(true, Ordering::Release);
+
+		// The data of dropped streams is output, too.
+		self.orw.send_unsent(Category::Std(StdRespType::StdOut)).await?;
+		self.orw.send_unsent(Category::Std(StdRespType::StdErr)).await?;
+
+		// A rejected request without output is answered like a request rejected by Requests.
+		if !matches!(result, RequestResult::Complete(_)) && !self.orw.has_output() {
+			self.orw.write_finish(result).await?;
+			self.orw.flush().await?;
+
+			return Ok(());
+		}
+
+		// If a status or variables were set but nothing was written, they are sent as the only headers.
+		if let Some(head) = self.orw.start_stdout(None).filter(|head| !head.is_empty()) {
+			let header = head + self.orw.line_ending.as_str();
+			self.orw.write_data(Category::Std(StdRespType::StdOut), header.as_bytes()).await?;
+		}
+
+		// The trailers are not part of the body checked against Content-Length.
+		let mismatch = self.orw.content_length_mismatch();
+
+		// The trailers end the chunked body of the response.
+		if let Some(trailers) = self.trailer_section() {
+			self.get_stdout().write(&trailers).await?;
+		}
+
+		self.get_stdout().close().await?;
+		self.get_stderr().close().await?;
+
+		self.orw.write_finish(result).await?;
+		self.orw.flush().await?;
+
+		if let Some((declared, written)) = mismatch {
+			warn!("FastCGI: Request {} declared a Content-Length of {} bytes but wrote {} bytes.", self.request_id, declared, written);
+			return Err(Error::ContentLengthMismatch(declared, written));
+		}
 
 		Ok(())
 	}
-}
-
-/// Processes records form an input and output stream.
-///
-/// FastCGI allow multiple requests to be interleaved within one data-stream.
-/// This struct reads the FastCGI-records from an input stream and assembles
-/// them into a [`Request`].
-///
-/// *Beware*: Requests are built in memory. Having huge requests can eat up all
-/// of your systems memory.
-pub struct Requests <R: AsyncRead + Unpin + Send, W: AsyncWrite + Unpin + Send> {
-	reader: R,
-	writer: Arc<Mutex<W>>,
-	requests: HashMap<RequestId, Request<W>>,
-	close_on_next: bool,
-	max_conns: u8,
-	max_reqs: u8
-}
 
-impl <'w, R: AsyncRead + Unpin + Send, W: AsyncWrite + Unpin + Send> Requests<R, W> {
-	/// Creates a new [`Requests`] instance.
+	/// Processes this request in a new task.
 	///
-	/// As soon as a new connection is accepted the read and write parts of this
-	/// connection should be passed to this function. It will create a new
-	/// [`Requests`] instance that will handle the communication between the
+	/// This is a shortcut for spawning the future returned by
+	/// [`process`](Request::process) on the current Tokio runtime. The
+	/// connection loop can fetch the next request from [`Requests::next`] right
+	/// away while this one is still processed. The records of all requests are
+	/// serialized by the writer that is shared by the requests of a connection.
+	/// They get interleaved but are never torn apart.
+	///
+	/// The returned [`JoinHandle`](tokio::task::JoinHandle) resolves to the
+	/// result of `process`. This function must be called from within a Tokio
+	/// runtime.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// let mut requests = Requests::new(empty(), sink(), 1, 10);
+	/// let mut processors = Vec::new();
+	///
+	/// while let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	///   processors.push(request.spawn_processor(|request| async move {
+	///     // Process the request
+	///     RequestResult::Complete(0)
+	///   }));
+	/// }
+	///
+	/// for processor in processors {
+	///   processor.await.expect("Processor panicked.").expect("Request could not be processed.");
+	/// }
+	/// # }
+	/// ```
+	pub fn spawn_processor<T, F, C>(self, callback: C) -> tokio::task::JoinHandle<Result<(), Error>>
+	where
+		W: Send + 'static,
+		T: Into<RequestResult> + Send + 'static,
+		F: Future<Output = T> + Send + 'static,
+		C: FnOnce(Arc<Self>) -> F + Send + 'static
+	{
+		tokio::spawn(self.process(callback))
+	}
+}
+
+/// Phase of the lifecycle of a request.
+///
+/// Returned by [`Request::phase`], [`Requests::pending_requests`] and
+/// [`Requests::processing_requests`]. A request that stays in one of the
+/// `Awaiting` phases for a long time hints at a web-server that does not send
+/// the terminating record of a stream. A request that stays in
+/// [`Flushing`](Phase::Flushing) hints at a web-server that does not read the
+/// response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Phase {
+	/// The `BeginRequest` record was received. The parameters are not
+	/// complete yet.
+	AwaitingParams,
+	/// The parameters were received. The `StdIn` stream is not complete yet.
+	AwaitingStdin,
+	/// The `StdIn` stream was received. The `Data` stream of a filter is not
+	/// complete yet.
+	AwaitingData,
+	/// The request was received completely. It is returned by
+	/// [`Requests::next`] and processed by the application.
+	Processing,
+	/// The processor returned. The terminating records of the streams and the
+	/// `EndRequest` record are being sent.
+	Flushing,
+	/// The `EndRequest` record was sent.
+	Complete
+}
+
+/// Information about a request that took longer than the threshold set via
+/// [`Requests::slow_request_threshold`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowRequest {
+	/// Id of the request.
+	pub request_id: u16,
+	/// Value of the `REQUEST_METHOD` parameter.
+	pub method: Option<String>,
+	/// Value of the `REQUEST_URI` parameter.
+	pub uri: Option<String>,
+	/// Time from the `BeginRequest` record until the processor returned.
+	pub elapsed: Duration
+}
+
+/// Information about a request that was processed completely.
+///
+/// Passed to the hook set via [`Requests::on_complete`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestSummary {
+	/// Id of the request.
+	pub request_id: u16,
+	/// Value of the `REQUEST_METHOD` parameter.
+	pub method: Option<String>,
+	/// Value of the `REQUEST_URI` parameter.
+	pub uri: Option<String>,
+	/// HTTP status sent via the `Status` header. `None` if the response did
+	/// not contain one. Web-servers answer such responses with `200 OK`
+	/// unless they contain a `Location` header.
+	pub status: Option<u16>,
+	/// Result returned by the processor.
+	pub result: RequestResult,
+	/// Number of bytes sent via `StdOut`, including the headers.
+	pub bytes_out: u64,
+	/// Time from the `BeginRequest` record until the request was ended.
+	pub elapsed: Duration
+}
+
+/// Information about a request that was just started by the web-server.
+///
+/// Passed to the hook set via [`Requests::on_begin`]. Only the contents of
+/// the `BeginRequest` record are known at this point.
+#[derive(Debug, PartialEq)]
+pub struct BeginRequestInfo {
+	/// Id of the request.
+	pub request_id: u16,
+	/// Role of the request. `None` if the role is not known to this library.
+	pub role: Option<Role>,
+	/// Number of the role as sent by the web-server.
+	pub role_number: u16,
+	/// `true` if the web-server set the `FCGI_KEEP_CONN` flag.
+	pub keep_connection: bool
+}
+
+/// Statistics about the requests received via one connection.
+///
+/// Returned by [`Requests::connection_stats`]. The statistics show if the
+/// web-server reuses its connections. If connections are reused, the
+/// web-server sets the `FCGI_KEEP_CONN` flag and sends more than one request
+/// per connection. If every connection only carries one request without this
+/// flag, a new connection is established for every request. For Apache's
+/// `mod_proxy_fcgi` this is the case if `enablereuse=on` is not set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+	/// Number of requests started by the web-server on this connection.
+	/// Requests that were rejected by the FastCGI application are not counted.
+	pub requests: usize,
+	/// Number of requests that had the `FCGI_KEEP_CONN` flag set.
+	pub keep_conn_requests: usize
+}
+
+impl ConnectionStats {
+	/// Returns `true` if the web-server asked to keep this connection open.
+	///
+	/// This is the case if at least one request had the `FCGI_KEEP_CONN` flag
+	/// set.
+	pub fn is_reused(&self) -> bool {
+		self.keep_conn_requests > 0
+	}
+}
+
+/// Holds the requests of a connection that are not completely received yet.
+///
+/// Most web-servers send only one request at a time over a connection. The
+/// first pending request is therefore stored without a map. The map is only
+/// used if the web-server multiplexes requests over the connection. This saves
+/// hashing and allocating for every request in the common case.
+struct PendingRequests<W: AsyncWrite + Unpin> {
+	single: Option<Request<W>>,
+	multiplexed: HashMap<RequestId, Request<W>>
+}
+
+impl <W: AsyncWrite + Unpin> PendingRequests<W> {
+	fn new() -> Self {
+		Self {
+			single: None,
+			multiplexed: HashMap::new()
+		}
+	}
+
+	fn is_empty(&self) -> bool {
+		self.single.is_none() && self.multiplexed.is_empty()
+	}
+
+	fn len(&self) -> usize {
+		usize::from(self.single.is_some()) + self.multiplexed.len()
+	}
+
+	fn contains(&self, request_id: RequestId) -> bool {
+		matches!(&self.single, Some(request) if request.request_id == request_id) || self.multiplexed.contains_key(&request_id)
+	}
+
+	fn get_mut(&mut self, request_id: RequestId) -> Option<&mut Request<W>> {
+		match &mut self.single {
+			Some(request) if request.request_id == request_id => Some(request),
+			_ => self.multiplexed.get_mut(&request_id)
+		}
+	}
+
+	/// Adds a new request. The caller must make sure that no request with the
+	/// same id is pending.
+	fn insert(&mut self, request: Request<W>) {
+		if self.single.is_none() {
+			self.single = Some(request);
+		} else {
+			self.multiplexed.insert(request.request_id, request);
+		}
+	}
+
+	fn remove(&mut self, request_id: RequestId) -> Option<Request<W>> {
+		match &self.single {
+			Some(request) if request.request_id == request_id => self.single.take(),
+			_ => self.multiplexed.remove(&request_id)
+		}
+	}
+
+	fn iter(&self) -> impl Iterator<Item = &Request<W>> {
+		self.single.iter().chain(self.multiplexed.values())
+	}
+}
+
+/// Processes records form an input and output stream.
+///
+/// FastCGI allow multiple requests to be interleaved within one data-stream.
+/// This struct reads the FastCGI-records from an input stream and assembles
+/// them into a [`Request`].
+///
+/// *Beware*: Requests are built in memory. Having huge requests can eat up all
+/// of your systems memory.
+pub struct Requests <R: AsyncRead + Unpin + Send, W: AsyncWrite + Unpin + Send> {
+	reader: BufReader<R>,
+	writer: Arc<SharedWriter<W>>,
+	requests: PendingRequests<W>,
+	rejected: HashSet<RequestId>,
+	processing: HashMap<RequestId, ProcessingRequest<W>>,
+	close_on_next: bool,
+	streaming: HashMap<RequestId, StdinSender>,
+	stream_stdin: bool,
+	max_conns: u8,
+	max_reqs: u8,
+	max_requests: Option<usize>,
+	accepted_requests: usize,
+	keep_conn_requests: usize,
+	record_timeout: Option<Duration>,
+	idle_timeout: Option<Duration>,
+	stdin_read_timeout: Option<Duration>,
+	max_body_size: Option<usize>,
+	param_name_normalizer: Option<Arc<ParamNameNormalizer>>,
+	response_config: ResponseConfig,
+	allow_unknown_roles: bool,
+	accepted_roles: Option<Vec<u16>>,
+	slow_request_threshold: Option<Duration>,
+	slow_request_callback: Option<Arc<SlowRequestCallback>>,
+	complete_callback: Option<Arc<CompleteCallback>>,
+	begin_callback: Option<Arc<BeginCallback>>,
+	connection_permit: Option<OwnedSemaphorePermit>,
+	over_capacity: bool,
+	concurrency: Option<Arc<Semaphore>>
+}
+
+impl <R: AsyncRead + Unpin + Send, W: AsyncWrite + Unpin + Send> Requests<R, W> {
+	/// Creates a new [`Requests`] instance.
+	///
+	/// As soon as a new connection is accepted the read and write parts of this
+	/// connection should be passed to this function. It will create a new
+	/// [`Requests`] instance that will handle the communication between the
 	/// web-server and the FastCGI application.
 	///
 	/// In addition to the read and write side of the connection two more
@@ -998,226 +2372,1652 @@ impl <'w, R: AsyncRead + Unpin + Send, W: AsyncWrite + Unpin + Send> Requests<R,
 	///   connection.
 	pub fn new(rd: R, wr: W, max_conns: u8, max_reqs: u8) -> Self {
 		Self {
-			requests: HashMap::with_capacity(1),
-			reader: rd,
-			writer: Arc::from(Mutex::from(wr)),
+			requests: PendingRequests::new(),
+			rejected: HashSet::new(),
+			processing: HashMap::new(),
+			reader: BufReader::with_capacity(DEFAULT_READ_BUFFER_SIZE, rd),
+			writer: Arc::from(SharedWriter::new(wr)),
 			close_on_next: false,
+			streaming: HashMap::new(),
+			stream_stdin: false,
 			max_conns,
-			max_reqs
+			max_reqs,
+			max_requests: None,
+			accepted_requests: 0,
+			keep_conn_requests: 0,
+			record_timeout: None,
+			idle_timeout: None,
+			stdin_read_timeout: None,
+			max_body_size: None,
+			param_name_normalizer: None,
+			response_config: ResponseConfig::default(),
+			allow_unknown_roles: false,
+			accepted_roles: None,
+			slow_request_threshold: None,
+			slow_request_callback: None,
+			complete_callback: None,
+			begin_callback: None,
+			connection_permit: None,
+			over_capacity: false,
+			concurrency: None
 		}
 	}
 
-	/// Same as [`new`](Requests::new) but takes a tuple containing the read and write
-	/// side of the socket instead of two distinct variables
+	/// Limits the number of requests that are served via this connection.
 	///
-	/// This is more convenient in combination with the `split` function.
+	/// After `max_requests` requests have been started by the web-server, all
+	/// further `BeginRequest` records are answered with
+	/// [`Overloaded`](RequestResult::Overloaded). As soon as the last accepted
+	/// request was returned, [`next`](Requests::next) returns `None` to signal
+	/// that the connection should be closed. This happens even if the web-server
+	/// asked to keep the connection open. This is useful to recycle connections
+	/// periodically.
+	///
+	/// By default the number of requests per connection is not limited.
 	///
 	/// # Example
 	///
 	/// ```rust
-	/// # use tokio::net::TcpListener;
-	/// # use tokio_fastcgi::{Requests, RequestResult};
-	/// # #[tokio::main]
-	/// # async fn main() {
-	/// # let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-	/// # let server = async move {
-	/// if let Ok(mut socket) = listener.accept().await {
-	///   tokio::spawn(async move {
-	///   // Directly use the result of the split() call to construct a Requests instance
-	///     let mut requests = Requests::from_split_socket(socket.0.split(), 10, 10);
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::Requests;
+	/// let requests = Requests::new(empty(), sink(), 10, 10)
+	///   .max_requests_per_connection(1000);
+	/// ```
+	pub fn max_requests_per_connection(mut self, max_requests: usize) -> Self {
+		self.max_requests = Some(max_requests);
+		self
+	}
+
+	/// Sets the size of the buffer used to read from the web-server connection.
 	///
-	///     // Process the requests
-	///   });
-	/// }
-	/// # }; }
+	/// Records are read from the connection through a buffer of this size.
+	/// Every time the buffer runs empty, one read call is issued to the reader.
+	/// A larger buffer reduces the number of reads needed to receive big
+	/// request bodies. A smaller buffer reduces the memory consumed by every
+	/// connection. This is helpful if many idle connections are kept open.
+	///
+	/// The content of a single record can be up to 65535 bytes long. Records
+	/// bigger than the buffer are read in multiple steps. Records smaller than
+	/// the buffer are combined, so that multiple records can be received with
+	/// one read call. The buffer size does not limit the size of the records.
+	///
+	/// The default buffer size is 16 KiB.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::Requests;
+	/// let requests = Requests::new(empty(), sink(), 10, 10)
+	///   .read_buffer_size(64 * 1024);
 	/// ```
-	pub fn from_split_socket(split_socket: (R, W), max_conns: u8, max_reqs: u8) -> Self {
-		Self::new(split_socket.0, split_socket.1, max_conns, max_reqs)
+	pub fn read_buffer_size(mut self, size: usize) -> Self {
+		// The buffer is replaced before anything was read. No data can be lost here.
+		debug_assert!(self.reader.buffer().is_empty());
+		self.reader = BufReader::with_capacity(size, self.reader.into_inner());
+		self
 	}
 
-	/// Processes and answers system records.
-	/// If this method returns Error::Canceled an FCGI_END_REQUEST was already sent. Just discard the Request instance
-	/// assigned for this connection.
-	async fn process_sys(&self, record: Record) -> Result<Option<RequestId>, Error> {
-		if let Category::Sys(record_type) = record.record_type {
-			let output_stream = OutRecordWriter::new(self.writer.clone(), record.request_id);
+	/// Limits the time it may take to receive a single record.
+	///
+	/// Waiting for the web-server to start the next record is not limited by
+	/// this setting. As soon as the first byte of a record was received, the
+	/// complete record must arrive within `timeout`. The content of a record
+	/// is limited to 65535 bytes by the protocol, but a peer could announce a
+	/// big record and stall while sending it. This would block the connection
+	/// and keep all requests on it in memory.
+	///
+	/// If a record is not received in time, [`next`](Requests::next) returns an
+	/// [`IoError`](Error::IoError) of kind
+	/// [`TimedOut`](std::io::ErrorKind::TimedOut). This error is fatal and the
+	/// connection should be closed.
+	///
+	/// By default the time to receive a record is not limited.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use std::time::Duration;
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::Requests;
+	/// let requests = Requests::new(empty(), sink(), 10, 10)
+	///   .record_timeout(Duration::from_secs(30));
+	/// ```
+	pub fn record_timeout(mut self, timeout: Duration) -> Self {
+		self.record_timeout = Some(timeout);
+		self
+	}
 
-			let result = match record_type {
-				SysReqType::GetValues => {
-					let mut params = HashMap::new();
+	/// Closes the connection if the web-server does not send anything for
+	/// `timeout`.
+	///
+	/// Every record received from the web-server counts as activity and
+	/// restarts the timer. This includes management records like `GetValues`
+	/// that are not part of a request. The timer only runs while
+	/// [`next`](Requests::next) waits for the next record.
+	///
+	/// If the timeout elapses and no request is partially received,
+	/// [`next`](Requests::next) returns `None` to signal that the connection
+	/// should be closed. If a request was started but not completed, an
+	/// [`IoError`](Error::IoError) of kind
+	/// [`TimedOut`](std::io::ErrorKind::TimedOut) is returned instead.
+	///
+	/// By default idle connections are kept open forever.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use std::time::Duration;
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::Requests;
+	/// let requests = Requests::new(empty(), sink(), 10, 10)
+	///   .idle_timeout(Duration::from_secs(300));
+	/// ```
+	pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+		self.idle_timeout = Some(timeout);
+		self
+	}
 
-					//TODO: Is this function correctly placed in request?
-					Request::<W>::add_nv_pairs(&mut params, record.get_content(), false)?;
+	/// Limits the time it may take to receive the complete `StdIn` stream of a
+	/// request.
+	///
+	/// The time starts as soon as the parameters of a request were received
+	/// and ends with the empty record terminating `StdIn`. Contrary to
+	/// [`record_timeout`](Requests::record_timeout) and
+	/// [`idle_timeout`](Requests::idle_timeout) this limits the total time. A
+	/// web-server trickling the body in tiny records, like a slowloris attack
+	/// forwarded by it, can not keep the request in memory forever. This
+	/// includes bodies that are announced via `CONTENT_LENGTH` but never
+	/// delivered.
+	///
+	/// If the body is not received in time, the request is ended with
+	/// [`Complete(1)`](RequestResult::Complete) and [`next`](Requests::next)
+	/// returns [`StdinTimeout`](Error::StdinTimeout). The error only affects
+	/// this request. Further requests can be fetched from the connection.
+	///
+	/// The deadline is checked between records. A record that is being
+	/// received is limited by [`record_timeout`](Requests::record_timeout).
+	///
+	/// By default the time to receive the body is not limited.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use std::time::Duration;
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::Requests;
+	/// let requests = Requests::new(empty(), sink(), 10, 10)
+	///   .record_timeout(Duration::from_secs(10))
+	///   .stdin_read_timeout(Duration::from_secs(60));
+	/// ```
+	pub fn stdin_read_timeout(mut self, timeout: Duration) -> Self {
+		self.stdin_read_timeout = Some(timeout);
+		self
+	}
 
-					// If we're testing this library we have to make sure that the output is sorted.
-					// Otherwise the binary compare of the produced FastCGI response is not stable.
-					// For production we will not do this, because it is an unnecessary performance bottleneck.
-					#[cfg(debug_assertions)]
-					let mut params: Vec<(String, _)> = params.into_iter().collect();
-					#[cfg(debug_assertions)]
-					params.sort_by(|a, b| { a.0.cmp(&b.0) });
+	/// Limits the number of bytes buffered for the `StdIn` and the `Data`
+	/// stream of a request.
+	///
+	/// Requests are only returned by [`next`](Requests::next) after their body
+	/// was received completely. Until then it is held in memory. Without a
+	/// limit a web-server forwarding a huge body can make the application run
+	/// out of memory.
+	///
+	/// The limit applies to each stream of every request separately. If a
+	/// stream gets bigger than `size` bytes, the already received data is
+	/// dropped and the request is ended with
+	/// [`Overloaded`](RequestResult::Overloaded). The remaining records of the
+	/// request are ignored. [`next`](Requests::next) returns
+	/// [`BodySizeExceeded`](Error::BodySizeExceeded). The error only affects
+	/// this request. Other requests on the same connection are not touched.
+	///
+	/// By default the size of the body is not limited.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::Requests;
+	/// let requests = Requests::new(empty(), sink(), 10, 10)
+	///   .max_body_size(16 * 1024 * 1024);
+	/// ```
+	pub fn max_body_size(mut self, size: usize) -> Self {
+		self.max_body_size = Some(size);
+		self
+	}
 
-					// Construct a vector containing the known parameters.
-					// All other parameters are simply ignored.
-					let mut output = Vec::with_capacity(128);
-					for (name, _) in params {
-						let result = match &*name {
-							"FCGI_MAX_CONNS" => Some(self.max_conns),
-							"FCGI_MAX_REQS" => Some(self.max_reqs),
-							"FCGI_MPXS_CONNS" => Some(1),
-							_ => None
-						};
+	/// Replaces the function that normalizes the names of the parameters.
+	///
+	/// By default the names of all parameters are converted to lower case. This
+	/// makes looking up parameters case insensitive. The passed `normalizer` is
+	/// called with the raw name of every received parameter. The returned name
+	/// is used to store the parameter. The normalizer is applied to the names
+	/// passed to [`get_param`](Request::get_param) and
+	/// [`get_str_param`](Request::get_str_param), too. It should therefore
+	/// return the same name if it is called with an already normalized name.
+	///
+	/// The normalizer runs once for every received parameter and once for
+	/// every lookup. Keep it cheap and return [`Cow::Borrowed`] if the name does
+	/// not need to be changed.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use std::borrow::Cow;
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::Requests;
+	/// // Keep the names of the parameters as the web-server sent them.
+	/// let requests = Requests::new(empty(), sink(), 10, 10)
+	///   .param_name_normalizer(|name| Cow::Borrowed(name));
+	/// ```
+	pub fn param_name_normalizer<F>(mut self, normalizer: F) -> Self
+	where
+		F: Fn(&[u8]) -> Cow<'_, [u8]> + Send + Sync + 'static
+	{
+		self.param_name_normalizer = Some(Arc::new(normalizer));
+		self
+	}
 
-						if let Some(result) = result {
-							let result_str = result.to_string();
-							// We can get away with casting here because we know that the names and values can not get longer than what fits into 7 Bits of an u8.
-							Write::write_all(&mut output, &[name.len() as u8])?;
-							Write::write_all(&mut output, &[result_str.len() as u8])?;
+	/// Sets the line ending of the CGI headers generated by this library.
+	///
+	/// The CGI specification allows the header lines of a response to end with
+	/// `\n` or `\r\n`. Some web-servers only accept one of them. The line
+	/// ending is used by all functions that generate headers, like
+	/// [`Request::respond_with`] and [`Request::set_status`], including the
+	/// empty line that separates the headers from the body. Headers written
+	/// directly to [`Request::get_stdout`] are sent unchanged.
+	///
+	/// The default is [`LineEnding::CrLf`].
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{LineEnding, Requests};
+	/// let requests = Requests::new(empty(), sink(), 10, 10)
+	///   .header_line_ending(LineEnding::Lf);
+	/// ```
+	pub fn header_line_ending(mut self, line_ending: LineEnding) -> Self {
+		self.response_config.line_ending = line_ending;
+		self
+	}
+
+	/// Accepts requests with a role number this library does not know.
+	///
+	/// By default a request with an unknown role makes [`next`](Requests::next)
+	/// fail with [`InvalidRoleNumber`](Error::InvalidRoleNumber). If `allow` is
+	/// `true`, these requests are returned like responders. The processor can
+	/// detect them via [`Request::try_role`] and answer them with
+	/// [`RequestResult::UnknownRole`].
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::Requests;
+	/// let requests = Requests::new(empty(), sink(), 10, 10)
+	///   .allow_unknown_roles(true);
+	/// ```
+	pub fn allow_unknown_roles(mut self, allow: bool) -> Self {
+		self.allow_unknown_roles = allow;
+		self
+	}
+
+	/// Restricts the roles of the requests accepted on this connection.
+	///
+	/// A request for a role that is not contained in `roles` is answered with
+	/// [`UnknownRole`](RequestResult::UnknownRole) as soon as its
+	/// `BeginRequest` record arrives. It is never returned by
+	/// [`next`](Requests::next), so the processor does not have to check the
+	/// role. By default all roles are accepted. Role numbers this library does
+	/// not know are not affected by this filter. See
+	/// [`allow_unknown_roles`](Requests::allow_unknown_roles) for them.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, Role};
+	/// let requests = Requests::new(empty(), sink(), 10, 10)
+	///   .accepted_roles(&[Role::Responder]);
+	/// ```
+	pub fn accepted_roles(mut self, roles: &[Role]) -> Self {
+		self.accepted_roles = Some(roles.iter().map(Role::number).collect());
+		self
+	}
+
+	/// Checks the `Content-Length` header of every response.
+	///
+	/// If `check` is `true`, the headers written to `StdOut` are inspected. If
+	/// they contain a `Content-Length` header, the number of body bytes written
+	/// after the headers must match it. Otherwise a warning is logged and
+	/// [`Request::process`] returns
+	/// [`ContentLengthMismatch`](Error::ContentLengthMismatch) after the
+	/// request was finished. This catches responses the web-server would
+	/// truncate or wait for forever.
+	///
+	/// The check costs some time for every write to `StdOut`. It is meant for
+	/// development and is disabled by default.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::Requests;
+	/// let requests = Requests::new(empty(), sink(), 10, 10)
+	///   .check_content_length(cfg!(debug_assertions));
+	/// ```
+	pub fn check_content_length(mut self, check: bool) -> Self {
+		self.response_config.check_content_length = check;
+		self
+	}
+
+	/// Limits the number of CGI headers a response may contain.
+	///
+	/// The header lines written to `StdOut` are counted until the empty line
+	/// that ends them. The `Status` header and the headers generated by this
+	/// library are counted, too. A write that exceeds `max_headers` is not
+	/// sent and fails with
+	/// [`TooManyResponseHeaders`](Error::TooManyResponseHeaders). This stops a
+	/// buggy processor that emits headers in a loop before it produces a
+	/// pathological response.
+	///
+	/// By default the number of headers is not limited.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::Requests;
+	/// let requests = Requests::new(empty(), sink(), 10, 10)
+	///   .max_response_headers(100);
+	/// ```
+	pub fn max_response_headers(mut self, max_headers: usize) -> Self {
+		self.response_config.max_response_headers = Some(max_headers);
+		self
+	}
+
+	/// Sets a `Content-Type` for responses that do not specify one.
+	///
+	/// The header lines written to `StdOut` are inspected. If the empty line
+	/// ending them is reached without a `Content-Type` header,
+	/// `Content-Type: <content_type>` is inserted before it. This keeps the
+	/// web-server from guessing the type of the response. Responses that only
+	/// consist of a status set via [`set_status`](Request::set_status) do not
+	/// get the header. They have no body.
+	///
+	/// A `content_type` containing a line break is ignored. By default no
+	/// `Content-Type` is added.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::Requests;
+	/// let requests = Requests::new(empty(), sink(), 10, 10)
+	///   .default_content_type("text/html; charset=utf-8");
+	/// ```
+	pub fn default_content_type(mut self, content_type: &str) -> Self {
+		self.response_config.set_default_content_type(content_type);
+		self
+	}
+
+	/// Reports requests that take longer than `threshold`.
+	///
+	/// The time is measured from the `BeginRequest` record until the processor
+	/// passed to [`Request::process`] returns (see [`Request::elapsed`]). If it
+	/// exceeds `threshold`, a warning with the id, the method, the URI and the
+	/// elapsed time is logged. This shows latency outliers without
+	/// instrumenting every processor. Use
+	/// [`on_slow_request`](Requests::on_slow_request) to handle the slow
+	/// requests in another way.
+	///
+	/// By default slow requests are not reported.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use std::time::Duration;
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::Requests;
+	/// let requests = Requests::new(empty(), sink(), 10, 10)
+	///   .slow_request_threshold(Duration::from_secs(1));
+	/// ```
+	pub fn slow_request_threshold(mut self, threshold: Duration) -> Self {
+		self.slow_request_threshold = Some(threshold);
+		self
+	}
+
+	/// Calls `callback` for every request that takes longer than the threshold
+	/// set via [`slow_request_threshold`](Requests::slow_request_threshold)
+	/// instead of logging a warning.
+	///
+	/// The callback is called from within [`Request::process`] right after the
+	/// processor returned. It should not block.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use std::time::Duration;
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::Requests;
+	/// let requests = Requests::new(empty(), sink(), 10, 10)
+	///   .slow_request_threshold(Duration::from_secs(1))
+	///   .on_slow_request(|request| println!("Slow request to {:?}: {:?}", request.uri, request.elapsed));
+	/// ```
+	pub fn on_slow_request<F>(mut self, callback: F) -> Self
+	where
+		F: Fn(&SlowRequest) + Send + Sync + 'static
+	{
+		self.slow_request_callback = Some(Arc::new(callback));
+		self
+	}
+
+	/// Calls `callback` for every request processed via [`Request::process`].
+	///
+	/// The [`RequestSummary`] passed to `callback` contains the method, the
+	/// URI, the HTTP status, the number of bytes sent and the time it took to
+	/// process the request. This allows writing an access log without
+	/// instrumenting every processor.
+	///
+	/// The callback is called from within [`Request::process`] after the
+	/// `EndRequest` record was sent and the connection was flushed. The
+	/// web-server may already have delivered the response to the client at
+	/// this point. If ending the request failed, the callback is called anyway
+	/// before the error is returned. Requests that are rejected or ended by
+	/// this connection without being processed are not reported. The callback
+	/// should not block.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::Requests;
+	/// let requests = Requests::new(empty(), sink(), 10, 10)
+	///   .on_complete(|summary| println!("{} {} {:?} {} bytes in {:?}",
+	///     summary.method.as_deref().unwrap_or("-"), summary.uri.as_deref().unwrap_or("-"),
+	///     summary.status, summary.bytes_out, summary.elapsed));
+	/// ```
+	pub fn on_complete<F>(mut self, callback: F) -> Self
+	where
+		F: Fn(&RequestSummary) + Send + Sync + 'static
+	{
+		self.complete_callback = Some(Arc::new(callback));
+		self
+	}
+
+	/// Sets a hook that can reject requests as soon as they begin.
+	///
+	/// `callback` is called for every `BeginRequest` record that passed the
+	/// checks of this connection (like [`accepted_roles`](Requests::accepted_roles)).
+	/// If it returns `Some(result)`, the request is ended with `result` right
+	/// away. Its parameters and body are skipped while they are received and
+	/// never buffered. The request is not returned by [`next`](Requests::next).
+	/// This saves the work for requests that are destined for rejection. If
+	/// `None` is returned, the request is received and processed normally.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{RequestResult, Requests, Role};
+	/// let requests = Requests::new(empty(), sink(), 10, 10)
+	///   .on_begin(|info| (info.role != Some(Role::Authorizer)).then_some(RequestResult::UnknownRole));
+	/// ```
+	pub fn on_begin<F>(mut self, callback: F) -> Self
+	where
+		F: Fn(&BeginRequestInfo) -> Option<RequestResult> + Send + Sync + 'static
+	{
+		self.begin_callback = Some(Arc::new(callback));
+		self
+	}
+
+	/// Makes this connection occupy a slot of `limiter`.
+	///
+	/// The slot is taken immediately and released when this instance is
+	/// dropped. If all slots are taken, the first request the web-server starts
+	/// on this connection is answered with
+	/// [`Overloaded`](RequestResult::Overloaded). Then [`next`](Requests::next)
+	/// returns `None` to close the connection. See [`ConnectionLimiter`] for
+	/// an example.
+	pub fn connection_limiter(mut self, limiter: &ConnectionLimiter) -> Self {
+		self.connection_permit = limiter.try_acquire();
+		self.over_capacity = self.connection_permit.is_none();
+		self
+	}
+
+	/// Returns the read and write side of the connection.
+	///
+	/// This allows the connection to be handed to another subsystem after the
+	/// FastCGI requests have been processed. The connection can only be
+	/// reclaimed if no requests are in flight. This means that all requests
+	/// returned by [`next`](Requests::next) must have been processed and
+	/// dropped and no partially received requests must be pending. Data that
+	/// was already read from the connection but not processed would be lost
+	/// if the reader was returned. This is why the connection can not be
+	/// reclaimed in this case either. Otherwise the unchanged [`Requests`]
+	/// instance is returned as the boxed error value.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// let mut requests = Requests::new(empty(), sink(), 1, 1);
+	///
+	/// while let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	///   request.process(|request| async move {
+	///     RequestResult::Complete(0)
+	///   }).await.expect("Request could not be processed.");
+	/// }
+	///
+	/// if let Ok((reader, writer)) = requests.into_inner() {
+	///   // Use the connection for something else.
+	/// }
+	/// # }
+	/// ```
+	pub fn into_inner(self) -> Result<(R, W), Box<Self>> {
+		if !self.requests.is_empty() || !self.reader.buffer().is_empty() {
+			return Err(Box::new(self));
+		}
+
+		// Every request holds a reference to the writer. If we can unwrap it, there are
+		// no requests in flight.
+		match Arc::try_unwrap(self.writer) {
+			Ok(writer) => Ok((self.reader.into_inner(), writer.stream.into_inner())),
+			Err(writer) => Err(Box::new(Self { writer, ..self }))
+		}
+	}
+
+	/// Returns statistics about the requests received via this connection.
+	///
+	/// This can be used to detect if the web-server reuses its connections.
+	/// Establishing a new connection for every request costs performance. If
+	/// the statistics show that connections are not reused, the configuration
+	/// of the web-server should be checked.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// let mut requests = Requests::new(empty(), sink(), 1, 1);
+	///
+	/// while let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	///   request.process(|request| async move {
+	///     RequestResult::Complete(0)
+	///   }).await.expect("Request could not be processed.");
+	/// }
+	///
+	/// let stats = requests.connection_stats();
+	/// if stats.requests == 1 && !stats.is_reused() {
+	///   println!("Connection was used for one request only. Is connection reuse enabled?");
+	/// }
+	/// # }
+	/// ```
+	pub fn connection_stats(&self) -> ConnectionStats {
+		ConnectionStats {
+			requests: self.accepted_requests,
+			keep_conn_requests: self.keep_conn_requests
+		}
+	}
+
+	/// Returns the ids and phases of the requests that are still being
+	/// received.
+	///
+	/// Requests are only returned by [`next`](Requests::next) after they were
+	/// received completely. This function shows the requests that are not
+	/// complete yet and what they are waiting for. Combined with
+	/// [`idle_timeout`](Requests::idle_timeout) it helps to diagnose a
+	/// web-server that never terminates a stream. The order of the returned
+	/// requests is not defined.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::Requests;
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// let mut requests = Requests::new(empty(), sink(), 1, 1);
+	///
+	/// while let Ok(Some(request)) = requests.next().await {
+	///   // Process the request
+	/// }
+	///
+	/// for (request_id, phase) in requests.pending_requests() {
+	///   println!("Request {} was not completed. It was stuck in phase {:?}.", request_id, phase);
+	/// }
+	/// # }
+	/// ```
+	pub fn pending_requests(&self) -> Vec<(u16, Phase)> {
+		self.requests.iter().map(|request| (request.request_id, request.phase())).collect()
+	}
+
+	/// Returns the ids and phases of the requests returned by
+	/// [`next`](Requests::next) that are still being processed.
+	///
+	/// This allows a monitoring task to find requests that do not finish. A
+	/// request stays listed in the phase [`Processing`](Phase::Processing)
+	/// while its processor runs and in the phase [`Flushing`](Phase::Flushing)
+	/// while its terminating records are sent. Requests ended via
+	/// [`Request::abort`] are listed as [`Complete`](Phase::Complete) until
+	/// they are dropped. The list is sorted by request id.
+	pub fn processing_requests(&self) -> Vec<(u16, Phase)> {
+		let mut processing: Vec<(u16, Phase)> = self.processing.iter()
+			.filter(|(_, processing)| processing.abort_signal.strong_count() > 0)
+			.filter_map(|(request_id, processing)| Some((*request_id, processing.orw.upgrade()?.phase())))
+			.collect();
+		processing.sort_unstable_by_key(|(request_id, _)| *request_id);
+
+		processing
+	}
+
+	/// Checks if the number of requests configured via
+	/// [`max_requests_per_connection`](Requests::max_requests_per_connection) was
+	/// accepted.
+	fn request_limit_reached(&self) -> bool {
+		matches!(self.max_requests, Some(max_requests) if self.accepted_requests >= max_requests)
+	}
+
+	/// Rejects the request with the given id by sending an `EndRequest` record.
+	///
+	/// All further records for this request id are ignored until the
+	/// web-server starts a new request with this id.
+	async fn reject(&mut self, request_id: RequestId, result: RequestResult) -> Result<(), Error> {
+		self.reject_with_status(request_id, result.app_status(), result.into()).await
+	}
+
+	/// Rejects the request with the given id like [`reject`](Requests::reject)
+	/// but sends the passed application and protocol status.
+	async fn reject_with_status(&mut self, request_id: RequestId, app_status: u32, protocol_status: u8) -> Result<(), Error> {
+		self.requests.remove(request_id);
+		self.rejected.insert(request_id);
+
+		let output_stream = OutRecordWriter::new(self.writer.clone(), request_id);
+		output_stream.write_end_request(app_status, protocol_status).await?;
+		output_stream.flush().await?;
+
+		Ok(())
+	}
+
+	/// Same as [`new`](Requests::new) but takes a tuple containing the read and write
+	/// side of the socket instead of two distinct variables
+	///
+	/// This is more convenient in combination with the `split` function.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use tokio::net::TcpListener;
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+	/// # let server = async move {
+	/// if let Ok(mut socket) = listener.accept().await {
+	///   tokio::spawn(async move {
+	///   // Directly use the result of the split() call to construct a Requests instance
+	///     let mut requests = Requests::from_split_socket(socket.0.split(), 10, 10);
+	///
+	///     // Process the requests
+	///   });
+	/// }
+	/// # }; }
+	/// ```
+	pub fn from_split_socket(split_socket: (R, W), max_conns: u8, max_reqs: u8) -> Self {
+		Self::new(split_socket.0, split_socket.1, max_conns, max_reqs)
+	}
+
+	/// Processes and answers system records.
+	/// If this method returns Error::Canceled an FCGI_END_REQUEST was already sent. Just discard the Request instance
+	/// assigned for this connection.
+	async fn process_sys(&self, record: Record) -> Result<Option<RequestId>, Error> {
+		if let Category::Sys(record_type) = record.record_type {
+			let output_stream = OutRecordWriter::new(self.writer.clone(), record.request_id);
+
+			let result = match record_type {
+				SysReqType::GetValues => {
+					let mut params = HashMap::new();
+
+					//TODO: Is this function correctly placed in request?
+					Request::<W>::add_nv_pairs(&mut params, None, record.get_content(), &|name| String::from_utf8_lossy(name).into_owned())?;
+
+					// If we're testing this library we have to make sure that the output is sorted.
+					// Otherwise the binary compare of the produced FastCGI response is not stable.
+					// For production we will not do this, because it is an unnecessary performance bottleneck.
+					#[cfg(debug_assertions)]
+					let mut params: Vec<(String, _)> = params.into_iter().collect();
+					#[cfg(debug_assertions)]
+					params.sort_by(|a, b| { a.0.cmp(&b.0) });
+
+					// Construct a vector containing the known parameters.
+					// All other parameters are simply ignored.
+					let mut output = Vec::with_capacity(128);
+					for (name, _) in params {
+						let result = match &*name {
+							"FCGI_MAX_CONNS" => Some(self.max_conns),
+							"FCGI_MAX_REQS" => Some(self.max_reqs),
+							"FCGI_MPXS_CONNS" => Some(1),
+							_ => None
+						};
+
+						if let Some(result) = result {
+							let result_str = result.to_string();
+							// We can get away with casting here because we know that the names and values can not get longer than what fits into 7 Bits of an u8.
+							Write::write_all(&mut output, &[name.len() as u8])?;
+							Write::write_all(&mut output, &[result_str.len() as u8])?;
+
+							Write::write_all(&mut output, name.as_bytes())?;
+							Write::write_all(&mut output, result_str.as_bytes())?;
+						}
+					}
+
+					output_stream.write_data(Category::Sys(SysRespType::GetValuesResult), &output[..]).await?;
+
+					Ok(None)
+				},
+				SysReqType::AbortRequest => {
+					output_stream.write_finish(RequestResult::Complete(0)).await?;
+
+					Ok(Some(record.get_request_id()))
+				}
+			};
+
+			output_stream.flush().await?;
+
+			result
+		} else {
+			panic!("process_sys called with non sys record.");
+		}
+	}
+
+	/// Fetches the next request from this connection
+	///
+	/// This function asynchronously fetches FastCGI records and assembles them
+	/// into requests. It does the de-interlacing to allow multiple requests to
+	/// be processed in parallel. As soon as the information for a request is
+	/// complete, it returns a [`Request`] instance for further processing.
+	///
+	/// This function will do the book keeping and process system requests like
+	/// `FCGI_GET_VALUES` or `FCGI_ABORT_REQUEST`.
+	///
+	/// If a request does not set the `FCGI_KEEP_CONN` flag, the connection is
+	/// closed after it: `None` is returned after the request. Requests the
+	/// web-server starts on the same connection while such a request is pending
+	/// are rejected with the protocol status `FCGI_CANT_MPX_CONN`.
+	pub async fn next(&mut self) -> Result<Option<Request<W>>, Error> {
+		self.next_until(None).await
+	}
+
+	/// Fetches the next request from this connection but waits at most `timeout`
+	/// for it.
+	///
+	/// Works like [`next`](Requests::next) but returns `Ok(None)` if no request
+	/// got ready within `timeout`. The connection is not closed in this case.
+	/// Use [`is_finished`](Requests::is_finished) to tell this apart from the
+	/// end of the connection and call `next_timeout` again to continue waiting.
+	/// This allows a server to do periodic maintenance in between.
+	///
+	/// Unlike wrapping [`next`](Requests::next) in [`tokio::time::timeout`] this
+	/// never loses data: The timeout only hits while waiting for the next
+	/// record to start. A record that already started is always read
+	/// completely. Records of requests that are not ready yet stay buffered
+	/// for the next call.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use std::time::Duration;
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// loop {
+	///   match requests.next_timeout(Duration::from_secs(60)).await.expect("Request could not be constructed.") {
+	///     Some(request) => {
+	///       request.process(|_request| async move { RequestResult::Complete(0) }).await.expect("Request could not be processed.");
+	///     },
+	///     None if requests.is_finished() => break,
+	///     None => {
+	///       // Do some maintenance.
+	///     }
+	///   }
+	/// }
+	/// # }
+	/// ```
+	pub async fn next_timeout(&mut self, timeout: Duration) -> Result<Option<Request<W>>, Error> {
+		self.next_until(Some(tokio::time::Instant::now() + timeout)).await
+	}
+
+	/// Returns `true` if this connection will not return any more requests.
+	///
+	/// This is the case as soon as [`next`](Requests::next) or
+	/// [`next_timeout`](Requests::next_timeout) signaled the end of the
+	/// connection or returned the last request of it. If
+	/// [`stream_stdin`](Requests::stream_stdin) is enabled, the connection is
+	/// only finished after the bodies of the returned requests were received.
+	pub fn is_finished(&self) -> bool {
+		self.close_on_next && !self.stdin_pending()
+	}
+
+	/// Implements [`next`](Requests::next) and
+	/// [`next_timeout`](Requests::next_timeout). If no request got ready until
+	/// `call_deadline`, `Ok(None)` is returned without closing the connection.
+	async fn next_until(&mut self, call_deadline: Option<tokio::time::Instant>) -> Result<Option<Request<W>>, Error> {
+		let result = self.read_until(call_deadline).await;
+
+		// No records are read after a fatal error. The streamed bodies fail.
+		if matches!(&result, Err(err) if err.is_fatal()) {
+			self.streaming.clear();
+		}
+
+		result
+	}
+
+	/// Reads records until a request got ready. See
+	/// [`next_until`](Requests::next_until).
+	async fn read_until(&mut self, call_deadline: Option<tokio::time::Instant>) -> Result<Option<Request<W>>, Error> {
+		if self.close_on_next && !self.stdin_pending() {
+			if !self.requests.is_empty() {
+				warn!("FastCGI: The web-server interleaved requests on this connection but did not use the FCGI_KEEP_CONN flag. {} requests will get lost.", self.requests.len());
+			}
+
+			// Signal to the user that this connection should be closed.
+			Ok(None)
+		} else {
+			loop
+			{
+				let stdin_deadline = self.requests.iter()
+					.filter(|request| request.phase() == Phase::AwaitingStdin)
+					.filter_map(|request| request.stdin_deadline.map(|deadline| (deadline, request.request_id)))
+					.min();
+
+				if let Some((deadline, request_id)) = stdin_deadline {
+					if tokio::time::Instant::now() >= deadline {
+						warn!("FastCGI: StdIn of request {} was not received in time. Ending the request.", request_id);
+						let keep_connection = self.requests.get_mut(request_id).is_some_and(|request| request.keep_connection);
+						self.reject(request_id, RequestResult::Complete(1)).await?;
+
+						// The connection is closed as if the request was processed.
+						self.close_on_next = !keep_connection || (self.request_limit_reached() && self.requests.is_empty());
+
+						return Err(Error::StdinTimeout(request_id));
+					}
+				}
+
+				let idle_deadline = self.idle_timeout.map(|idle_timeout| tokio::time::Instant::now() + idle_timeout);
+				let deadline = [idle_deadline, stdin_deadline.map(|(deadline, _)| deadline), call_deadline].into_iter().flatten().min();
+
+				if let Some(deadline) = deadline {
+					// Wait for the next record to start. Filling the buffer does not consume any data.
+					if tokio::time::timeout_at(deadline, self.reader.fill_buf()).await.is_err() {
+						if stdin_deadline.is_some_and(|(stdin_deadline, _)| stdin_deadline == deadline) {
+							// The StdIn deadline of a request passed.
+							continue;
+						} else if Some(deadline) == call_deadline {
+							trace!("FastCGI: No request got ready in time. Keeping the connection open.");
+							return Ok(None);
+						} else if self.requests.is_empty() && !self.stdin_pending() {
+							debug!("FastCGI: Connection was idle for {:?}. Closing it.", self.idle_timeout.unwrap_or_default());
+							self.close_on_next = true;
+							return Ok(None);
+						} else {
+							return Err(Error::from(std::io::Error::new(std::io::ErrorKind::TimedOut, "Connection was idle while requests were pending")));
+						}
+					}
+				}
+
+				match Record::new(&mut self.reader, self.record_timeout).await {
+					// Success, a new record hast to be added to its request...
+					Ok(record) => {
+						if record.is_sys_record() {
+							// A request that is processed is signaled. The processor ends it.
+							if let Category::Sys(SysReqType::AbortRequest) = record.record_type {
+								if let Some(abort_signal) = self.processing.get(&record.get_request_id()).and_then(|processing| processing.abort_signal.upgrade()) {
+									debug!("FastCGI: Request {} was aborted by the web-server while it was processed.", record.get_request_id());
+									abort_signal.abort();
+
+									// The rest of the body is not sent anymore. Dropping the sender fails the stream.
+									self.streaming.remove(&record.get_request_id());
+									continue;
+								}
+							}
+
+							if let Some(canceled_request_id) = self.process_sys(record).await? {
+								// The request got canceled. Remove it from the list
+								self.requests.remove(canceled_request_id);
+
+								// The rest of the body is not sent anymore. Dropping the sender fails the stream.
+								self.streaming.remove(&canceled_request_id);
+
+								// If this was the last accepted request, the connection can be closed.
+								if self.request_limit_reached() && self.requests.is_empty() {
+									self.close_on_next = true;
+									return Ok(None);
+								}
+							}
+						} else {
+							let request_id = record.get_request_id();
+
+							// The body of a returned request is passed to its processor.
+							if let (Category::Std(StdReqType::StdIn), Some(sender)) = (record.record_type, self.streaming.get(&request_id)) {
+								if self.max_body_size.is_some_and(|limit| sender.received() + record.get_content().len() > limit) {
+									warn!("FastCGI: Body of request {} exceeds {} bytes. Ignoring the rest of it.", request_id, self.max_body_size.unwrap_or_default());
+									sender.fail(std::io::ErrorKind::InvalidData);
+									self.streaming.remove(&request_id);
+									self.rejected.insert(request_id);
+
+									return Err(Error::BodySizeExceeded(request_id));
+								}
+
+								sender.push(record.get_content());
+								if record.get_content().is_empty() {
+									self.streaming.remove(&request_id);
+
+									if self.close_on_next && !self.stdin_pending() {
+										return Ok(None);
+									}
+								}
+
+								continue;
+							}
+
+							if let Category::Std(StdReqType::BeginRequest) = record.record_type {
+								// A new request replaces a rejected one with the same id.
+								self.rejected.remove(&request_id);
+
+								// A new request with the same id replaces one whose body was still received.
+								self.streaming.remove(&request_id);
+
+								// The connection is only kept open until the pending bodies were received.
+								if self.close_on_next {
+									let protocol_status = if self.request_limit_reached() { RequestResult::Overloaded.into() } else { FCGI_CANT_MPX_CONN };
+									warn!("FastCGI: Connection is closed after the pending bodies were received. Rejecting request {}.", request_id);
+									self.reject_with_status(request_id, 0, protocol_status).await?;
+									continue;
+								}
+
+								if self.over_capacity {
+									warn!("FastCGI: Connection limit reached. Rejecting request {} and closing the connection.", request_id);
+									self.reject(request_id, RequestResult::Overloaded).await?;
+									self.close_on_next = true;
+									return Ok(None);
+								}
+
+								// Without FCGI_KEEP_CONN the connection is closed after the request. No other request may be started on it.
+								let closing_request_id = self.requests.iter().find(|request| !request.keep_connection && request.request_id != request_id).map(|request| request.request_id);
+								if let Some(closing_request_id) = closing_request_id {
+									warn!("FastCGI: Request {} did not set FCGI_KEEP_CONN. Rejecting request {} started on the same connection.", closing_request_id, request_id);
+									self.reject_with_status(request_id, 0, FCGI_CANT_MPX_CONN).await?;
+									continue;
+								}
+
+								if !self.requests.contains(request_id) && self.request_limit_reached() {
+									warn!("FastCGI: Maximum number of requests for this connection reached. Rejecting request {}.", request_id);
+									self.reject(request_id, RequestResult::Overloaded).await?;
+									continue;
+								}
+
+								if let (Some(accepted_roles), Some(&[high, low])) = (&self.accepted_roles, record.get_content().get(..2)) {
+									let role_number = u16::from_be_bytes([high, low]);
+									if Role::from_number(role_number).is_some() && !accepted_roles.contains(&role_number) {
+										debug!("FastCGI: Role {} is not accepted. Rejecting request {}.", role_number, request_id);
+										self.reject(request_id, RequestResult::UnknownRole).await?;
+										continue;
+									}
+								}
+
+								if let (Some(callback), Some(&[role_high, role_low, flags])) = (&self.begin_callback, record.get_content().get(..3)) {
+									let role_number = u16::from_be_bytes([role_high, role_low]);
+									let info = BeginRequestInfo {
+										request_id,
+										role: Role::from_number(role_number),
+										role_number,
+										keep_connection: (flags & FCGI_KEEP_CONN) == FCGI_KEEP_CONN
+									};
+
+									if let Some(result) = callback(&info) {
+										debug!("FastCGI: Request {} was rejected by the begin hook.", request_id);
+										self.reject(request_id, result).await?;
+										continue;
+									}
+								}
+							} else if self.rejected.contains(&request_id) {
+								trace!("FastCGI: Ignoring record for rejected request {}.", request_id);
+								continue;
+							}
+
+							let request_ready = match self.requests.get_mut(request_id) {
+								Some(request) => {
+									if self.max_body_size.is_some_and(|limit| request.exceeds_body_size(&record, limit)) {
+										warn!("FastCGI: Body of request {} exceeds {} bytes. Ending the request.", request_id, self.max_body_size.unwrap_or_default());
+										let keep_connection = request.keep_connection;
+										self.reject(request_id, RequestResult::Overloaded).await?;
+
+										// The connection is closed as if the request was processed.
+										self.close_on_next = !keep_connection || (self.request_limit_reached() && self.requests.is_empty());
+
+										return Err(Error::BodySizeExceeded(request_id));
+									}
+
+									let request_ready = request.update(&record)?;
+
+									// The time to receive StdIn starts as soon as the parameters are complete.
+									if let Some(timeout) = self.stdin_read_timeout {
+										if request.stdin_deadline.is_none() && request.phase() == Phase::AwaitingStdin {
+											request.stdin_deadline = Some(tokio::time::Instant::now() + timeout);
+										}
+									}
+
+									request_ready
+								},
+								None => {
+									let mut request = Request::new(&record, self.writer.clone(), self.param_name_normalizer.clone(), self.response_config.clone(), self.allow_unknown_roles)?;
+
+									// The request keeps its slot of serve_concurrent until it was processed.
+									if let Some(slots) = &self.concurrency {
+										match slots.clone().try_acquire_owned() {
+											Ok(permit) => request.concurrency_permit = Some(permit),
+											Err(_) => {
+												warn!("FastCGI: Maximum number of concurrent requests reached. Rejecting request {}.", request_id);
+												self.reject(request_id, RequestResult::Overloaded).await?;
+												continue;
+											}
+										}
+									}
+									if self.stream_stdin && request.role == Role::Responder {
+										request.stdin_channel = Some(Arc::default());
+									}
+
+									self.accepted_requests += 1;
+									if request.keep_connection {
+										self.keep_conn_requests += 1;
+									}
+
+									self.requests.insert(request);
+									false
+								}
+							};
+
+							if request_ready {
+								let mut request = self.requests.remove(request_id).unwrap();
+								request.slow_request = self.slow_request_threshold.map(|threshold| (threshold, self.slow_request_callback.clone()));
+								request.complete_callback = self.complete_callback.clone();
+
+								// Aborts for the request are signaled as long as it is processed.
+								self.processing.retain(|_, processing| processing.abort_signal.strong_count() > 0);
+								self.processing.insert(request_id, ProcessingRequest {
+									abort_signal: Arc::downgrade(&request.abort_signal),
+									orw: Arc::downgrade(&request.orw)
+								});
+
+								// The rest of the body is received while the request is processed.
+								if let Some(channel) = request.stdin_channel.as_ref().filter(|channel| !channel.is_done()) {
+									self.streaming.insert(request_id, StdinSender::new(channel.clone()));
+								}
+
+								// Store if we should close the connection after handling this request.
+								// If the request limit was reached, the connection is closed as soon as
+								// the last accepted request was returned.
+								self.close_on_next = !request.keep_connection || (self.request_limit_reached() && self.requests.is_empty());
+
+								// Calling unwrap here is ok because we made sure there is an object for this id.
+								return Ok(Some(request));
+							}
+						}
+					},
+					// IoError UnexpectedEof: May be ok or an error. Depends on if requests have been processed.
+					Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+						// An I/O-error signals the end of the stream. On record construction this is ok as long
+						// as there are no other requests in flight.
+						for (_, sender) in self.streaming.drain() {
+							sender.fail(std::io::ErrorKind::UnexpectedEof);
+						}
+
+						if self.requests.is_empty() {
+							self.close_on_next = true;
+							return Ok(None)
+						} else {
+							return Err(Error::from(err));
+						}
+					},
+					// UnknownRecordType must be transmitted back to the server. This is not a fatal error.
+					Err(Error::UnknownRecordType(request_id, type_id)) => {
+						let output_stream = OutRecordWriter::new(self.writer.clone(), request_id);
+						output_stream.write_unkown_type(type_id).await?;
+					},
+					// An error occurred, exit the loop and return it...
+					Err(err) => {
+						return Err(err);
+					}
+				}
+			}
+		}
+	}
+
+	/// Processes all requests of this connection concurrently.
+	///
+	/// Every request returned by [`next`](Requests::next) is passed to a clone
+	/// of `processor`, which is spawned as a separate task like
+	/// [`Request::spawn_processor`] does. At most `max_concurrent` requests are
+	/// handled at the same time. A request takes its slot as soon as its
+	/// `BeginRequest` record is received and frees it when its processor has
+	/// finished. While all slots are taken, the records of the connection are
+	/// still read. This way `FCGI_ABORT_REQUEST` and `FCGI_GET_VALUES` records
+	/// are handled right away and streamed bodies (see
+	/// [`stream_stdin`](Requests::stream_stdin)) are received. New requests are
+	/// not buffered. They are rejected with `FCGI_OVERLOADED`.
+	///
+	/// Errors of single requests are logged and do not stop the connection.
+	/// This is true for non-fatal errors returned by [`next`](Requests::next)
+	/// as well as for errors and panics of the processors. A fatal error (see
+	/// [`Error::is_fatal`]) stops pulling new requests. It is returned after
+	/// all running processors have finished. If the connection ends regularly
+	/// `Ok(())` is returned as soon as the last processor has finished.
+	///
+	/// This function must be called from within a Tokio runtime.
+	///
+	/// # Panics
+	///
+	/// Panics if `max_concurrent` is zero.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// let mut requests = Requests::new(empty(), sink(), 10, 10);
+	///
+	/// requests.serve_concurrent(|request| async move {
+	///   // Process the request
+	///   RequestResult::Complete(0)
+	/// }, 4).await.expect("Connection failed.");
+	/// # }
+	/// ```
+	pub async fn serve_concurrent<T, F, C>(&mut self, processor: C, max_concurrent: usize) -> Result<(), Error>
+	where
+		W: 'static,
+		T: Into<RequestResult> + Send + 'static,
+		F: Future<Output = T> + Send + 'static,
+		C: FnOnce(Arc<Request<W>>) -> F + Clone + Send + 'static
+	{
+		assert!(max_concurrent > 0, "max_concurrent must be at least one.");
+
+		self.concurrency = Some(Arc::new(Semaphore::new(max_concurrent)));
+		let mut processors = tokio::task::JoinSet::new();
+
+		let result = loop {
+			while let Some(result) = processors.try_join_next() {
+				Self::log_processor_result(result);
+			}
+
+			match self.next().await {
+				Ok(Some(request)) => {
+					processors.spawn(request.process(processor.clone()));
+				},
+				Ok(None) => break Ok(()),
+				Err(err) if err.is_fatal() => break Err(err),
+				Err(err) => warn!("FastCGI: Request failed: {}", err)
+			}
+		};
+
+		while let Some(result) = processors.join_next().await {
+			Self::log_processor_result(result);
+		}
+		self.concurrency = None;
+
+		result
+	}
+
+	/// Logs the outcome of a processor spawned by
+	/// [`serve_concurrent`](Requests::serve_concurrent) if it failed.
+	fn log_processor_result(result: Result<Result<(), Error>, tokio::task::JoinError>) {
+		match result {
+			Ok(Ok(())) => (),
+			Ok(Err(err)) => warn!("FastCGI: Request could not be processed: {}", err),
+			Err(err) => warn!("FastCGI: Request processor failed: {}", err)
+		}
+	}
+}
+
+impl Requests<OwnedReadHalf, OwnedWriteHalf> {
+	/// Creates a `Requests` instance for a TCP connection accepted from the
+	/// web-server.
+	///
+	/// The stream is split into its read and write half. If `nodelay` is
+	/// `true`, `TCP_NODELAY` is set before. This disables Nagle's algorithm.
+	/// The small records of streamed responses (like server-sent events) and
+	/// the `EndRequest` record are then sent right away instead of being
+	/// delayed until the web-server acknowledged the previous segment. Use
+	/// [`into_inner`](Requests::into_inner) and
+	/// [`OwnedReadHalf::reunite`] to get the stream back.
+	///
+	/// Returns an error if `TCP_NODELAY` could not be set.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use tokio::net::TcpListener;
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+	/// # let server = async move {
+	/// if let Ok((stream, _)) = listener.accept().await {
+	///   tokio::spawn(async move {
+	///     let mut requests = Requests::from_tcp_stream(stream, 10, 10, true).expect("TCP_NODELAY could not be set.");
+	///
+	///     // Process the requests
+	///   });
+	/// }
+	/// # }; }
+	/// ```
+	pub fn from_tcp_stream(stream: TcpStream, max_conns: u8, max_reqs: u8, nodelay: bool) -> Result<Self, Error> {
+		if nodelay {
+			stream.set_nodelay(true)?;
+		}
+
+		Ok(Self::from_split_socket(stream.into_split(), max_conns, max_reqs))
+	}
+}
+
+// Generate nicer debug output for Request. This is useful if you look at the request
+// from within the `process` function.
+impl <W: AsyncWrite + Unpin> Debug for Request<W> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "Request {{ request_id: {}, keep_connection: {:?}, stdin: {:?}, data: {:?}, params: {{", self.request_id, self.keep_connection, self.stdin, self.data)?;
+
+		for (param_index, (param_key, param_value)) in self.params.iter().enumerate() {
+			let delimiter = if param_index > 0 { ", " } else { "" };
+
+			if let Ok(str_value) = std::str::from_utf8(param_value) {
+				write!(f, "{}{}: \"{}\"", delimiter, param_key, str_value)?;
+			} else {
+				write!(f, "{}{}: {:?}", delimiter, param_key, param_value)?;
+			}
+		}
+
+		writeln!(f, "}}")
+	}
+}
+
+/// Writer of a connection that is shared by all of its requests.
+#[derive(Debug)]
+struct SharedWriter<W: AsyncWrite> {
+	stream: Mutex<W>,
+	/// Set while a record is written. If it is still set when the next record
+	/// is started, the previous record was not completed.
+	in_record: AtomicBool
+}
+
+impl <W: AsyncWrite> SharedWriter<W> {
+	fn new(stream: W) -> Self {
+		Self {
+			stream: Mutex::from(stream),
+			in_record: AtomicBool::new(false)
+		}
+	}
+}
+
+/// Sends output records to the web-server.
+#[derive(Debug)]
+struct OutRecordWriter<W: AsyncWrite> {
+	inner_stream: Arc<SharedWriter<W>>,
+	request_id: RequestId,
+	finished: AtomicBool,
+	flushing: AtomicBool,
+	stdout_started: AtomicBool,
+	pending_status: AtomicU16,
+	has_output: AtomicBool,
+	variables: std::sync::Mutex<Vec<(String, String)>>,
+	line_ending: LineEnding,
+	content_length_check: Option<std::sync::Mutex<ContentLengthCheck>>,
+	header_counter: Option<std::sync::Mutex<HeaderCounter>>,
+	content_type_check: Option<std::sync::Mutex<ContentTypeCheck>>,
+	status_check: std::sync::Mutex<StatusCheck>,
+	bytes_out: AtomicU64,
+	stdout_closed: AtomicBool,
+	stderr_closed: AtomicBool,
+	stdout_unsent: std::sync::Mutex<Vec<u8>>,
+	stderr_unsent: std::sync::Mutex<Vec<u8>>
+}
+
+impl <W: AsyncWrite + Unpin> OutRecordWriter<W> {
+	fn new(inner_stream: Arc<SharedWriter<W>>, request_id: RequestId) -> Self {
+		Self {
+			inner_stream,
+			request_id,
+			finished: AtomicBool::new(false),
+			flushing: AtomicBool::new(false),
+			stdout_started: AtomicBool::new(false),
+			pending_status: AtomicU16::new(0),
+			has_output: AtomicBool::new(false),
+			variables: std::sync::Mutex::new(Vec::new()),
+			line_ending: LineEnding::default(),
+			content_length_check: None,
+			header_counter: None,
+			content_type_check: None,
+			status_check: std::sync::Mutex::new(StatusCheck::default()),
+			bytes_out: AtomicU64::new(0),
+			stdout_closed: AtomicBool::new(false),
+			stderr_closed: AtomicBool::new(false),
+			stdout_unsent: std::sync::Mutex::new(Vec::new()),
+			stderr_unsent: std::sync::Mutex::new(Vec::new())
+		}
+	}
+
+	/// Applies the response settings of the connection to this request.
+	fn with_config(mut self, config: ResponseConfig) -> Self {
+		self.line_ending = config.line_ending;
+		self.content_length_check = config.check_content_length.then(|| std::sync::Mutex::new(ContentLengthCheck::default()));
+		self.header_counter = config.max_response_headers.map(|max_headers| std::sync::Mutex::new(HeaderCounter::new(max_headers)));
+		self.content_type_check = config.default_content_type.map(|content_type| std::sync::Mutex::new(ContentTypeCheck::new(content_type)));
+		self
+	}
+
+	/// Inserts the default `Content-Type` header into data written to `StdOut`
+	/// if the headers end without one and a default was configured.
+	fn default_content_type<'a>(&self, data: Cow<'a, [u8]>) -> Cow<'a, [u8]> {
+		match &self.content_type_check {
+			Some(check) => check.lock().unwrap().apply(data, self.line_ending),
+			None => data
+		}
+	}
+
+	/// Counts the headers within data written to `StdOut`. Fails if the
+	/// maximum number of headers is exceeded.
+	fn count_headers(&self, data: &[u8]) -> Result<(), Error> {
+		match &self.header_counter {
+			Some(counter) if !counter.lock().unwrap().feed(data) => {
+				warn!("FastCGI: Response of request {} exceeds the maximum number of headers.", self.request_id);
+				Err(Error::TooManyResponseHeaders)
+			},
+			_ => Ok(())
+		}
+	}
+
+	/// Inspects data written to `StdOut` for the `Content-Length` check.
+	fn check_stdout(&self, data: &[u8]) {
+		if let Some(check) = &self.content_length_check {
+			check.lock().unwrap().feed(data);
+		}
+	}
+
+	/// Returns the declared and the written length of the body if the
+	/// `Content-Length` check is enabled and they differ.
+	fn content_length_mismatch(&self) -> Option<(u64, u64)> {
+		self.content_length_check.as_ref().and_then(|check| check.lock().unwrap().mismatch())
+	}
+
+	/// Stores the HTTP status that is sent with the first write to `StdOut`.
+	/// Returns `false` if something was already written to `StdOut`.
+	fn set_status(&self, status: u16) -> bool {
+		if self.stdout_started.load(Ordering::Acquire) {
+			false
+		} else {
+			self.pending_status.store(status, Ordering::Release);
+			true
+		}
+	}
+
+	/// Stores a variable that is sent as `Variable-` header with the first
+	/// write to `StdOut`. Returns `false` if something was already written to
+	/// `StdOut`.
+	fn add_variable(&self, name: &str, value: &str) -> bool {
+		let mut variables = self.variables.lock().unwrap();
+		if self.stdout_started.load(Ordering::Acquire) {
+			false
+		} else {
+			variables.push((name.to_string(), value.to_string()));
+			true
+		}
+	}
+
+	/// Marks `StdOut` as started and returns the header lines that were not
+	/// sent yet: the `Status` header and the `Variable-` headers. `status`
+	/// replaces the status set via [`set_status`](OutRecordWriter::set_status).
+	/// Returns `None` if `StdOut` was already started and an empty string if
+	/// there is nothing to send.
+	fn start_stdout(&self, status: Option<u16>) -> Option<String> {
+		let mut variables = self.variables.lock().unwrap();
+
+		// Data of a dropped stream that was not sent yet already started StdOut.
+		if !self.unsent(Category::Std(StdRespType::StdOut)).lock().unwrap().is_empty() || self.stdout_started.swap(true, Ordering::AcqRel) {
+			return None;
+		}
+
+		let line_ending = self.line_ending.as_str();
+		let pending_status = Some(self.pending_status.swap(0, Ordering::AcqRel)).filter(|status| *status != 0);
+
+		let mut head = status.or(pending_status).map(|status| response::status_line(status, line_ending)).unwrap_or_default();
+		for (name, value) in variables.drain(..) {
+			head.push_str(&format!("Variable-{}: {}{}", name, value, line_ending));
+		}
+
+		Some(head)
+	}
+
+	/// Checks if data was written to `StdOut` or `StdErr`.
+	fn has_output(&self) -> bool {
+		self.has_output.load(Ordering::Acquire)
+	}
+
+	/// Marks the request as finished. Returns `true` if the request was already
+	/// finished before.
+	fn finish(&self) -> bool {
+		self.finished.swap(true, Ordering::AcqRel)
+	}
+
+	/// Checks if an `EndRequest` record was already sent for this request.
+	fn is_finished(&self) -> bool {
+		self.finished.load(Ordering::Acquire)
+	}
+
+	/// Returns the phase of a request that is processed: [`Phase::Complete`]
+	/// if it was ended, [`Phase::Flushing`] while it is ended and
+	/// [`Phase::Processing`] otherwise.
+	fn phase(&self) -> Phase {
+		if self.is_finished() {
+			Phase::Complete
+		} else if self.flushing.load(Ordering::Acquire) {
+			Phase::Flushing
+		} else {
+			Phase::Processing
+		}
+	}
+
+	/// Returns the flag that is set as soon as the stream `record_type` was
+	/// terminated.
+	fn closed_flag(&self, record_type: ResponseType) -> &AtomicBool {
+		match record_type {
+			Category::Std(StdRespType::StdErr) => &self.stderr_closed,
+			_ => &self.stdout_closed
+		}
+	}
+
+	/// Returns the data of the stream `record_type` that was collected by a
+	/// dropped [`OutStream`] but not sent yet.
+	fn unsent(&self, record_type: ResponseType) -> &std::sync::Mutex<Vec<u8>> {
+		match record_type {
+			Category::Std(StdRespType::StdErr) => &self.stderr_unsent,
+			_ => &self.stdout_unsent
+		}
+	}
+
+	/// Sends the data that was collected by a dropped [`OutStream`] for the
+	/// stream `record_type`.
+	async fn send_unsent(&self, record_type: ResponseType) -> std::result::Result<(), Error> {
+		let data = std::mem::take(&mut *self.unsent(record_type).lock().unwrap());
+		if !data.is_empty() {
+			self.send_stream(record_type, &data).await?;
+		}
+
+		Ok(())
+	}
+
+	/// Sends `data` via the stream `record_type`.
+	///
+	/// If the stream was already closed or the request was ended,
+	/// [`StreamAlreadyClosed`](Error::StreamAlreadyClosed) is returned.
+	async fn write_stream(&self, record_type: ResponseType, data: &[u8]) -> std::result::Result<usize, Error> {
+		if self.closed_flag(record_type).load(Ordering::Acquire) || self.is_finished() {
+			return Err(Error::StreamAlreadyClosed);
+		}
+
+		// The data of a dropped stream precedes the data written now.
+		self.send_unsent(record_type).await?;
+
+		if data.is_empty() {
+			return Ok(0);
+		}
+
+		self.send_stream(record_type, data).await
+	}
 
-							Write::write_all(&mut output, name.as_bytes())?;
-							Write::write_all(&mut output, result_str.as_bytes())?;
-						}
-					}
+	/// Sends `data` via the stream `record_type` without checking if the
+	/// stream was closed.
+	async fn send_stream(&self, record_type: ResponseType, data: &[u8]) -> std::result::Result<usize, Error> {
+		if matches!(record_type, Category::Std(StdRespType::StdOut)) {
+			let mut output = Cow::Borrowed(data);
 
-					output_stream.write_data(Category::Sys(SysRespType::GetValuesResult), &output[..]).await?;
+			// The status and the variables set via Request precede the first data written to StdOut.
+			if let Some(head) = self.start_stdout(None).filter(|head| !head.is_empty()) {
+				output = Cow::Owned([head.as_bytes(), data].concat());
+			}
 
-					Ok(None)
-				},
-				SysReqType::AbortRequest => {
-					output_stream.write_finish(RequestResult::Complete(0)).await?;
+			let output = self.default_content_type(output);
+			if output.is_empty() {
+				return Ok(data.len());
+			}
 
-					Ok(Some(record.get_request_id()))
-				}
-			};
+			self.count_headers(&output)?;
+			self.check_stdout(&output);
+			self.write_records(record_type, &output).await?;
 
-			output_stream.flush().await?;
+			return Ok(data.len());
+		}
 
-			result
+		self.write_records(record_type, data).await
+	}
+
+	/// Sends `data` as one or more records of type `record_type`.
+	async fn write_records(&self, record_type: ResponseType, data: &[u8]) -> std::result::Result<usize, Error> {
+		if !data.is_empty() {
+			self.has_output.store(true, Ordering::Release);
+		}
+
+		// Check if the data can be transmitted in one chunk.
+		// If not, split the data in chunks of u16 - 1 size.
+		if data.len() < u16::MAX as usize {
+			Ok(self.write_data(record_type, data).await?)
 		} else {
-			panic!("process_sys called with non sys record.");
+			// Transmit large streams in junks of 64k
+			const JUNK_SIZE: usize = (u16::MAX - 1) as usize;
+			for offset in (0..data.len()).step_by(JUNK_SIZE) {
+				self.write_data(record_type, &data[offset..(offset + JUNK_SIZE).min(data.len())]).await?;
+			}
+
+			Ok(data.len())
 		}
 	}
 
-	/// Fetches the next request from this connection
-	///
-	/// This function asynchronously fetches FastCGI records and assembles them
-	/// into requests. It does the de-interlacing to allow multiple requests to
-	/// be processed in parallel. As soon as the information for a request is
-	/// complete, it returns a [`Request`] instance for further processing.
+	/// Terminates the stream `record_type` by sending an empty record.
 	///
-	/// This function will do the book keeping and process system requests like
-	/// `FCGI_GET_VALUES` or `FCGI_ABORT_REQUEST`.
-	pub async fn next(&mut self) -> Result<Option<Request<W>>, Error> {
-		if self.close_on_next {
-			if !self.requests.is_empty() {
-				warn!("FastCGI: The web-server interleaved requests on this connection but did not use the FCGI_KEEP_CONN flag. {} requests will get lost.", self.requests.len());
-			}
+	/// Closing a stream that is already closed does nothing. If the request
+	/// was ended, [`StreamAlreadyClosed`](Error::StreamAlreadyClosed) is
+	/// returned.
+	async fn close_stream(&self, record_type: ResponseType) -> std::result::Result<(), Error> {
+		if self.is_finished() {
+			return Err(Error::StreamAlreadyClosed);
+		}
 
-			// Signal to the user that this connection should be closed.
-			Ok(None)
-		} else {
-			loop
-			{
-				match Record::new(&mut self.reader).await {
-					// Success, a new record hast to be added to its request...
-					Ok(record) => {
-						if record.is_sys_record() {
-							if let Some(canceled_request_id) = self.process_sys(record).await? {
-								// The request got canceled. Remove it from the list
-								self.requests.remove(&canceled_request_id);
-							}
-						} else {
-							let request_ready = match self.requests.entry(record.get_request_id()) {
-								Entry::Occupied(mut e) => { e.get_mut().update(&record) },
-								Entry::Vacant(e) => { e.insert(Request::new(&record, self.writer.clone())?); Ok(false) }
-							}?;
+		self.terminate_stream(record_type).await
+	}
 
-							if request_ready {
-								let request = self.requests.remove(&record.get_request_id()).unwrap();
+	/// Terminates the stream `record_type` like [`close_stream`](Self::close_stream),
+	/// but also after the request was marked as finished.
+	async fn terminate_stream(&self, record_type: ResponseType) -> std::result::Result<(), Error> {
+		if self.closed_flag(record_type).load(Ordering::Acquire) {
+			return Ok(());
+		}
 
-								// Store if we should close the connection after handling this request.
-								self.close_on_next = !request.keep_connection;
+		self.send_unsent(record_type).await?;
 
-								// Calling unwrap here is ok because we made sure there is an object for this id.
-								return Ok(Some(request));
-							}
-						}
-					},
-					// IoError UnexpectedEof: May be ok or an error. Depends on if requests have been processed.
-					Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
-						// An I/O-error signals the end of the stream. On record construction this is ok as long
-						// as there are no other requests in flight.
-						if self.requests.is_empty() {
-							return Ok(None)
-						} else {
-							return Err(Error::from(err));
-						}
-					},
-					// UnknownRecordType must be transmitted back to the server. This is not a fatal error.
-					Err(Error::UnknownRecordType(request_id, type_id)) => {
-						let output_stream = OutRecordWriter::new(self.writer.clone(), request_id);
-						output_stream.write_unkown_type(type_id).await?;
-					},
-					// An error occurred, exit the loop and return it...
-					Err(err) => {
-						return Err(err);
-					}
-				}
-			}
+		// The characters held back by the Content-Type check are sent before the stream ends.
+		let held_back = match (&self.content_type_check, record_type) {
+			(Some(check), Category::Std(StdRespType::StdOut)) => check.lock().unwrap().take_held_back(),
+			_ => 0
+		};
+		if held_back > 0 {
+			let output = vec![b'\r'; held_back];
+			self.count_headers(&output)?;
+			self.check_stdout(&output);
+			self.write_records(record_type, &output).await?;
 		}
-	}
-}
 
-// Generate nicer debug output for Request. This is useful if you look at the request
-// from within the `process` function.
-impl <W: AsyncWrite + Unpin> Debug for Request<W> {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-		write!(f, "Request {{ request_id: {}, keep_connection: {:?}, stdin: {:?}, data: {:?}, params: {{", self.request_id, self.keep_connection, self.stdin, self.data)?;
+		// Send an empty record to close the stream.
+		self.write_data(record_type, &[0u8; 0]).await?;
 
-		for (param_index, param_key) in self.params.keys().enumerate() {
-			let delimiter = if param_index > 0 { ", " } else { "" };
+		self.flush().await?;
 
-			if let Some(str_value) = self.get_str_param(param_key) {
-				write!(f, "{}{}: \"{}\"", delimiter, param_key, str_value)?;
-			} else {
-				write!(f, "{}{}: {:?}", delimiter, param_key, self.get_param(param_key))?;
-			}
-		}
+		// Now mark this stream as closed. Do not do it any earlier, because
+		// the empty record must pass the check for closed streams.
+		self.closed_flag(record_type).store(true, Ordering::Release);
 
-		writeln!(f, "}}")
+		Ok(())
 	}
-}
 
-/// Sends output records to the web-server.
-#[derive(Debug)]
-struct OutRecordWriter<W: AsyncWrite> {
-	inner_stream: Arc<Mutex<W>>,
-	request_id: RequestId,
-}
+	async fn write_data(&self, record_type: ResponseType, data: &[u8]) -> std::result::Result<usize, Error> {
+		trace!("FastCGI: Out record {{T:{:?}, ID: {}, L:{}}}", record_type, self.request_id, RECORD_HEADER_SIZE + data.len());
 
-impl <W: AsyncWrite + Unpin> OutRecordWriter<W> {
-	fn new(inner_stream: Arc<Mutex<W>>, request_id: RequestId) -> Self {
-		Self {
-			inner_stream,
-			request_id
+		if !matches!(record_type, Category::Std(StdRespType::StdOut)) {
+			return self.write_record(record_type.into(), data).await;
 		}
+
+		self.status_check.lock().unwrap().feed(data);
+		let written = self.write_record(record_type.into(), data).await?;
+		self.bytes_out.fetch_add(data.len() as u64, Ordering::AcqRel);
+
+		Ok(written)
 	}
 
-	async fn write_data(&self, record_type: ResponseType, data: &[u8]) -> std::result::Result<usize, std::io::Error> {
-		trace!("FastCGI: Out record {{T:{:?}, ID: {}, L:{}}}", record_type, self.request_id, RECORD_HEADER_SIZE + data.len());
+	/// Returns the HTTP status sent via the `Status` header of `StdOut`.
+	fn status(&self) -> Option<u16> {
+		self.status_check.lock().unwrap().status()
+	}
 
+	/// Frames `data` as one record of type `record_type` and sends it. The
+	/// length of `data` must fit into an u16.
+	async fn write_record(&self, record_type: u8, data: &[u8]) -> std::result::Result<usize, Error> {
 		// Construct the header
 		// We use unwrap here because we're writing to a vec. This must never fail.
 		let mut message_header = Vec::with_capacity(8);
 		byteorder::WriteBytesExt::write_u8(&mut message_header, 1).unwrap();                                // Version
-		byteorder::WriteBytesExt::write_u8(&mut message_header, record_type.into()).unwrap();               // Record Type
+		byteorder::WriteBytesExt::write_u8(&mut message_header, record_type).unwrap();                      // Record Type
 		byteorder::WriteBytesExt::write_u16::<BigEndian>(&mut message_header, self.request_id).unwrap();    // Request ID
 		byteorder::WriteBytesExt::write_u16::<BigEndian>(&mut message_header, data.len() as u16).unwrap();  // Content length
 		byteorder::WriteBytesExt::write_u8(&mut message_header, 0).unwrap();                                // No padding.
 		byteorder::WriteBytesExt::write_u8(&mut message_header, 0).unwrap();                                // Reserved
 
 		// Aquire the mutext guard to prevent the header and the payload to pe torn apart.
-		let mut is = self.inner_stream.try_lock().expect(ERR_LOCK_FAILED);
+		// Requests processed in parallel share the same writer. Waiting for the lock
+		// serializes their records at the record boundary.
+		let mut is = self.inner_stream.stream.lock().await;
+
+		// If the previous record was torn apart, the web-server would interpret the
+		// header of this record as content of the previous one.
+		if self.inner_stream.in_record.swap(true, Ordering::AcqRel) {
+			warn!("FastCGI: Record of request {} not sent. A previous record was only partially written.", self.request_id);
+			return Err(Error::WriterDesync);
+		}
 
 		// Write the messge header
 		is.write_all_buf(&mut Cursor::new(message_header)).await?;
@@ -1226,22 +4026,30 @@ impl <W: AsyncWrite + Unpin> OutRecordWriter<W> {
 		// Writing empty data blocks breaks tokio-test. Therefore we only call write if the data-buffer is not empty.
 		if !data.is_empty() {
 			is.write_all_buf(&mut Cursor::new(data)).await?;
-			Ok(data.len())
-		} else {
-			Ok(0)
 		}
 
 		// Write no padding
+
+		// The record is complete. If this point is not reached, the flag stays set.
+		self.inner_stream.in_record.store(false, Ordering::Release);
+
+		Ok(data.len())
 	}
 
 	/// Sends an `EndRequest` response to the web-server and ends the current
 	/// request.
-	async fn write_finish(&self, result: RequestResult) -> Result<(), std::io::Error> {
+	async fn write_finish(&self, result: RequestResult) -> Result<(), Error> {
+		self.write_end_request(result.app_status(), result.into()).await
+	}
+
+	/// Sends an `EndRequest` record with the passed application and protocol
+	/// status.
+	async fn write_end_request(&self, app_status: u32, protocol_status: u8) -> Result<(), Error> {
 		let mut end_message = Vec::with_capacity(8);
 
 		// Unwrap is safe here because we're writing to an in memory buffer. This must never fail.
-		byteorder::WriteBytesExt::write_u32::<BigEndian>(&mut end_message, result.app_status()).unwrap();
-		byteorder::WriteBytesExt::write_u8(&mut end_message, result.into()).unwrap();
+		byteorder::WriteBytesExt::write_u32::<BigEndian>(&mut end_message, app_status).unwrap();
+		byteorder::WriteBytesExt::write_u8(&mut end_message, protocol_status).unwrap();
 		// Write 3 reserved bytes
 		std::io::Write::write_all(&mut end_message, &[0u8; 3]).unwrap();
 
@@ -1251,7 +4059,7 @@ impl <W: AsyncWrite + Unpin> OutRecordWriter<W> {
 	}
 
 	/// Sends an `UnknownType` response to the web-server.
-	async fn write_unkown_type(&self, type_id: u8) -> Result<(), std::io::Error> {
+	async fn write_unkown_type(&self, type_id: u8) -> Result<(), Error> {
 		let mut ut_message = Vec::with_capacity(8);
 
 		// Unwrap is safe here because we're writing to an in memory buffer. This must never fail.
@@ -1265,19 +4073,48 @@ impl <W: AsyncWrite + Unpin> OutRecordWriter<W> {
 	}
 
 	async fn flush(&self) -> std::result::Result<(), std::io::Error> {
-		self.inner_stream.try_lock().expect(ERR_LOCK_FAILED).flush().await
+		self.inner_stream.stream.lock().await.flush().await
 	}
 }
 
+/// Kind of the operation started by the [`AsyncWrite`] implementation of
+/// [`OutStream`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PendingKind {
+	Write,
+	Flush,
+	Shutdown
+}
+
+/// Future of the operation started by the [`AsyncWrite`] implementation of
+/// [`OutStream`]. The wrapper keeps [`OutStream`] `Sync`. The future is only
+/// accessed via `&mut`.
+type PendingFuture = sync_wrapper::SyncWrapper<std::pin::Pin<Box<dyn Future<Output = std::io::Result<()>> + Send>>>;
+
 /// Implements a data stream from the FastCGI application to the web-server.
 ///
 /// The maximum chunk size is 64k. The calls made by this
 /// interface may block if the web-server is not receiving the data fast enough.
 /// Therefore all calls are implemented as async functions.
+///
+/// If the writer of the connection is `Send` and `'static` (like the halves of
+/// a split TCP or Unix stream), the stream implements [`AsyncWrite`]. It can
+/// be passed to everything that expects a writer, like [`tokio::io::copy`].
+/// The data written this way is collected and sent when the stream is flushed
+/// or shut down, or when 16k were collected. Data that was not flushed when
+/// the stream is dropped is sent with the next output of the stream or when
+/// the request is finished. Call `shutdown` to terminate the stream before the
+/// request is finished.
+///
+/// Borrowed halves of a socket, like the ones returned by `TcpStream::split`,
+/// are not `'static`. With them the stream does not implement [`AsyncWrite`].
+/// Use `into_split` to get owned halves instead.
 pub struct OutStream<W: AsyncWrite + Unpin> {
 	orw: Arc<OutRecordWriter<W>>,
 	record_type: ResponseType,
-	closed: bool
+	/// Data accepted by `poll_write` that was not sent yet.
+	buffer: Vec<u8>,
+	pending: Option<(PendingKind, PendingFuture)>
 }
 
 impl <W: AsyncWrite + Unpin> OutStream<W> {
@@ -1285,7 +4122,8 @@ impl <W: AsyncWrite + Unpin> OutStream<W> {
 		Self {
 			orw,
 			record_type,
-			closed: false
+			buffer: Vec::new(),
+			pending: None
 		}
 	}
 
@@ -1293,31 +4131,57 @@ impl <W: AsyncWrite + Unpin> OutStream<W> {
 	///
 	/// If the data is bigger than 64k the transfer is automatically split into
 	/// chunks of 64k.
-	/// If the stream is already closed, the function will always return
+	/// If the stream is already closed or the request was ended via
+	/// [`Request::abort`], the function will always return
 	/// [`StreamAlreadyClosed`](Error::StreamAlreadyClosed).
+	///
+	/// The data is passed to the underlying writer immediately but it may be
+	/// buffered there. Call [`flush`](OutStream::flush) to make sure it is sent
+	/// to the web-server.
+	///
+	/// Writing an empty slice sends nothing. FastCGI uses an empty record to
+	/// terminate a stream. The streams are terminated when the request is
+	/// finished.
+	///
+	/// Data written via [`AsyncWrite`] must be flushed before this function is
+	/// called. Otherwise it is sent after the data passed here.
 	pub async fn write(&mut self, data: &[u8]) -> std::result::Result<usize, Error> {
-		if self.closed {
-			return Err(Error::StreamAlreadyClosed);
-		}
-
-		// Check if the data can be transmitted in one chunk.
-		// If not, split the data in chunks of u16 - 1 size.
-		if data.len() < u16::max_value() as usize {
-			Ok(self.orw.write_data(self.record_type, data).await?)
-		} else {
-			// Transmit large streams in junks of 64k
-			const JUNK_SIZE: usize = (u16::max_value() - 1) as usize;
-			for offset in (0..data.len()).step_by(JUNK_SIZE) {
-				self.orw.write_data(self.record_type, &data[offset..(offset + JUNK_SIZE).min(data.len())]).await?;
-			}
-
-			Ok(data.len())
-		}
+		self.orw.write_stream(self.record_type, data).await
 	}
 
 	/// Flushes the data to the web-server immediately.
 	///
-	/// This function also calls flush on the underlying stream.
+	/// This function also calls flush on the underlying stream. All data
+	/// written before calling `flush` is handed to the web-server before the
+	/// processor returns. This allows responses to be streamed to the client
+	/// while they are still being generated, like for server-sent events.
+	/// Whether the web-server passes the data on to the client right away
+	/// depends on its configuration (for example `fastcgi_buffering off` for
+	/// nginx or `flushpackets=on` for Apache).
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   let mut stdout = request.get_stdout();
+	///
+	///   stdout.write(b"Content-Type: text/event-stream\r\n\r\n").await.unwrap();
+	///   for event in 0..3 {
+	///     stdout.write(format!("data: {}\n\n", event).as_bytes()).await.unwrap();
+	///     // Deliver the event to the web-server before producing the next one.
+	///     stdout.flush().await.unwrap();
+	///   }
+	///
+	///   RequestResult::Complete(0)
+	/// });
+	/// # } }
+	/// ```
 	pub async fn flush(&self) -> std::result::Result<(), std::io::Error> {
 		self.orw.flush().await
 	}
@@ -1326,18 +4190,118 @@ impl <W: AsyncWrite + Unpin> OutStream<W> {
 	///
 	/// FastCGI closes a stream by sending an empty packet. After calling this
 	/// method, further calls to [`write`] will fail with
-	/// [`StreamAlreadyClosed`](Error::StreamAlreadyClosed).
-	async fn close(&mut self) -> Result<(), Error>{
-		// Send an empty record to close the stream.
-		self.write(&[0u8; 0]).await?;
+	/// [`StreamAlreadyClosed`](Error::StreamAlreadyClosed). Closing a stream
+	/// that was already closed via `shutdown` does nothing.
+	async fn close(&mut self) -> Result<(), Error> {
+		self.orw.close_stream(self.record_type).await
+	}
 
-		self.flush().await?;
+	/// Polls the operation started by the [`AsyncWrite`] implementation.
+	/// Returns its kind as soon as it is done or `None` if no operation is
+	/// pending.
+	fn poll_pending(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<Option<PendingKind>>> {
+		match &mut self.pending {
+			Some((kind, future)) => {
+				let kind = *kind;
+				let result = std::task::ready!(future.get_mut().as_mut().poll(cx));
+				self.pending = None;
+
+				std::task::Poll::Ready(result.map(|_| Some(kind)))
+			},
+			None => std::task::Poll::Ready(Ok(None))
+		}
+	}
+}
 
-		// Now mark this stream as closed. Do not do it any earlier, because
-		// we need to call write on the stream to close it.
-		self.closed = true;
+impl <W: AsyncWrite + Unpin + Send + 'static> OutStream<W> {
+	/// Sends the collected data. An interrupted operation is finished first.
+	/// Returns the kind of the last operation that was done.
+	fn poll_send(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<Option<PendingKind>>> {
+		loop {
+			let done_kind = std::task::ready!(self.poll_pending(cx))?;
+			if self.buffer.is_empty() {
+				return std::task::Poll::Ready(Ok(done_kind));
+			}
 
-		Ok(())
+			let data = std::mem::take(&mut self.buffer);
+			let (orw, record_type) = (self.orw.clone(), self.record_type);
+			self.pending = Some((PendingKind::Write, sync_wrapper::SyncWrapper::new(Box::pin(async move {
+				orw.write_stream(record_type, &data).await?;
+				Ok(())
+			}))));
+		}
+	}
+
+	/// Sends the collected data and polls the operation started via `start`
+	/// until it is done. An interrupted operation of the same kind is resumed.
+	fn poll_operation<F, Fut>(&mut self, cx: &mut std::task::Context<'_>, kind: PendingKind, start: F) -> std::task::Poll<std::io::Result<()>>
+	where
+		F: FnOnce(Arc<OutRecordWriter<W>>, ResponseType) -> Fut,
+		Fut: Future<Output = std::io::Result<()>> + Send + 'static
+	{
+		if std::task::ready!(self.poll_send(cx))? != Some(kind) {
+			self.pending = Some((kind, sync_wrapper::SyncWrapper::new(Box::pin(start(self.orw.clone(), self.record_type)))));
+			std::task::ready!(self.poll_pending(cx))?;
+		}
+
+		std::task::Poll::Ready(Ok(()))
+	}
+}
+
+impl <W: AsyncWrite + Unpin + Send + 'static> AsyncWrite for OutStream<W> {
+	/// Collects `buf` to be sent as one or more records.
+	///
+	/// All of `buf` is accepted at once. The call only waits if more than 16k
+	/// are collected. Then the collected data is sent before `buf` is
+	/// accepted.
+	fn poll_write(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>, buf: &[u8]) -> std::task::Poll<std::io::Result<usize>> {
+		let this = self.get_mut();
+
+		if this.orw.closed_flag(this.record_type).load(Ordering::Acquire) || this.orw.is_finished() {
+			return std::task::Poll::Ready(Err(Error::StreamAlreadyClosed.into()));
+		}
+
+		if this.buffer.len() >= ASYNC_WRITE_BUFFER_SIZE {
+			std::task::ready!(this.poll_send(cx))?;
+		}
+
+		this.buffer.extend_from_slice(buf);
+		std::task::Poll::Ready(Ok(buf.len()))
+	}
+
+	/// Sends the collected data, hands all records written so far to the
+	/// underlying writer and flushes it.
+	fn poll_flush(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+		self.get_mut().poll_operation(cx, PendingKind::Flush, |orw, _| async move {
+			orw.flush().await
+		})
+	}
+
+	/// Sends the collected data and terminates the stream by sending an empty
+	/// record.
+	///
+	/// Further writes fail. The stream is terminated automatically when the
+	/// request is finished.
+	fn poll_shutdown(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+		self.get_mut().poll_operation(cx, PendingKind::Shutdown, |orw, record_type| async move {
+			orw.close_stream(record_type).await?;
+			orw.flush().await
+		})
+	}
+}
+
+impl <W: AsyncWrite + Unpin> Drop for OutStream<W> {
+	fn drop(&mut self) {
+		if self.buffer.is_empty() {
+			return;
+		}
+
+		// The data is sent with the next output of the stream or when the request is finished.
+		if self.orw.closed_flag(self.record_type).load(Ordering::Acquire) || self.orw.is_finished() {
+			warn!("FastCGI: Request {} was already ended. {} bytes written to {:?} were discarded.", self.orw.request_id, self.buffer.len(), self.record_type);
+		} else {
+			self.orw.unsent(self.record_type).lock().unwrap().append(&mut self.buffer);
+		}
 	}
 }
 
@@ -1348,6 +4312,378 @@ mod tests {
 
 	fn is_send<T: Send>(_: T) { }
 
+	fn is_sync<T: Sync>(_: &T) { }
+
+	#[tokio::test]
+	async fn unit_result() {
+		assert_eq!(RequestResult::default(), RequestResult::Complete(0));
+		assert_eq!(RequestResult::from(()), RequestResult::Complete(0));
+
+		let (request, output) = Request::test_builder().build();
+		request.process(|request| async move {
+			request.get_stdout().write(b"Hello").await.unwrap();
+		}).await.unwrap();
+
+		assert_eq!(output.stdout(), b"Hello");
+		assert_eq!(output.end_request(), Some((0, 0)));
+	}
+
+	#[tokio::test]
+	async fn abort() {
+		let (request, output) = Request::test_builder().default_content_type("text/plain").build();
+		request.process(|request| async move {
+			request.get_stdout().write(b"Status: 500\r\n\r").await.unwrap();
+			request.get_stderr().close().await.unwrap();
+			request.abort(RequestResult::Overloaded).await.unwrap();
+		}).await.unwrap();
+
+		// The held back line ending is sent before StdOut is terminated.
+		assert_eq!(output.stdout(), b"Status: 500\r\n\r");
+
+		// Streams that were already closed are not terminated again.
+		let terminators = |stream_type| output.records().iter().filter(|(record_type, _, content)| *record_type == stream_type && content.is_empty()).count();
+		assert_eq!(terminators(6), 1);
+		assert_eq!(terminators(7), 1);
+		assert_eq!(output.end_request(), Some((0, 2)));
+	}
+
+	#[test]
+	fn take_data() {
+		// The part that was already read is not returned.
+		let (request, _output) = Request::test_builder().role(Role::Filter).data(b"HEADER:BODY").build();
+		let mut header = [0u8; 7];
+		request.get_data().read_exact(&mut header).unwrap();
+		assert_eq!(request.take_data(), Some(b"BODY".to_vec()));
+
+		// Only filters have a Data stream.
+		let (request, _output) = Request::test_builder().data(b"IGNORED").build();
+		assert_eq!(request.take_data(), None);
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn elapsed() {
+		let (request, _output) = Request::test_builder().build();
+		assert_eq!(request.elapsed(), Duration::ZERO);
+
+		tokio::time::sleep(Duration::from_millis(10)).await;
+		assert_eq!(request.elapsed(), Duration::from_millis(10));
+		assert_eq!(request.begin_instant() + request.elapsed(), tokio::time::Instant::now());
+	}
+
+	#[tokio::test]
+	async fn write_raw_record() {
+		let (request, output) = Request::test_builder().request_id(0x0102).build();
+
+		request.write_raw_record(42, b"EXT").await.unwrap();
+		request.write_raw_record(43, &[]).await.unwrap();
+		assert_eq!(output.bytes(), [1, 42, 1, 2, 0, 3, 0, 0, b'E', b'X', b'T', 1, 43, 1, 2, 0, 0, 0, 0]);
+
+		// Content that does not fit into one record is rejected.
+		match request.write_raw_record(42, &vec![0; u16::MAX as usize + 1]).await {
+			Err(Error::IoError(err)) => assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput),
+			other => panic!("Unexpected result: {:?}", other)
+		}
+		assert_eq!(output.bytes().len(), 19);
+	}
+
+	#[test]
+	fn is_secure() {
+		let secure = |name: &str, value: &str| Request::test_builder().param(name, value).build().0.is_secure();
+
+		assert!(secure("HTTPS", "on"));
+		assert!(secure("HTTPS", "ON"));
+		assert!(secure("HTTPS", "1"));
+		assert!(secure("REQUEST_SCHEME", "https"));
+		assert!(secure("SERVER_PORT_SECURE", "1"));
+
+		assert!(!secure("HTTPS", "off"));
+		assert!(!secure("HTTPS", ""));
+		assert!(!secure("REQUEST_SCHEME", "http"));
+		assert!(!secure("HTTP_X_FORWARDED_PROTO", "https"));
+		assert!(!Request::test_builder().build().0.is_secure());
+	}
+
+	#[test]
+	fn raw_uri_query() {
+		let (request, _output) = Request::test_builder()
+			.param("QUERY_STRING", "q=a%20b+c&empty=&x=%2F")
+			.build();
+		assert_eq!(request.raw_uri_query(), Some("q=a%20b+c&empty=&x=%2F"));
+
+		let (request, _output) = Request::test_builder().build();
+		assert_eq!(request.raw_uri_query(), None);
+	}
+
+	#[test]
+	fn fatal_errors() {
+		assert!(Error::from(std::io::Error::from(std::io::ErrorKind::BrokenPipe)).is_fatal());
+		assert!(Error::InvalidRecordVersion.is_fatal());
+		assert!(Error::SequenceError.is_fatal());
+		assert!(Error::StreamAlreadyDone.is_fatal());
+		assert!(Error::InvalidRoleNumber.is_fatal());
+
+		assert!(!Error::StreamAlreadyClosed.is_fatal());
+		assert!(!Error::UnknownRecordType(1, 99).is_fatal());
+	}
+
+	#[test]
+	fn nv_lengths() {
+		// 1-byte lengths
+		assert_eq!(Request::<Vec<u8>>::read_length(&mut &[0x00][..]).unwrap(), 0);
+		assert_eq!(Request::<Vec<u8>>::read_length(&mut &[0x7F][..]).unwrap(), 127);
+
+		// 4-byte lengths. The high bit is not part of the length.
+		assert_eq!(Request::<Vec<u8>>::read_length(&mut &[0x80, 0x00, 0x00, 0x80][..]).unwrap(), 128);
+		assert_eq!(Request::<Vec<u8>>::read_length(&mut &[0x80, 0x00, 0x00, 0xC8][..]).unwrap(), 200);
+		assert_eq!(Request::<Vec<u8>>::read_length(&mut &[0x81, 0x02, 0x03, 0x04][..]).unwrap(), 0x01020304);
+		assert_eq!(Request::<Vec<u8>>::read_length(&mut &[0xFF, 0xFF, 0xFF, 0xFF][..]).unwrap(), 0x7FFFFFFF);
+
+		// Truncated 4-byte length
+		assert!(Request::<Vec<u8>>::read_length(&mut &[0x80, 0x00][..]).is_err());
+	}
+
+	#[test]
+	fn nv_pairs() {
+		// 1-byte name length and 4-byte value length
+		let mut src = b"\x04\x80\x00\x00\xC8NAME".to_vec();
+		src.extend_from_slice(&[b'V'; 200]);
+		// 4-byte name length and 1-byte value length
+		src.extend_from_slice(b"\x80\x00\x00\x82\x02");
+		src.extend_from_slice(&[b'N'; 130]);
+		src.extend_from_slice(b"OK");
+
+		let mut params = HashMap::new();
+		Request::<Vec<u8>>::add_nv_pairs(&mut params, None, &src, &|name| String::from_utf8_lossy(name).into_owned()).unwrap();
+		assert_eq!(params.len(), 2);
+		assert_eq!(params["NAME"], vec![b'V'; 200]);
+		assert_eq!(params[&"N".repeat(130)], b"OK");
+
+		// The declared lengths exceed the available data.
+		let mut params = HashMap::new();
+		assert!(Request::<Vec<u8>>::add_nv_pairs(&mut params, None, b"\xFF\xFF\xFF\xFF\x01NAME", &|name| String::from_utf8_lossy(name).into_owned()).is_err());
+		assert!(Request::<Vec<u8>>::add_nv_pairs(&mut params, None, b"\x04\x05NAMEV", &|name| String::from_utf8_lossy(name).into_owned()).is_err());
+	}
+
+	#[tokio::test]
+	async fn async_write() {
+		const STDOUT: u8 = StdRespType::StdOut as u8;
+		const STDERR: u8 = StdRespType::StdErr as u8;
+		const END: u8 = StdRespType::EndRequest as u8;
+
+		let body: Vec<u8> = (0..600_000u32).map(|index| (index % 251) as u8).collect();
+
+		// A single write bigger than a record is split into several records.
+		let (request, captured) = Request::test_builder().build();
+		let written = body.clone();
+		request.process(|request| async move {
+			let mut stdout = request.get_stdout();
+			assert_eq!(AsyncWriteExt::write(&mut stdout, &written[..150_000]).await.unwrap(), 150_000);
+			AsyncWriteExt::flush(&mut stdout).await.unwrap();
+		}).await.unwrap();
+
+		let records = captured.records();
+		assert_eq!(records.iter().map(|(record_type, _, content)| (*record_type, content.len())).collect::<Vec<_>>(), vec![
+			(STDOUT, 65534), (STDOUT, 65534), (STDOUT, 18932), (STDOUT, 0), (STDERR, 0), (END, 8)
+		]);
+		assert_eq!(captured.stdout(), &body[..150_000]);
+
+		// The stream can be used with tokio::io::copy.
+		let (request, captured) = Request::test_builder().build();
+		let source = body.clone();
+		request.process(|request| async move {
+			let mut stdout = request.get_stdout();
+			assert_eq!(tokio::io::copy(&mut &source[..], &mut stdout).await.unwrap(), 600_000);
+		}).await.unwrap();
+
+		assert!(captured.records().iter().all(|(_, _, content)| content.len() < u16::MAX as usize));
+		assert_eq!(captured.stdout(), body);
+		assert_eq!(captured.end_request(), Some((0, 0)));
+
+		// Small writes are accepted right away and sent as one record on flush.
+		let (request, captured) = Request::test_builder().build();
+		let collected = captured.clone();
+		request.process(|request| async move {
+			let mut stdout = request.get_stdout();
+			is_sync(&stdout);
+			for _ in 0..3 {
+				assert_eq!(tokio_test::assert_ready!(tokio_test::task::spawn(AsyncWriteExt::write(&mut stdout, b"Data")).poll()).unwrap(), 4);
+			}
+			assert!(collected.records().is_empty());
+
+			AsyncWriteExt::flush(&mut stdout).await.unwrap();
+			// A flush without new data sends nothing.
+			AsyncWriteExt::flush(&mut stdout).await.unwrap();
+			stdout.write_all(b"More").await.unwrap();
+			AsyncWriteExt::flush(&mut stdout).await.unwrap();
+		}).await.unwrap();
+
+		assert_eq!(captured.records().into_iter().map(|(_, _, content)| content).collect::<Vec<_>>(), vec![
+			b"DataDataData".to_vec(), b"More".to_vec(), Vec::new(), Vec::new(), vec![0, 0, 0, 0, 0, 0, 0, 0]
+		]);
+
+		// Shutting the stream down terminates it right away.
+		let (request, captured) = Request::test_builder().build();
+		request.process(|request| async move {
+			let mut stderr = request.get_stderr();
+			stderr.write_all(b"Warning").await.unwrap();
+			stderr.shutdown().await.unwrap();
+			assert!(matches!(stderr.write(b"Late").await, Err(Error::StreamAlreadyClosed)));
+
+			request.get_stdout().write(b"Body").await.unwrap();
+		}).await.unwrap();
+
+		let records: Vec<(u8, Vec<u8>)> = captured.records().into_iter().map(|(record_type, _, content)| (record_type, content)).collect();
+		assert_eq!(records, vec![
+			(STDERR, b"Warning".to_vec()),
+			(STDERR, Vec::new()),
+			(STDOUT, b"Body".to_vec()),
+			(STDOUT, Vec::new()),
+			(END, vec![0, 0, 0, 0, 0, 0, 0, 0])
+		]);
+
+		// Data of a stream dropped without flushing it precedes the next output or is sent when the request is finished.
+		let (request, captured) = Request::test_builder().build();
+		request.process(|request| async move {
+			let mut stdout = request.get_stdout();
+			stdout.write_all(b"Unflushed ").await.unwrap();
+			drop(stdout);
+			request.get_stdout().write(b"Body").await.unwrap();
+
+			request.get_stderr().write_all(b"Warning").await.unwrap();
+		}).await.unwrap();
+
+		let records: Vec<(u8, Vec<u8>)> = captured.records().into_iter().map(|(record_type, _, content)| (record_type, content)).collect();
+		assert_eq!(records, vec![
+			(STDOUT, b"Unflushed ".to_vec()),
+			(STDOUT, b"Body".to_vec()),
+			(STDERR, b"Warning".to_vec()),
+			(STDOUT, Vec::new()),
+			(STDERR, Vec::new()),
+			(END, vec![0, 0, 0, 0, 0, 0, 0, 0])
+		]);
+	}
+
+	#[tokio::test]
+	async fn wait_stdin_complete_while_reading() {
+		let (request, _output) = Request::test_builder().stdin(b"Body").build();
+
+		// Waiting must not lock the stream the caller is already reading.
+		let mut stdin = request.get_stdin();
+		request.wait_stdin_complete().await;
+
+		let mut body = Vec::new();
+		stdin.read_to_end(&mut body).unwrap();
+		assert_eq!(body, b"Body");
+	}
+
+	#[tokio::test]
+	async fn output_before_input() {
+		const STDOUT: u8 = StdRespType::StdOut as u8;
+		const STDERR: u8 = StdRespType::StdErr as u8;
+
+		let (request, captured) = Request::test_builder()
+			.request_id(3)
+			.param("PATH_INFO", "/item")
+			.stdin(b"Body")
+			.build();
+
+		// The output streams do not depend on the input streams being read.
+		request.process(|request| async move {
+			request.get_stdout().write(b"Content-Type: text/plain\r\n\r\n").await.unwrap();
+			let path = request.get_str_param("PATH_INFO").unwrap().to_owned();
+			request.get_stderr().write(path.as_bytes()).await.unwrap();
+
+			let mut body = String::new();
+			request.get_stdin().read_to_string(&mut body).unwrap();
+			request.get_stdout().write(format!("{} {}", path, body).as_bytes()).await.unwrap();
+
+			RequestResult::Complete(0)
+		}).await.unwrap();
+
+		let records: Vec<(u8, u16, Vec<u8>)> = captured.records().into_iter().take(3).collect();
+		assert_eq!(records, vec![
+			(STDOUT, 3, b"Content-Type: text/plain\r\n\r\n".to_vec()),
+			(STDERR, 3, b"/item".to_vec()),
+			(STDOUT, 3, b"/item Body".to_vec())
+		]);
+		assert_eq!(captured.end_request(), Some((0, 0)));
+	}
+
+	#[tokio::test]
+	async fn param_name_normalizer() {
+		let record = |record_type: u8, content: &[u8]| {
+			let mut record = vec![1, record_type, 0, 1, 0, content.len() as u8, 0, 0];
+			record.extend_from_slice(content);
+			record
+		};
+
+		let mut input = record(1, &[0, 1, 0, 0, 0, 0, 0, 0]);
+		input.extend(record(4, b"\x0F\x05HTTP_USER_AGENTcurl/\x04\x03PathYES"));
+		input.extend(record(4, &[]));
+		input.extend(record(5, &[]));
+
+		// Strip the HTTP_ prefix and convert everything to upper case.
+		let mut requests = Requests::new(&input[..], tokio::io::sink(), 1, 1)
+			.param_name_normalizer(|name| Cow::Owned(name.strip_prefix(b"HTTP_").unwrap_or(name).to_ascii_uppercase()));
+
+		let request = requests.next().await.unwrap().unwrap();
+		let mut params: Vec<(&str, &[u8])> = request.params_iter().unwrap().collect();
+		params.sort();
+		assert_eq!(params, vec![("PATH", &b"YES"[..]), ("USER_AGENT", &b"curl/"[..])]);
+
+		// Lookups use the normalizer, too.
+		assert_eq!(request.get_str_param("user_agent"), Some("curl/"));
+		assert_eq!(request.get_str_param("HTTP_USER_AGENT"), Some("curl/"));
+		assert_eq!(request.get_str_param("path"), Some("YES"));
+	}
+
+	#[test]
+	fn header() {
+		let (request, _output) = Request::test_builder()
+			.param("HTTP_USER_AGENT", "curl/8.0")
+			.param("HTTP_X_FORWARDED_FOR", "10.0.0.1")
+			.param("CONTENT_TYPE", "text/plain")
+			.param("CONTENT_LENGTH", "5")
+			.param("HTTP_CONTENT_TYPE", "wrong")
+			.build();
+
+		assert_eq!(request.header("User-Agent"), Some(&b"curl/8.0"[..]));
+		assert_eq!(request.header("x-forwarded-for"), Some(&b"10.0.0.1"[..]));
+		assert_eq!(request.header("Content-Type"), Some(&b"text/plain"[..]));
+		assert_eq!(request.header("content-length"), Some(&b"5"[..]));
+		assert_eq!(request.header("Accept"), None);
+	}
+
+	#[test]
+	fn io_error_conversion() {
+		// I/O-errors survive the round trip unchanged.
+		let error = Error::from(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "Reset by peer"));
+		assert!(matches!(&error, Error::IoError(io_error) if io_error.kind() == std::io::ErrorKind::ConnectionReset));
+
+		let io_error = std::io::Error::from(error);
+		assert_eq!(io_error.kind(), std::io::ErrorKind::ConnectionReset);
+		assert_eq!(io_error.to_string(), "Reset by peer");
+
+		// All other errors are wrapped and can be retrieved again.
+		let io_error = std::io::Error::from(Error::StdinTimeout(7));
+		assert_eq!(io_error.kind(), std::io::ErrorKind::TimedOut);
+		assert!(matches!(*io_error.into_inner().unwrap().downcast::<Error>().unwrap(), Error::StdinTimeout(7)));
+
+		assert_eq!(std::io::Error::from(Error::SequenceError).kind(), std::io::ErrorKind::InvalidData);
+		assert_eq!(std::io::Error::from(Error::WriterDesync).kind(), std::io::ErrorKind::BrokenPipe);
+
+		// The question mark operator converts in both directions.
+		fn fastcgi_result() -> Result<(), Error> {
+			Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))?
+		}
+
+		fn io_result() -> std::io::Result<()> {
+			Ok(fastcgi_result()?)
+		}
+
+		assert_eq!(io_result().unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+	}
+
 	/// Verify that the future created by process is Send to allow using it
 	/// with Tokio.
 	#[test]
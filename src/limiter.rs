@@ -0,0 +1,71 @@
+//! Limit for the number of connections that accept requests.
+//!
+//! The [`ConnectionLimiter`] is shared by all connections of a server. Every
+//! [`Requests`](crate::Requests) instance it is passed to occupies one slot
+//! until it is dropped.
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Limits the number of connections that accept new requests.
+///
+/// A clone of the limiter is passed to every connection via
+/// [`Requests::connection_limiter`](crate::Requests::connection_limiter). If
+/// all slots are taken, the first request of a new connection is answered with
+/// [`Overloaded`](crate::RequestResult::Overloaded) and the connection is
+/// closed. This sheds load at the connection level before any request is
+/// processed. A slot is released as soon as the `Requests` instance that
+/// occupies it is dropped.
+///
+/// The limit is not announced to the web-server. The `max_conns` value passed
+/// to [`Requests::new`](crate::Requests::new) is sent to web-servers that ask
+/// for `FCGI_MAX_CONNS`. Pass the same value to both to keep web-servers that
+/// respect `FCGI_MAX_CONNS` from opening connections that get rejected.
+///
+/// ## Example
+///
+/// ```rust
+/// # use tokio::net::TcpListener;
+/// # use tokio_fastcgi::{ConnectionLimiter, Requests, RequestResult};
+/// # #[tokio::main]
+/// # async fn main() {
+/// # let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+/// # let server = async move {
+/// let limiter = ConnectionLimiter::new(100);
+///
+/// while let Ok((stream, _)) = listener.accept().await {
+///   let limiter = limiter.clone();
+///   tokio::spawn(async move {
+///     let mut requests = Requests::from_split_socket(tokio::io::split(stream), 100, 10)
+///       .connection_limiter(&limiter);
+///
+///     // Process the requests
+///   });
+/// }
+/// # }; }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ConnectionLimiter {
+	semaphore: Arc<Semaphore>,
+	max_connections: usize
+}
+
+impl ConnectionLimiter {
+	/// Creates a limiter that allows `max_connections` connections to accept
+	/// requests at the same time.
+	pub fn new(max_connections: usize) -> Self {
+		Self {
+			semaphore: Arc::new(Semaphore::new(max_connections)),
+			max_connections
+		}
+	}
+
+	/// Returns the number of connections that currently occupy a slot.
+	pub fn active_connections(&self) -> usize {
+		self.max_connections - self.semaphore.available_permits()
+	}
+
+	/// Occupies a slot. Returns `None` if all slots are taken.
+	pub(crate) fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+		self.semaphore.clone().try_acquire_owned().ok()
+	}
+}
@@ -0,0 +1,94 @@
+//! Borrowed view of the parameters of a request.
+//!
+//! The [`ParamsView`] bundles the lookup and iteration of the parameters into
+//! one value that can be passed around within the processor.
+use std::collections::HashMap;
+
+use log::warn;
+
+use crate::ParamNameNormalizer;
+
+/// Converts the name of a parameter into the key used to store it.
+///
+/// If no normalizer was configured, the name is converted to lower case.
+pub(crate) fn normalize_param_name(normalizer: Option<&ParamNameNormalizer>, name: &[u8]) -> String {
+	match normalizer {
+		Some(normalizer) => String::from_utf8_lossy(&normalizer(name)).into_owned(),
+		None => String::from_utf8_lossy(name).to_ascii_lowercase()
+	}
+}
+
+/// Borrowed view of the parameters of a request.
+///
+/// Returned by [`Request::params`](crate::Request::params). The names passed
+/// to [`get`](ParamsView::get), [`get_str`](ParamsView::get_str) and
+/// [`contains`](ParamsView::contains) are normalized the same way as the names
+/// of the received parameters. If the parameters of the request were not
+/// received completely, the view is empty.
+#[derive(Clone, Copy)]
+pub struct ParamsView<'a> {
+	params: Option<&'a HashMap<String, Vec<u8>>>,
+	order: &'a [String],
+	normalizer: Option<&'a ParamNameNormalizer>
+}
+
+impl <'a> ParamsView<'a> {
+	/// Creates a view of `params`, which were received in `order`. Pass `None`
+	/// if the parameters were not received completely.
+	pub(crate) fn new(params: Option<&'a HashMap<String, Vec<u8>>>, order: &'a [String], normalizer: Option<&'a ParamNameNormalizer>) -> Self {
+		match params {
+			Some(params) => Self { params: Some(params), order, normalizer },
+			None => Self { params: None, order: &[], normalizer }
+		}
+	}
+
+	/// Returns the value of the parameter `name`. If the parameter does not
+	/// exist `None` is returned.
+	pub fn get(&self, name: &str) -> Option<&'a [u8]> {
+		self.params?.get(&normalize_param_name(self.normalizer, name.as_bytes())).map(|value| &value[..])
+	}
+
+	/// Returns the value of the parameter `name` as a UTF-8 string. If the
+	/// parameter does not exist or is not valid UTF-8 `None` is returned.
+	pub fn get_str(&self, name: &str) -> Option<&'a str> {
+		match std::str::from_utf8(self.get(name)?) {
+			Ok(value) => Some(value),
+			Err(_) => {
+				warn!("FastCGI: Parameter {} is not valid utf8.", name);
+				None
+			}
+		}
+	}
+
+	/// Returns `true` if the parameter `name` exists.
+	pub fn contains(&self, name: &str) -> bool {
+		self.get(name).is_some()
+	}
+
+	/// Returns the number of parameters.
+	pub fn len(&self) -> usize {
+		self.params.map_or(0, HashMap::len)
+	}
+
+	/// Returns `true` if there are no parameters.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Returns an iterator over the names and values of all parameters in the
+	/// order they were received.
+	///
+	/// See [`Request::params_iter_ordered`](crate::Request::params_iter_ordered)
+	/// for the handling of parameters that were sent more than once.
+	pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a [u8])> + 'a {
+		let params = self.params;
+
+		self.order.iter().filter_map(move |name| Some((name.as_str(), &params?[name][..])))
+	}
+}
+
+impl std::fmt::Debug for ParamsView<'_> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_map().entries(self.iter().map(|(name, value)| (name, String::from_utf8_lossy(value)))).finish()
+	}
+}
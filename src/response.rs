@@ -0,0 +1,1203 @@
+//! Helpers for generating CGI responses.
+//!
+//! FastCGI does not know anything about HTTP. The HTTP status and the response
+//! headers are sent to the web-server as CGI headers via the `StdOut` stream.
+//! The helpers within this module make sure the `Status` header and the
+//! [`RequestResult`] of a request are always generated together.
+use std::borrow::Cow;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+
+use log::warn;
+
+use crate::{Error, OutStream, Request, RequestResult};
+
+/// Returns the reason phrase for the most common HTTP status codes.
+///
+/// If the status code is not known `None` is returned.
+fn reason_phrase(status: u16) -> Option<&'static str> {
+	match status {
+		100 => Some("Continue"),
+		101 => Some("Switching Protocols"),
+		200 => Some("OK"),
+		201 => Some("Created"),
+		202 => Some("Accepted"),
+		204 => Some("No Content"),
+		206 => Some("Partial Content"),
+		301 => Some("Moved Permanently"),
+		302 => Some("Found"),
+		303 => Some("See Other"),
+		304 => Some("Not Modified"),
+		307 => Some("Temporary Redirect"),
+		308 => Some("Permanent Redirect"),
+		400 => Some("Bad Request"),
+		401 => Some("Unauthorized"),
+		403 => Some("Forbidden"),
+		404 => Some("Not Found"),
+		405 => Some("Method Not Allowed"),
+		406 => Some("Not Acceptable"),
+		408 => Some("Request Timeout"),
+		409 => Some("Conflict"),
+		410 => Some("Gone"),
+		411 => Some("Length Required"),
+		412 => Some("Precondition Failed"),
+		413 => Some("Payload Too Large"),
+		414 => Some("URI Too Long"),
+		415 => Some("Unsupported Media Type"),
+		416 => Some("Range Not Satisfiable"),
+		422 => Some("Unprocessable Entity"),
+		429 => Some("Too Many Requests"),
+		500 => Some("Internal Server Error"),
+		501 => Some("Not Implemented"),
+		502 => Some("Bad Gateway"),
+		503 => Some("Service Unavailable"),
+		504 => Some("Gateway Timeout"),
+		_ => None
+	}
+}
+
+/// Streamed response started via [`Request::stream`].
+///
+/// Chunks of the body are collected by [`write`](ResponseStream::write) and
+/// delivered to the web-server by [`flush`](ResponseStream::flush). Every
+/// flush sends all collected data as consecutive `StdOut` records and flushes
+/// the connection. A chunk that is assembled by multiple writes (like a
+/// server-sent event) therefore always reaches the web-server as a whole. The
+/// web-server takes care of the HTTP framing of the response (for example
+/// chunked transfer encoding).
+///
+/// Data that was written but not flushed is discarded if the stream is
+/// dropped. Call [`finish`](ResponseStream::finish) to flush the remaining
+/// data and get the [`RequestResult`] of the response.
+pub struct ResponseStream<W: AsyncWrite + Unpin> {
+	stdout: OutStream<W>,
+	status: u16,
+	buffer: Vec<u8>
+}
+
+impl <W: AsyncWrite + Unpin> ResponseStream<W> {
+	/// Adds `data` to the current chunk. Nothing is sent until
+	/// [`flush`](ResponseStream::flush) is called.
+	pub fn write(&mut self, data: &[u8]) {
+		self.buffer.extend_from_slice(data);
+	}
+
+	/// Delivers the current chunk to the web-server.
+	///
+	/// Returns after the chunk was written and the connection was flushed.
+	/// Flushing without a pending chunk only flushes the connection.
+	pub async fn flush(&mut self) -> Result<(), Error> {
+		if !self.buffer.is_empty() {
+			let chunk = std::mem::take(&mut self.buffer);
+			self.stdout.write(&chunk).await?;
+		}
+
+		Ok(self.stdout.flush().await?)
+	}
+
+	/// Adds `data` to the current chunk and delivers it to the web-server.
+	pub async fn send(&mut self, data: &[u8]) -> Result<(), Error> {
+		self.write(data);
+		self.flush().await
+	}
+
+	/// Delivers the remaining data and ends the stream.
+	///
+	/// The returned [`RequestResult`] is generated from the status passed to
+	/// [`Request::stream`].
+	pub async fn finish(mut self) -> Result<RequestResult, Error> {
+		self.flush().await?;
+
+		Ok(RequestResult::from_http_status(self.status))
+	}
+}
+
+/// Line ending of the CGI headers generated by this library.
+///
+/// Set via [`Requests::header_line_ending`](crate::Requests::header_line_ending).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEnding {
+	/// Headers end with `\r\n`. This is accepted by all web-servers.
+	#[default]
+	CrLf,
+	/// Headers end with `\n`. Some web-servers expect this from CGI programs.
+	Lf
+}
+
+impl LineEnding {
+	/// Returns the characters that end a header line.
+	pub fn as_str(self) -> &'static str {
+		match self {
+			LineEnding::CrLf => "\r\n",
+			LineEnding::Lf => "\n"
+		}
+	}
+}
+
+/// Formats the CGI `Status` header line for the passed HTTP status code.
+pub(crate) fn status_line(status: u16, line_ending: &str) -> String {
+	match reason_phrase(status) {
+		Some(reason) => format!("Status: {} {}{}", status, reason, line_ending),
+		None => format!("Status: {}{}", status, line_ending)
+	}
+}
+
+/// Checks if a response header can be sent. The name must be an HTTP token
+/// and the value must not contain a line break.
+fn is_valid_header(name: &str, value: &str) -> bool {
+	let is_token_char = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+
+	!name.is_empty() && name.chars().all(is_token_char) && !value.contains(['\r', '\n'])
+}
+
+/// Settings for the responses of the requests received via one connection.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ResponseConfig {
+	/// See [`Requests::header_line_ending`](crate::Requests::header_line_ending).
+	pub(crate) line_ending: LineEnding,
+	/// See [`Requests::check_content_length`](crate::Requests::check_content_length).
+	pub(crate) check_content_length: bool,
+	/// See [`Requests::max_response_headers`](crate::Requests::max_response_headers).
+	pub(crate) max_response_headers: Option<usize>,
+	/// See [`Requests::default_content_type`](crate::Requests::default_content_type).
+	pub(crate) default_content_type: Option<Arc<str>>
+}
+
+impl ResponseConfig {
+	/// Sets the default `Content-Type`. A value containing a line break would
+	/// tear the header apart. It is ignored.
+	pub(crate) fn set_default_content_type(&mut self, content_type: &str) {
+		if content_type.contains(['\r', '\n']) {
+			warn!("FastCGI: Default Content-Type {:?} ignored. It contains a line break.", content_type);
+		} else {
+			self.default_content_type = Some(Arc::from(content_type));
+		}
+	}
+}
+
+/// Watches the CGI headers written to `StdOut` for a `Content-Type` header.
+///
+/// Enabled via [`Requests::default_content_type`](crate::Requests::default_content_type).
+#[derive(Debug)]
+pub(crate) struct ContentTypeCheck {
+	content_type: Arc<str>,
+	line_prefix: Vec<u8>,
+	line_length: usize,
+	/// Offset of the next byte within the stream.
+	position: usize,
+	/// Offset of the current line within the stream.
+	line_start: usize,
+	/// Number of `\r` characters at the end of the data that were not sent.
+	held_back: usize,
+	found: bool,
+	done: bool
+}
+
+impl ContentTypeCheck {
+	/// Length of the `Content-Type:` prefix of a header line.
+	const PREFIX_LENGTH: usize = 13;
+
+	pub(crate) fn new(content_type: Arc<str>) -> Self {
+		Self {
+			content_type,
+			line_prefix: Vec::with_capacity(Self::PREFIX_LENGTH),
+			line_length: 0,
+			position: 0,
+			line_start: 0,
+			held_back: 0,
+			found: false,
+			done: false
+		}
+	}
+
+	/// Inspects the next chunk of data written to `StdOut`. If the headers end
+	/// within `data` without a `Content-Type` header, the stream offset of the
+	/// empty line ending them is returned. The default header must be inserted
+	/// there.
+	fn feed(&mut self, data: &[u8]) -> Option<usize> {
+		for byte in data {
+			if self.done {
+				break;
+			}
+
+			self.position += 1;
+			match byte {
+				b'\n' if self.line_length == 0 => {
+					self.done = true;
+					if !self.found {
+						return Some(self.line_start);
+					}
+				},
+				b'\n' => {
+					self.found |= self.line_prefix.eq_ignore_ascii_case(b"content-type:");
+					self.line_prefix.clear();
+					self.line_length = 0;
+					self.line_start = self.position;
+				},
+				b'\r' => (),
+				_ => {
+					if self.line_prefix.len() < Self::PREFIX_LENGTH {
+						self.line_prefix.push(*byte);
+					}
+					self.line_length += 1;
+				}
+			}
+		}
+
+		None
+	}
+
+	/// Returns the data to send for the next chunk written to `StdOut`.
+	///
+	/// If the headers end without a `Content-Type` header, the default header
+	/// is inserted in front of the empty line ending them. `\r` characters at
+	/// the end of `data` that may start this line are held back and sent with
+	/// the next chunk.
+	pub(crate) fn apply<'a>(&mut self, data: Cow<'a, [u8]>, line_ending: LineEnding) -> Cow<'a, [u8]> {
+		if self.done {
+			return data;
+		}
+
+		// The returned data starts with the characters that were held back.
+		let held_back = self.take_held_back();
+		let start = self.position - held_back;
+		let headers_end = self.feed(&data);
+
+		let mut output = match held_back {
+			0 => data,
+			_ => Cow::Owned([vec![b'\r'; held_back], data.into_owned()].concat())
+		};
+
+		if let Some(line_start) = headers_end {
+			let offset = line_start - start;
+			output.to_mut().splice(offset..offset, self.header(line_ending).into_bytes());
+		} else if !self.found && self.line_length == 0 {
+			// The current line may still become the empty line ending the headers.
+			self.held_back = self.position - self.line_start;
+			let length = output.len() - self.held_back;
+			output = match output {
+				Cow::Borrowed(bytes) => Cow::Borrowed(&bytes[..length]),
+				Cow::Owned(mut bytes) => {
+					bytes.truncate(length);
+					Cow::Owned(bytes)
+				}
+			};
+		}
+
+		output
+	}
+
+	/// Returns the number of `\r` characters that were held back. They must be
+	/// sent before `StdOut` is terminated.
+	pub(crate) fn take_held_back(&mut self) -> usize {
+		std::mem::take(&mut self.held_back)
+	}
+
+	/// Returns the default header line.
+	pub(crate) fn header(&self, line_ending: LineEnding) -> String {
+		format!("Content-Type: {}{}", self.content_type, line_ending.as_str())
+	}
+}
+
+/// Counts the CGI header lines written to `StdOut` until the empty line that
+/// ends the headers.
+///
+/// Enabled via [`Requests::max_response_headers`](crate::Requests::max_response_headers).
+#[derive(Debug)]
+pub(crate) struct HeaderCounter {
+	max_headers: usize,
+	headers: usize,
+	line_length: usize,
+	done: bool
+}
+
+impl HeaderCounter {
+	pub(crate) fn new(max_headers: usize) -> Self {
+		Self {
+			max_headers,
+			headers: 0,
+			line_length: 0,
+			done: false
+		}
+	}
+
+	/// Counts the header lines within the next chunk of data written to
+	/// `StdOut`. Returns `false` if the chunk exceeds the maximum number of
+	/// headers. In this case the counter is not changed.
+	pub(crate) fn feed(&mut self, data: &[u8]) -> bool {
+		let (mut headers, mut line_length, mut done) = (self.headers, self.line_length, self.done);
+
+		for byte in data {
+			if done {
+				break;
+			}
+
+			match byte {
+				b'\n' if line_length == 0 => done = true,
+				b'\n' => {
+					headers += 1;
+					line_length = 0;
+				},
+				b'\r' => (),
+				_ => line_length += 1
+			}
+		}
+
+		if headers > self.max_headers {
+			false
+		} else {
+			(self.headers, self.line_length, self.done) = (headers, line_length, done);
+			true
+		}
+	}
+}
+
+/// Extracts the HTTP status from the `Status` header written to `StdOut`.
+///
+/// Used to report the status via [`Requests::on_complete`](crate::Requests::on_complete).
+#[derive(Debug, Default)]
+pub(crate) struct StatusCheck {
+	line_prefix: Vec<u8>,
+	line_length: usize,
+	status: Option<u16>,
+	done: bool
+}
+
+impl StatusCheck {
+	/// Number of bytes of a header line that are inspected. This covers
+	/// `Status:`, some white space and the three digits of the status.
+	const PREFIX_LENGTH: usize = 16;
+
+	/// Inspects the next chunk of data written to `StdOut`.
+	pub(crate) fn feed(&mut self, data: &[u8]) {
+		for byte in data {
+			if self.done {
+				break;
+			}
+
+			match byte {
+				b'\n' if self.line_length == 0 => self.done = true,
+				b'\n' => {
+					if self.status.is_none() {
+						self.status = Self::parse_status(&self.line_prefix);
+					}
+					self.line_prefix.clear();
+					self.line_length = 0;
+				},
+				b'\r' => (),
+				_ => {
+					if self.line_prefix.len() < Self::PREFIX_LENGTH {
+						self.line_prefix.push(*byte);
+					}
+					self.line_length += 1;
+				}
+			}
+		}
+	}
+
+	/// Returns the status of the first `Status` header. `None` if there was
+	/// none.
+	pub(crate) fn status(&self) -> Option<u16> {
+		self.status
+	}
+
+	/// Parses the status from the beginning of a header line. Returns `None`
+	/// if the line is not a valid `Status` header.
+	fn parse_status(line: &[u8]) -> Option<u16> {
+		let (name, value) = line.split_at_checked(7)?;
+		if !name.eq_ignore_ascii_case(b"status:") {
+			return None;
+		}
+
+		let value = value.trim_ascii_start();
+		match value.get(..3) {
+			Some(digits) if digits.iter().all(u8::is_ascii_digit) => std::str::from_utf8(digits).ok()?.parse().ok(),
+			_ => None
+		}
+	}
+}
+
+/// Maximum size of the CGI headers inspected by [`ContentLengthCheck`]. If
+/// the headers are longer, the response is not checked.
+const MAX_CHECKED_HEADER_SIZE: usize = 64 * 1024;
+
+/// Compares the `Content-Length` header written to `StdOut` with the number
+/// of body bytes that follow the headers.
+///
+/// Enabled via [`Requests::check_content_length`](crate::Requests::check_content_length).
+#[derive(Debug, Default)]
+pub(crate) struct ContentLengthCheck {
+	header: Vec<u8>,
+	header_done: bool,
+	declared: Option<u64>,
+	written: u64
+}
+
+impl ContentLengthCheck {
+	/// Inspects the next chunk of data written to `StdOut`.
+	pub(crate) fn feed(&mut self, data: &[u8]) {
+		if self.header_done {
+			self.written += data.len() as u64;
+			return;
+		}
+
+		// The end of the headers may be split across several writes.
+		let search_start = self.header.len().saturating_sub(3);
+		self.header.extend_from_slice(data);
+
+		let header_end = self.header[search_start..].windows(2).enumerate().find_map(|(offset, window)| {
+			let position = search_start + offset;
+			match window {
+				b"\n\n" => Some((position, position + 2)),
+				b"\r\n" if self.header[position + 2..].starts_with(b"\r\n") => Some((position, position + 4)),
+				_ => None
+			}
+		});
+
+		if let Some((end, body_start)) = header_end {
+			self.declared = String::from_utf8_lossy(&self.header[..end]).lines().find_map(|line| {
+				let (name, value) = line.split_once(':')?;
+				if name.trim().eq_ignore_ascii_case("Content-Length") { value.trim().parse().ok() } else { None }
+			});
+			self.written = (self.header.len() - body_start) as u64;
+			self.header_done = true;
+			self.header = Vec::new();
+		} else if self.header.len() > MAX_CHECKED_HEADER_SIZE {
+			warn!("FastCGI: Headers longer than {} bytes. The Content-Length of the response is not checked.", MAX_CHECKED_HEADER_SIZE);
+			self.header_done = true;
+			self.header = Vec::new();
+		}
+	}
+
+	/// Returns the declared and the written length if they differ.
+	pub(crate) fn mismatch(&self) -> Option<(u64, u64)> {
+		self.declared.filter(|declared| *declared != self.written).map(|declared| (declared, self.written))
+	}
+}
+
+impl RequestResult {
+	/// Returns the result for a request that was answered with the HTTP status
+	/// code `status`.
+	///
+	/// The application status of a FastCGI request is the exit code a CGI
+	/// program would have returned. It is independent of the HTTP status code
+	/// that is sent to the client via the `Status` header. Most web-servers
+	/// ignore the application status. This function follows the convention of
+	/// a CGI program that exits successfully as long as it was able to generate
+	/// a response:
+	///
+	/// * `1xx` to `4xx` return [`Complete(0)`](RequestResult::Complete). The
+	///   request was handled even if the client is told that it was invalid.
+	/// * `5xx` and all unknown status codes return
+	///   [`Complete(1)`](RequestResult::Complete) to signal that the application
+	///   failed to process the request.
+	///
+	/// Use [`respond_with`](Request::respond_with) to send the `Status` header and
+	/// get the matching result in one go.
+	pub fn from_http_status(status: u16) -> Self {
+		match status {
+			100..=499 => RequestResult::Complete(0),
+			_ => RequestResult::Complete(1)
+		}
+	}
+}
+
+impl <W: AsyncWrite + Unpin> Request<W> {
+	/// Sets the HTTP status of the response.
+	///
+	/// The status is not sent immediately. The `Status` header is inserted in
+	/// front of the first data written to [`get_stdout`](Request::get_stdout).
+	/// If nothing is written to `StdOut`, the `Status` header is sent as the
+	/// only header when the request is finished. This allows the status to be
+	/// decided in one place (like a middleware) and the headers and the body to
+	/// be generated somewhere else. The headers written to `StdOut` must not
+	/// contain another `Status` header.
+	///
+	/// The status can be changed until the first data was written to `StdOut`.
+	/// Calling this function afterwards has no effect. If the status is never
+	/// set, no `Status` header is inserted and the web-server uses the default
+	/// status `200 OK`. [`respond_with`](Request::respond_with) always sends the
+	/// status it was called with.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   request.set_status(404);
+	///
+	///   // Sends "Status: 404 Not Found" in front of the headers.
+	///   request.get_stdout().write(b"Content-Type: text/plain\r\n\r\nNot here").await.unwrap();
+	///
+	///   RequestResult::Complete(0)
+	/// });
+	/// # } }
+	/// ```
+	pub fn set_status(&self, status: u16) {
+		if !self.orw.set_status(status) {
+			warn!("FastCGI: Status {} of request {} ignored. The response was already started.", status, self.get_request_id());
+		}
+	}
+
+	/// Sets a variable for the requests following the authorization.
+	///
+	/// An authorizer (see [`Role::Authorizer`](crate::Role::Authorizer)) that
+	/// grants access can pass variables to the application that handles the
+	/// authorized request. They are sent to the web-server as
+	/// `Variable-<name>: <value>` headers. A typical variable is
+	/// `REMOTE_USER`. The web-server removes the `Variable-` prefix and adds
+	/// the variables to the parameters of the authorized request. Most
+	/// web-servers only honor them in a `200 OK` response.
+	///
+	/// The variables are sent together with the status set via
+	/// [`set_status`](Request::set_status) in front of the first data written
+	/// to `StdOut`. If nothing is written, they are sent as the only headers
+	/// when the request is finished. Variables set after the response was
+	/// started and variables with a name or value that contains a line break
+	/// or a name that contains a colon are ignored.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   // Sends "Status: 200 OK\r\nVariable-REMOTE_USER: alice\r\n\r\n".
+	///   request.set_status(200);
+	///   request.set_variable("REMOTE_USER", "alice");
+	///
+	///   RequestResult::Complete(0)
+	/// });
+	/// # } }
+	/// ```
+	pub fn set_variable(&self, name: &str, value: &str) {
+		let invalid = |text: &str| text.contains(['\r', '\n']);
+
+		if name.is_empty() || name.contains(':') || invalid(name) || invalid(value) {
+			warn!("FastCGI: Invalid variable {:?} of request {} ignored.", name, self.get_request_id());
+		} else if !self.orw.add_variable(name, value) {
+			warn!("FastCGI: Variable {:?} of request {} ignored. The response was already started.", name, self.get_request_id());
+		}
+	}
+
+	/// Adds a trailer to the response.
+	///
+	/// CGI can not transport HTTP trailers. The trailers are therefore sent
+	/// the same way an HTTP/1.1 server sends them: as the trailer section of a
+	/// body with `Transfer-Encoding: chunked`. The trailers are buffered and
+	/// sent in the order they were added, after everything the request
+	/// processor wrote to `StdOut`, when the request is finished by
+	/// [`process`](Request::process). The terminating zero length chunk is
+	/// sent in front of them. The trailer section is part of the HTTP body and
+	/// always uses `\r\n` line endings.
+	///
+	/// This only works if the request processor and the web-server follow this
+	/// convention:
+	///
+	/// * The response headers contain `Transfer-Encoding: chunked` and a
+	///   `Trailer` header listing the names of the trailers.
+	/// * The body is written chunk encoded (`<length in hex>\r\n<data>\r\n`)
+	///   but without the terminating zero length chunk.
+	/// * The web-server passes the chunked body through without decoding it and
+	///   converts the trailer section into HTTP trailers. Most web-servers
+	///   decode the body of a CGI response and discard the trailer section.
+	///   Check the documentation of the web-server before using trailers (for
+	///   example gRPC needs the `grpc-status` trailer).
+	///
+	/// Trailers with a name or value that contains a line break or with a name
+	/// that contains a colon are ignored. The same is true for trailers added
+	/// after the request was aborted.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   let mut stdout = request.get_stdout();
+	///   stdout.write(b"Transfer-Encoding: chunked\r\nTrailer: grpc-status\r\n\r\n").await.unwrap();
+	///   stdout.write(b"5\r\nHello\r\n").await.unwrap();
+	///
+	///   // Sent as "0\r\ngrpc-status: 0\r\n\r\n" after the body.
+	///   request.trailer("grpc-status", "0");
+	///
+	///   RequestResult::Complete(0)
+	/// });
+	/// # } }
+	/// ```
+	pub fn trailer(&self, name: &str, value: &str) {
+		let invalid = |text: &str| text.contains(['\r', '\n']);
+
+		if name.is_empty() || name.contains(':') || invalid(name) || invalid(value) {
+			warn!("FastCGI: Invalid trailer {:?} of request {} ignored.", name, self.get_request_id());
+		} else {
+			self.trailers.lock().unwrap().push((name.to_string(), value.to_string()));
+		}
+	}
+
+	/// Returns the trailer section that ends the chunked body of the response.
+	/// Returns `None` if no trailers were added.
+	pub(crate) fn trailer_section(&self) -> Option<Vec<u8>> {
+		let trailers = self.trailers.lock().unwrap();
+		if trailers.is_empty() {
+			return None;
+		}
+
+		let mut section = b"0\r\n".to_vec();
+		for (name, value) in trailers.iter() {
+			section.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+		}
+		section.extend_from_slice(b"\r\n");
+
+		Some(section)
+	}
+
+	/// Sends a complete response to the web-server.
+	///
+	/// The CGI `Status` header is generated from `status`. It is followed by the
+	/// passed `headers`, the empty line terminating the header section and the
+	/// `body`. Because the whole body is known, a `Content-Length` header is
+	/// added automatically. This is skipped if `headers` already contain a
+	/// `Content-Length` or a `Transfer-Encoding` header. Responses that are
+	/// streamed via [`get_stdout`](Request::get_stdout) never get a
+	/// `Content-Length` header.
+	///
+	/// The returned [`RequestResult`] is generated from the HTTP status
+	/// via [`RequestResult::from_http_status`]. Returning it from the callback
+	/// passed to [`process`](Request::process) makes sure the status sent to the
+	/// client and the result sent to the web-server always match.
+	///
+	/// A header name must be a valid HTTP token and a value must not contain a
+	/// line break. Otherwise nothing is sent and
+	/// [`InvalidResponseHeader`](Error::InvalidResponseHeader) is returned. If
+	/// data was already written to `StdOut`, the headers can not be sent
+	/// anymore and [`ResponseAlreadyStarted`](Error::ResponseAlreadyStarted)
+	/// is returned.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   request.respond_with(404, &[("Content-Type", "text/plain")], b"Not here").await
+	///     .unwrap_or(RequestResult::Complete(1))
+	/// });
+	/// # } }
+	/// ```
+	pub async fn respond_with(&self, status: u16, headers: &[(&str, &str)], body: &[u8]) -> Result<RequestResult, Error> {
+		let mut response = self.response_head(status, headers, Some(body.len()))?.into_bytes();
+		response.extend_from_slice(body);
+
+		self.get_stdout().write(&response).await?;
+
+		Ok(RequestResult::from_http_status(status))
+	}
+
+	/// Sends a response whose body is read from `reader`.
+	///
+	/// The `Status` header and the passed `headers` are sent first. Afterwards
+	/// `reader` is copied to `StdOut` until it reaches its end. This allows
+	/// serving files or piping another stream without buffering the whole body.
+	/// Because the length of the body is not known, no `Content-Length` header
+	/// is generated. Pass one in `headers` if the length is known (like the size
+	/// of a file). The headers are checked like by
+	/// [`respond_with`](Request::respond_with).
+	///
+	/// If reading from `reader` fails, the headers were already sent. The
+	/// response is cut short and [`Complete(1)`](RequestResult::Complete) is
+	/// returned to signal the failure to the web-server. Otherwise the result is
+	/// derived from `status` via [`RequestResult::from_http_status`].
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink, AsyncReadExt};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   let body = tokio::io::repeat(b'x').take(1024);
+	///   request.send_body_reader(200, &[("Content-Type", "text/plain"), ("Content-Length", "1024")], body).await
+	///     .unwrap_or(RequestResult::Complete(1))
+	/// });
+	/// # } }
+	/// ```
+	pub async fn send_body_reader<R: AsyncRead + Unpin>(&self, status: u16, headers: &[(&str, &str)], mut reader: R) -> Result<RequestResult, Error> {
+		const CHUNK_SIZE: usize = 16 * 1024;
+
+		let mut stdout = self.get_stdout();
+		stdout.write(self.response_head(status, headers, None)?.as_bytes()).await?;
+
+		let mut buffer = vec![0; CHUNK_SIZE];
+		loop {
+			match reader.read(&mut buffer).await {
+				Ok(0) => break,
+				Ok(length) => {
+					stdout.write(&buffer[..length]).await?;
+				},
+				Err(err) => {
+					warn!("FastCGI: Reading the body of the response to request {} failed: {}", self.get_request_id(), err);
+					return Ok(RequestResult::Complete(1));
+				}
+			}
+		}
+
+		Ok(RequestResult::from_http_status(status))
+	}
+
+	/// Starts a streamed response.
+	///
+	/// The `Status` header and the passed `headers` are sent and flushed right
+	/// away. This makes the web-server start the response to the client. The
+	/// body is sent in chunks via the returned [`ResponseStream`]. This is the
+	/// building block for server-sent events and long-polling. No
+	/// `Content-Length` header is generated. The headers are checked like by
+	/// [`respond_with`](Request::respond_with).
+	///
+	/// Whether the web-server passes every chunk on to the client right away
+	/// depends on its configuration (for example `fastcgi_buffering off` for
+	/// nginx or `flushpackets=on` for Apache).
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   let mut stream = request.stream(200, &[("Content-Type", "text/event-stream")]).await.unwrap();
+	///   for event in 0..3 {
+	///     stream.write(format!("id: {}\n", event).as_bytes());
+	///     stream.write(b"data: tick\n\n");
+	///     // Deliver the complete event before producing the next one.
+	///     stream.flush().await.unwrap();
+	///   }
+	///
+	///   stream.finish().await.unwrap_or(RequestResult::Complete(1))
+	/// });
+	/// # } }
+	/// ```
+	pub async fn stream(&self, status: u16, headers: &[(&str, &str)]) -> Result<ResponseStream<W>, Error> {
+		let mut stream = ResponseStream {
+			stdout: self.get_stdout(),
+			status,
+			buffer: Vec::new()
+		};
+
+		stream.send(self.response_head(status, headers, None)?.as_bytes()).await?;
+
+		Ok(stream)
+	}
+
+	/// Generates the header section of a response. If `body_length` is passed,
+	/// a `Content-Length` header is added unless `headers` contain a
+	/// `Content-Length` or `Transfer-Encoding` header.
+	fn response_head(&self, status: u16, headers: &[(&str, &str)], body_length: Option<usize>) -> Result<String, Error> {
+		let line_ending = self.orw.line_ending.as_str();
+
+		// Nothing is sent if a header would tear the header section apart.
+		if let Some((name, _)) = headers.iter().find(|(name, value)| !is_valid_header(name, value)) {
+			warn!("FastCGI: Invalid response header {:?} of request {} rejected.", name, self.get_request_id());
+			return Err(Error::InvalidResponseHeader(name.to_string()));
+		}
+
+		// The status passed to this function replaces the one set via set_status.
+		let mut head = self.orw.start_stdout(Some(status)).ok_or(Error::ResponseAlreadyStarted)?;
+
+		for (name, value) in headers {
+			head.push_str(&format!("{}: {}{}", name, value, line_ending));
+		}
+
+		if let Some(body_length) = body_length {
+			let has_length = headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("Content-Length") || name.eq_ignore_ascii_case("Transfer-Encoding"));
+			if !has_length {
+				head.push_str(&format!("Content-Length: {}{}", body_length, line_ending));
+			}
+		}
+
+		head.push_str(line_ending);
+
+		Ok(head)
+	}
+
+	/// Sends a complete JSON response to the web-server.
+	///
+	/// This works like [`respond_with`](Request::respond_with) but sets the
+	/// `Content-Type` header to `application/json`. The `json` string must
+	/// already be serialized.
+	pub async fn respond_json(&self, status: u16, json: &str) -> Result<RequestResult, Error> {
+		self.respond_with(status, &[("Content-Type", "application/json")], json.as_bytes()).await
+	}
+
+	/// Makes the web-server answer the request with the resource at `path`.
+	///
+	/// A `200 OK` response with an empty body and an `X-Accel-Redirect` header
+	/// containing `path` is sent. nginx serves the resource at `path` instead
+	/// of this response. This is the common pattern to serve protected static
+	/// files: the application checks the permissions and nginx delivers the
+	/// file from an `internal` location. Other web-servers use different
+	/// headers (like `X-Sendfile`). They can be sent via
+	/// [`respond_with`](Request::respond_with).
+	///
+	/// If `path` contains a line break, the header would be torn apart. No
+	/// redirect is sent then. The request is answered with
+	/// `500 Internal Server Error`.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   request.internal_redirect("/protected/report.pdf").await.unwrap_or(RequestResult::Complete(1))
+	/// });
+	/// # } }
+	/// ```
+	pub async fn internal_redirect(&self, path: &str) -> Result<RequestResult, Error> {
+		if path.contains(['\r', '\n']) {
+			warn!("FastCGI: Internal redirect of request {} to {:?} rejected. The path contains a line break.", self.get_request_id(), path);
+			return self.respond_with(500, &[], &[]).await;
+		}
+
+		self.respond_with(200, &[("X-Accel-Redirect", path)], &[]).await
+	}
+
+	/// Rejects the request if the declared length of its body exceeds `limit`.
+	///
+	/// The length is taken from the `CONTENT_LENGTH` parameter (see
+	/// [`content_length`](Request::content_length)). If it is bigger than `limit`
+	/// a `413 Payload Too Large` response is sent and the matching
+	/// [`RequestResult`] is returned. The processor should return it without
+	/// touching the body. If the body is within the limit or no length was
+	/// declared, `None` is returned and the request can be processed normally.
+	///
+	/// The body is never read by this function. Be aware that the body of a
+	/// request is received before the request is handed to the processor. This
+	/// function saves the work of processing a body that is destined for
+	/// rejection but it can not prevent the body from being received.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.process(|request| async move {
+	///   match request.reject_if_too_large(1024 * 1024).await {
+	///     Ok(Some(result)) => return result,
+	///     Ok(None) => { /* Process the body */ },
+	///     Err(_) => return RequestResult::Complete(1)
+	///   }
+	///
+	///   RequestResult::Complete(0)
+	/// });
+	/// # } }
+	/// ```
+	pub async fn reject_if_too_large(&self, limit: u64) -> Result<Option<RequestResult>, Error> {
+		match self.content_length() {
+			Some(length) if length > limit => Ok(Some(self.respond_with(413, &[], &[]).await?)),
+			_ => Ok(None)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn from_http_status() {
+		assert_eq!(RequestResult::from_http_status(200), RequestResult::Complete(0));
+		assert_eq!(RequestResult::from_http_status(302), RequestResult::Complete(0));
+		assert_eq!(RequestResult::from_http_status(404), RequestResult::Complete(0));
+		assert_eq!(RequestResult::from_http_status(503), RequestResult::Complete(1));
+		assert_eq!(RequestResult::from_http_status(999), RequestResult::Complete(1));
+	}
+
+	#[tokio::test]
+	async fn internal_redirect() {
+		let (request, output) = Request::test_builder().build();
+		request.process(|request| async move { request.internal_redirect("/protected/file name.pdf?v=1").await.unwrap() }).await.unwrap();
+		assert_eq!(output.stdout(), b"Status: 200 OK\r\nX-Accel-Redirect: /protected/file name.pdf?v=1\r\nContent-Length: 0\r\n\r\n");
+		assert_eq!(output.end_request(), Some((0, 0)));
+
+		let (request, output) = Request::test_builder().build();
+		request.process(|request| async move { request.internal_redirect("/file\r\nSet-Cookie: a=b").await.unwrap() }).await.unwrap();
+		assert_eq!(output.stdout(), b"Status: 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n");
+		assert_eq!(output.end_request(), Some((1, 0)));
+	}
+
+	#[tokio::test]
+	async fn invalid_headers() {
+		for (name, value) in [("Content Type", "text/plain"), ("X-Test:", "1"), ("", "1"), ("X-Test", "1\r\nSet-Cookie: a=b")] {
+			let (request, output) = Request::test_builder().build();
+			request.process(|request| async move {
+				assert!(matches!(request.respond_with(200, &[(name, value)], b"Hi").await, Err(Error::InvalidResponseHeader(invalid)) if invalid == name));
+				RequestResult::Complete(0)
+			}).await.unwrap();
+			assert_eq!(output.stdout(), b"");
+		}
+
+		// A second header section would end up in the body.
+		let (request, output) = Request::test_builder().build();
+		request.process(|request| async move {
+			request.get_stdout().write(b"Status: 200 OK\r\n\r\nHi").await.unwrap();
+			assert!(matches!(request.respond_with(404, &[], &[]).await, Err(Error::ResponseAlreadyStarted)));
+			RequestResult::Complete(0)
+		}).await.unwrap();
+		assert_eq!(output.stdout(), b"Status: 200 OK\r\n\r\nHi");
+	}
+
+	#[tokio::test]
+	async fn line_endings() {
+		let (request, output) = Request::test_builder().build();
+		request.process(|request| async move { request.respond_with(200, &[("Content-Type", "text/plain")], b"Hi").await.unwrap() }).await.unwrap();
+		assert_eq!(output.stdout(), b"Status: 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 2\r\n\r\nHi");
+
+		let (request, output) = Request::test_builder().header_line_ending(LineEnding::Lf).build();
+		request.process(|request| async move { request.respond_with(200, &[("Content-Type", "text/plain")], b"Hi").await.unwrap() }).await.unwrap();
+		assert_eq!(output.stdout(), b"Status: 200 OK\nContent-Type: text/plain\nContent-Length: 2\n\nHi");
+
+		let (request, output) = Request::test_builder().header_line_ending(LineEnding::Lf).build();
+		request.process(|request| async move {
+			request.set_status(204);
+			RequestResult::Complete(0)
+		}).await.unwrap();
+		assert_eq!(output.stdout(), b"Status: 204 No Content\n\n");
+	}
+
+	#[tokio::test]
+	async fn variables() {
+		// Without output the variables are sent as the only headers.
+		let (request, output) = Request::test_builder().role(crate::Role::Authorizer).build();
+		request.process(|request| async move {
+			request.set_status(200);
+			request.set_variable("REMOTE_USER", "alice");
+			request.set_variable("AUTH:TYPE", "ignored");
+			request.set_variable("AUTH_GROUP", "admins");
+
+			RequestResult::Complete(0)
+		}).await.unwrap();
+		assert_eq!(output.stdout(), b"Status: 200 OK\r\nVariable-REMOTE_USER: alice\r\nVariable-AUTH_GROUP: admins\r\n\r\n");
+
+		// The variables precede the data written to StdOut. Later ones are ignored.
+		let (request, output) = Request::test_builder().header_line_ending(LineEnding::Lf).build();
+		request.process(|request| async move {
+			request.set_variable("REMOTE_USER", "bob");
+			request.get_stdout().write(b"\n").await.unwrap();
+			request.set_variable("LATE", "ignored");
+
+			RequestResult::Complete(0)
+		}).await.unwrap();
+		assert_eq!(output.stdout(), b"Variable-REMOTE_USER: bob\n\n");
+
+		// respond_with sends them, too.
+		let (request, output) = Request::test_builder().build();
+		request.process(|request| async move {
+			request.set_variable("REMOTE_USER", "carol");
+			request.respond_with(200, &[], &[]).await.unwrap()
+		}).await.unwrap();
+		assert_eq!(output.stdout(), b"Status: 200 OK\r\nVariable-REMOTE_USER: carol\r\nContent-Length: 0\r\n\r\n");
+	}
+
+	#[tokio::test]
+	async fn send_body_reader() {
+		const STDOUT: u8 = 6;
+
+		let body: Vec<u8> = (0..40000u32).map(|index| (index % 251) as u8).collect();
+		let reader_body = body.clone();
+
+		let (request, output) = Request::test_builder().build();
+		request.process(|request| async move {
+			request.send_body_reader(200, &[("Content-Type", "application/octet-stream")], &reader_body[..]).await.unwrap()
+		}).await.unwrap();
+
+		// The headers are followed by one record per chunk read and the end of the stream.
+		let records: Vec<Vec<u8>> = output.records().into_iter()
+			.filter(|(record_type, _, _)| *record_type == STDOUT)
+			.map(|(_, _, content)| content)
+			.collect();
+		assert_eq!(records.iter().map(Vec::len).collect::<Vec<_>>(), [58, 16384, 16384, 7232, 0]);
+		assert_eq!(records[0], b"Status: 200 OK\r\nContent-Type: application/octet-stream\r\n\r\n");
+		assert_eq!(records[1..].concat(), body);
+		assert_eq!(output.end_request(), Some((0, 0)));
+
+		// A failing reader cuts the response short.
+		struct FailingReader;
+
+		impl AsyncRead for FailingReader {
+			fn poll_read(self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>, _buf: &mut tokio::io::ReadBuf<'_>) -> std::task::Poll<std::io::Result<()>> {
+				std::task::Poll::Ready(Err(std::io::Error::other("Disk failed")))
+			}
+		}
+
+		let (request, output) = Request::test_builder().build();
+		request.process(|request| async move {
+			request.send_body_reader(200, &[], (&b"Partial"[..]).chain(FailingReader)).await.unwrap()
+		}).await.unwrap();
+		assert_eq!(output.stdout(), b"Status: 200 OK\r\n\r\nPartial");
+		assert_eq!(output.end_request(), Some((1, 0)));
+	}
+
+	#[test]
+	fn content_type_header() {
+		let check = ContentTypeCheck::new(Arc::from("text/plain"));
+		assert_eq!(check.header(LineEnding::CrLf), "Content-Type: text/plain\r\n");
+		assert_eq!(check.header(LineEnding::Lf), "Content-Type: text/plain\n");
+	}
+
+	#[tokio::test]
+	async fn max_response_headers() {
+		// Headers passed to respond_with are counted together with the status.
+		let (request, output) = Request::test_builder().max_response_headers(3).build();
+		request.process(|request| async move {
+			let headers = [("Content-Type", "text/plain"), ("X-One", "1"), ("X-Two", "2")];
+			assert!(matches!(request.respond_with(200, &headers, b"Body").await, Err(Error::TooManyResponseHeaders)));
+
+			RequestResult::Complete(1)
+		}).await.unwrap();
+		assert_eq!(output.stdout(), b"");
+
+		// Headers written in a loop are stopped as soon as the limit is exceeded.
+		let (request, output) = Request::test_builder().max_response_headers(3).build();
+		request.process(|request| async move {
+			let mut stdout = request.get_stdout();
+			for header in 0.. {
+				if let Err(err) = stdout.write(format!("X-Header-{}: {}\r\n", header, header).as_bytes()).await {
+					assert!(matches!(err, Error::TooManyResponseHeaders));
+					break;
+				}
+			}
+
+			RequestResult::Complete(0)
+		}).await.unwrap();
+		assert_eq!(output.stdout(), b"X-Header-0: 0\r\nX-Header-1: 1\r\nX-Header-2: 2\r\n");
+
+		// The lines of the body are not counted.
+		let (request, output) = Request::test_builder().max_response_headers(1).build();
+		request.process(|request| async move {
+			let mut stdout = request.get_stdout();
+			stdout.write(b"Content-Type: text/plain\n\n").await.unwrap();
+			stdout.write(b"Line 1\nLine 2\n").await.unwrap();
+		}).await.unwrap();
+		assert_eq!(output.stdout(), b"Content-Type: text/plain\n\nLine 1\nLine 2\n");
+	}
+
+	#[tokio::test]
+	async fn trailers() {
+		let (request, output) = Request::test_builder().build();
+		request.process(|request| async move {
+			let mut stdout = request.get_stdout();
+			stdout.write(b"Transfer-Encoding: chunked\r\nTrailer: grpc-status, grpc-message\r\n\r\n").await.unwrap();
+			request.trailer("grpc-status", "0");
+			stdout.write(b"5\r\nHello\r\n").await.unwrap();
+			request.trailer("grpc-message", "OK");
+			request.trailer("invalid:name", "ignored");
+			request.trailer("grpc-details", "ignored\r\n");
+
+			RequestResult::Complete(0)
+		}).await.unwrap();
+
+		// The trailers follow the body in the order they were added.
+		assert_eq!(output.stdout(), b"Transfer-Encoding: chunked\r\nTrailer: grpc-status, grpc-message\r\n\r\n5\r\nHello\r\n0\r\ngrpc-status: 0\r\ngrpc-message: OK\r\n\r\n");
+		assert_eq!(output.end_request(), Some((0, 0)));
+	}
+
+	#[tokio::test]
+	async fn content_length_check() {
+		// The body is shorter than the declared length.
+		let (request, output) = Request::test_builder().check_content_length(true).build();
+		let result = request.process(|request| async move {
+			let mut stdout = request.get_stdout();
+			stdout.write(b"Content-Length: 10\r\n\r").await.unwrap();
+			stdout.write(b"\nHel").await.unwrap();
+			stdout.write(b"lo").await.unwrap();
+
+			RequestResult::Complete(0)
+		}).await;
+
+		assert!(matches!(result, Err(Error::ContentLengthMismatch(10, 5))));
+		assert_eq!(output.end_request(), Some((0, 0)));
+
+		// Responses generated by respond_with always match.
+		let (request, _output) = Request::test_builder().check_content_length(true).build();
+		request.process(|request| async move {
+			request.set_status(404);
+			request.respond_with(200, &[], b"Hello").await.unwrap()
+		}).await.unwrap();
+
+		// Without the check the mismatch is not detected.
+		let (request, _output) = Request::test_builder().build();
+		request.process(|request| async move {
+			request.get_stdout().write(b"Content-Length: 10\n\nHello").await.unwrap();
+		}).await.unwrap();
+	}
+
+	#[test]
+	fn content_length_check_parsing() {
+		let mut check = ContentLengthCheck::default();
+		check.feed(b"Status: 200 OK\ncontent-length:  3 \n\nabc");
+		assert_eq!(check.mismatch(), None);
+		check.feed(b"d");
+		assert_eq!(check.mismatch(), Some((3, 4)));
+
+		// Responses without Content-Length are not checked.
+		let mut check = ContentLengthCheck::default();
+		check.feed(b"Transfer-Encoding: chunked\r\n\r\n5\r\nHello\r\n");
+		assert_eq!(check.mismatch(), None);
+	}
+
+	#[test]
+	fn status_check_parsing() {
+		let mut check = StatusCheck::default();
+		check.feed(b"Content-Type: text/plain\r\nsta");
+		check.feed(b"tus:  404 Not Found\r\n\r\nStatus: 200 OK\r\n");
+		assert_eq!(check.status(), Some(404));
+
+		// Responses without a Status header and invalid headers do not have a status.
+		let mut check = StatusCheck::default();
+		check.feed(b"Content-Type: text/plain\n\nStatus: 200 OK\n");
+		assert_eq!(check.status(), None);
+
+		let mut check = StatusCheck::default();
+		check.feed(b"Status: 20x\n\n");
+		assert_eq!(check.status(), None);
+	}
+
+	#[test]
+	fn status_lines() {
+		assert_eq!(status_line(200, "\r\n"), "Status: 200 OK\r\n");
+		assert_eq!(status_line(413, "\r\n"), "Status: 413 Payload Too Large\r\n");
+		assert_eq!(status_line(299, "\n"), "Status: 299\n");
+	}
+}
@@ -0,0 +1,316 @@
+//! Streaming of the `StdIn` stream of a request.
+//!
+//! By default the body of a request is received completely before the request
+//! is returned by [`Requests::next`](crate::Requests::next). If streaming is
+//! enabled via [`Requests::stream_stdin`](crate::Requests::stream_stdin),
+//! responders are returned as soon as their parameters are complete. The
+//! `StdIn` records that arrive afterwards are passed from the connection to the
+//! processor via a [`StdinChannel`].
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::Notify;
+
+use crate::{OwnedInStream, Request, Requests};
+
+/// State of a [`StdinChannel`].
+#[derive(Debug, Default)]
+struct ChannelState {
+	data: VecDeque<u8>,
+	received: usize,
+	done: bool,
+	error: Option<ErrorKind>,
+	reader: Option<Waker>
+}
+
+/// Passes the `StdIn` records of a streamed request from the connection to the
+/// processor.
+#[derive(Debug, Default)]
+pub(crate) struct StdinChannel {
+	state: std::sync::Mutex<ChannelState>,
+	complete: Notify
+}
+
+impl StdinChannel {
+	/// Appends the content of a `StdIn` record. An empty record ends the
+	/// stream.
+	pub(crate) fn push(&self, data: &[u8]) {
+		let mut state = self.state.lock().unwrap();
+		if state.done {
+			return;
+		}
+
+		if data.is_empty() {
+			state.done = true;
+		} else {
+			state.data.extend(data);
+			state.received += data.len();
+		}
+
+		let done = state.done;
+		if let Some(reader) = state.reader.take() {
+			reader.wake();
+		}
+		drop(state);
+
+		if done {
+			self.complete.notify_waiters();
+		}
+	}
+
+	/// Ends the stream with an error. The data received so far can still be
+	/// read. Afterwards reading fails with `kind`.
+	pub(crate) fn fail(&self, kind: ErrorKind) {
+		let mut state = self.state.lock().unwrap();
+		if state.done {
+			return;
+		}
+
+		state.done = true;
+		state.error = Some(kind);
+		if let Some(reader) = state.reader.take() {
+			reader.wake();
+		}
+		drop(state);
+
+		self.complete.notify_waiters();
+	}
+
+	/// Returns the number of bytes received so far.
+	pub(crate) fn received(&self) -> usize {
+		self.state.lock().unwrap().received
+	}
+
+	/// Checks if the stream was received completely or failed.
+	pub(crate) fn is_done(&self) -> bool {
+		self.state.lock().unwrap().done
+	}
+
+	/// Waits until the stream was received completely or failed.
+	pub(crate) async fn wait_done(&self) {
+		// The future is registered before the flag is checked. The end of the stream in between is not missed.
+		let notified = self.complete.notified();
+		if !self.is_done() {
+			notified.await;
+		}
+	}
+
+	/// Moves the data that was not read yet out of the channel. Returns the
+	/// error the stream failed with, too.
+	pub(crate) fn take(&self) -> (Vec<u8>, Option<ErrorKind>) {
+		let mut state = self.state.lock().unwrap();
+		let data = std::mem::take(&mut state.data).into();
+
+		(data, state.error)
+	}
+
+	/// Moves the data received so far out of the channel. Returns `None` at
+	/// the end of the stream.
+	#[cfg(feature = "hyper")]
+	pub(crate) fn poll_chunk(&self, cx: &mut Context<'_>) -> Poll<Option<std::io::Result<Vec<u8>>>> {
+		let mut state = self.state.lock().unwrap();
+
+		if !state.data.is_empty() {
+			Poll::Ready(Some(Ok(std::mem::take(&mut state.data).into())))
+		} else if let Some(kind) = state.error {
+			Poll::Ready(Some(Err(incomplete(kind))))
+		} else if state.done {
+			Poll::Ready(None)
+		} else {
+			state.reader = Some(cx.waker().clone());
+			Poll::Pending
+		}
+	}
+
+	fn poll_read(&self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+		let mut state = self.state.lock().unwrap();
+
+		if !state.data.is_empty() {
+			let length = state.data.len().min(buf.remaining());
+			let (front, back) = state.data.as_slices();
+			let front_length = length.min(front.len());
+			buf.put_slice(&front[..front_length]);
+			buf.put_slice(&back[..length - front_length]);
+			state.data.drain(..length);
+
+			Poll::Ready(Ok(()))
+		} else if let Some(kind) = state.error {
+			Poll::Ready(Err(incomplete(kind)))
+		} else if state.done {
+			Poll::Ready(Ok(()))
+		} else {
+			state.reader = Some(cx.waker().clone());
+			Poll::Pending
+		}
+	}
+}
+
+/// Returns the error reading a stream fails with that was not received
+/// completely.
+pub(crate) fn incomplete(kind: ErrorKind) -> std::io::Error {
+	std::io::Error::new(kind, "StdIn was not received completely")
+}
+
+/// The connection side of a [`StdinChannel`].
+///
+/// If the sender is dropped before the stream was received completely, the
+/// stream fails with [`ConnectionAborted`](ErrorKind::ConnectionAborted). This
+/// way a processor never waits for records of a connection that is gone.
+#[derive(Debug)]
+pub(crate) struct StdinSender {
+	channel: Arc<StdinChannel>
+}
+
+impl StdinSender {
+	pub(crate) fn new(channel: Arc<StdinChannel>) -> Self {
+		Self { channel }
+	}
+
+	/// Checks if the request reading the stream still exists.
+	pub(crate) fn is_read(&self) -> bool {
+		Arc::strong_count(&self.channel) > 1
+	}
+}
+
+impl std::ops::Deref for StdinSender {
+	type Target = StdinChannel;
+
+	fn deref(&self) -> &StdinChannel {
+		&self.channel
+	}
+}
+
+impl Drop for StdinSender {
+	fn drop(&mut self) {
+		self.channel.fail(ErrorKind::ConnectionAborted);
+	}
+}
+
+/// Reads the `StdIn` stream of a request asynchronously.
+///
+/// Returned by [`Request::stdin_reader`](crate::Request::stdin_reader). If
+/// streaming is enabled via
+/// [`Requests::stream_stdin`](crate::Requests::stream_stdin), the
+/// reader waits for the `StdIn` records that are still being received.
+/// Otherwise it returns the buffered body and never waits.
+///
+/// If the stream can not be received completely, because the request was
+/// aborted, the body got too large or the connection was closed, reading
+/// fails after the data received so far was returned.
+#[derive(Debug)]
+pub struct StdinReader<'a> {
+	stdin: OwnedInStream<'a>,
+	channel: Option<&'a StdinChannel>
+}
+
+impl <'a> StdinReader<'a> {
+	pub(crate) fn new(stdin: OwnedInStream<'a>, channel: Option<&'a StdinChannel>) -> Self {
+		Self {
+			stdin,
+			channel
+		}
+	}
+}
+
+impl AsyncRead for StdinReader<'_> {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+		let this = self.get_mut();
+
+		// After waiting for the complete stream the data was moved into the buffered stream.
+		match this.channel {
+			Some(channel) if !this.stdin.is_done() => channel.poll_read(cx, buf),
+			_ => {
+				let length = this.stdin.read(buf.initialize_unfilled())?;
+				buf.advance(length);
+
+				Poll::Ready(Ok(()))
+			}
+		}
+	}
+}
+
+impl <W: AsyncWrite + Unpin> Request<W> {
+	/// Returns an asynchronous reader for the StdIn stream of this request.
+	///
+	/// If streaming is enabled via [`Requests::stream_stdin`], the reader
+	/// returns the body while it is received from the web-server. Otherwise it
+	/// returns the buffered body like [`get_stdin`](Request::get_stdin) does.
+	/// Reading fails if the body could not be received completely, for example
+	/// because the request was aborted or the connection was closed.
+	///
+	/// The reader locks the StdIn stream like [`get_stdin`](Request::get_stdin).
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink, AsyncReadExt};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// # let mut requests = Requests::new(empty(), sink(), 1, 1).stream_stdin(true);
+	/// # if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+	/// request.spawn_processor(|request| async move {
+	///   let mut body = Vec::new();
+	///   if request.stdin_reader().read_to_end(&mut body).await.is_err() {
+	///     return RequestResult::Complete(1);
+	///   }
+	///
+	///   RequestResult::Complete(0)
+	/// });
+	/// # } }
+	/// ```
+	pub fn stdin_reader(&self) -> StdinReader<'_> {
+		StdinReader::new(self.get_stdin(), self.stdin_channel.as_deref())
+	}
+}
+
+impl <R: AsyncRead + Unpin + Send, W: AsyncWrite + Unpin + Send> Requests<R, W> {
+	/// Returns responders before their `StdIn` stream was received.
+	///
+	/// By default a request is only returned by [`next`](Requests::next) after
+	/// its body was received completely. If streaming is enabled, responders
+	/// are returned as soon as their parameters are complete. The body is
+	/// passed to the processor while it is received. It is read via
+	/// [`Request::stdin_reader`] or after waiting for it via
+	/// [`Request::wait_stdin_complete`]. Authorizers and filters are not
+	/// affected.
+	///
+	/// The `StdIn` records are read by [`next`](Requests::next). Therefore the
+	/// processor must run concurrently to it, for example via
+	/// [`Request::spawn_processor`] or [`serve_concurrent`](Requests::serve_concurrent).
+	/// A processor that is awaited before `next` is called again waits for the
+	/// body forever.
+	///
+	/// [`max_body_size`](Requests::max_body_size) still limits the body. It
+	/// bounds the data that was received but not read by the processor yet,
+	/// too. If the limit is exceeded, reading the body fails and
+	/// [`next`](Requests::next) returns
+	/// [`BodySizeExceeded`](crate::Error::BodySizeExceeded). The processor ends
+	/// the request. [`stdin_read_timeout`](Requests::stdin_read_timeout) does
+	/// not apply to streamed bodies. If the request is aborted or the
+	/// connection ends before the body was received, reading it fails, too.
+	/// The connection is kept open until the bodies of the returned requests
+	/// were received, even if the web-server did not set `FCGI_KEEP_CONN`.
+	///
+	/// # Example
+	///
+	/// ```rust
+	/// # use tokio::io::{empty, sink};
+	/// # use tokio_fastcgi::Requests;
+	/// let requests = Requests::new(empty(), sink(), 10, 10)
+	///   .max_body_size(16 * 1024 * 1024)
+	///   .stream_stdin(true);
+	/// ```
+	pub fn stream_stdin(mut self, stream: bool) -> Self {
+		self.stream_stdin = stream;
+		self
+	}
+
+	/// Returns `true` if the body of a returned request is still received.
+	pub(crate) fn stdin_pending(&self) -> bool {
+		self.streaming.values().any(StdinSender::is_read)
+	}
+}
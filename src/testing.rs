@@ -0,0 +1,331 @@
+//! Helpers for testing request processors without a web-server.
+//!
+//! A [`Request`] can be constructed in memory via [`Request::test_builder`].
+//! The records it produces are captured by a [`CapturedOutput`] instance
+//! that can be inspected after the request was processed.
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncWrite, DuplexStream, ReadHalf, WriteHalf};
+
+use crate::{write_length, Category, LineEnding, Record, Request, Requests, ResponseConfig, SharedWriter, RequestId, Role, StdReqType, StdRespType, FCGI_KEEP_CONN, RECORD_HEADER_SIZE};
+
+/// Maximum content length of a single record.
+const MAX_CONTENT_LENGTH: usize = u16::MAX as usize;
+
+/// Builds a [`Request`] in memory for testing request processors.
+///
+/// The request is assembled from the same records a web-server would send.
+/// An instance is created by calling [`Request::test_builder`].
+///
+/// ## Example
+///
+/// ```rust
+/// # use tokio_fastcgi::{Request, RequestResult, Role};
+/// # #[tokio::main]
+/// # async fn main() {
+/// let (request, output) = Request::test_builder()
+///   .role(Role::Responder)
+///   .param("REQUEST_METHOD", "POST")
+///   .stdin(b"Hello")
+///   .build();
+///
+/// request.process(|request| async move {
+///   let method = request.get_str_param("REQUEST_METHOD").unwrap().to_string();
+///   request.get_stdout().write(method.as_bytes()).await.unwrap();
+///
+///   RequestResult::Complete(0)
+/// }).await.unwrap();
+///
+/// assert_eq!(output.stdout(), b"POST");
+/// assert_eq!(output.end_request(), Some((0, 0)));
+/// # }
+/// ```
+pub struct RequestBuilder {
+	role: Role,
+	request_id: RequestId,
+	keep_connection: bool,
+	params: Vec<(Vec<u8>, Vec<u8>)>,
+	stdin: Vec<u8>,
+	data: Vec<u8>,
+	response_config: ResponseConfig
+}
+
+impl RequestBuilder {
+	fn new() -> Self {
+		Self {
+			role: Role::Responder,
+			request_id: 1,
+			keep_connection: false,
+			params: Vec::new(),
+			stdin: Vec::new(),
+			data: Vec::new(),
+			response_config: ResponseConfig::default()
+		}
+	}
+
+	/// Sets the role of the request. The default is [`Role::Responder`].
+	pub fn role(mut self, role: Role) -> Self {
+		self.role = role;
+		self
+	}
+
+	/// Sets the request id of the request. The default is `1`.
+	pub fn request_id(mut self, request_id: u16) -> Self {
+		self.request_id = request_id;
+		self
+	}
+
+	/// Sets the `FCGI_KEEP_CONN` flag of the request. The default is `false`.
+	pub fn keep_connection(mut self, keep_connection: bool) -> Self {
+		self.keep_connection = keep_connection;
+		self
+	}
+
+	/// Adds a parameter to the request.
+	pub fn param<V: AsRef<[u8]>>(mut self, name: &str, value: V) -> Self {
+		self.params.push((name.as_bytes().to_vec(), value.as_ref().to_vec()));
+		self
+	}
+
+	/// Appends `stdin` to the data that is sent via the `StdIn` stream.
+	///
+	/// Authorizers do not get a `StdIn` stream. The data is ignored for them.
+	pub fn stdin<D: AsRef<[u8]>>(mut self, stdin: D) -> Self {
+		self.stdin.extend_from_slice(stdin.as_ref());
+		self
+	}
+
+	/// Appends `data` to the data that is sent via the `Data` stream.
+	///
+	/// Only filters get a `Data` stream. The data is ignored for all other
+	/// roles.
+	pub fn data<D: AsRef<[u8]>>(mut self, data: D) -> Self {
+		self.data.extend_from_slice(data.as_ref());
+		self
+	}
+
+	/// Sets the line ending of the generated headers. The default is
+	/// [`LineEnding::CrLf`].
+	///
+	/// See [`Requests::header_line_ending`](crate::Requests::header_line_ending).
+	pub fn header_line_ending(mut self, line_ending: LineEnding) -> Self {
+		self.response_config.line_ending = line_ending;
+		self
+	}
+
+	/// Checks the `Content-Length` header of the response.
+	///
+	/// See [`Requests::check_content_length`](crate::Requests::check_content_length).
+	pub fn check_content_length(mut self, check: bool) -> Self {
+		self.response_config.check_content_length = check;
+		self
+	}
+
+	/// Limits the number of headers of the response.
+	///
+	/// See [`Requests::max_response_headers`](crate::Requests::max_response_headers).
+	pub fn max_response_headers(mut self, max_headers: usize) -> Self {
+		self.response_config.max_response_headers = Some(max_headers);
+		self
+	}
+
+	/// Sets a `Content-Type` for responses that do not specify one.
+	///
+	/// See [`Requests::default_content_type`](crate::Requests::default_content_type).
+	pub fn default_content_type(mut self, content_type: &str) -> Self {
+		self.response_config.set_default_content_type(content_type);
+		self
+	}
+
+	/// Builds the request and returns it together with a [`CapturedOutput`]
+	/// instance that receives all records written by the request.
+	pub fn build(self) -> (Request<CapturedOutput>, CapturedOutput) {
+		let output = CapturedOutput::default();
+
+		(self.build_with_writer(output.clone()), output)
+	}
+
+	/// Builds the request and makes it write its records to `writer`.
+	///
+	/// This allows the records to be sent to a custom sink.
+	pub fn build_with_writer<W: AsyncWrite + Unpin>(self, writer: W) -> Request<W> {
+		let request_id = self.request_id;
+		let record = |record_type, content: &[u8]| Record {
+			record_type: Category::Std(record_type),
+			request_id,
+			content: content.to_vec()
+		};
+
+		let mut begin_request = self.role.number().to_be_bytes().to_vec();
+		begin_request.push(if self.keep_connection { FCGI_KEEP_CONN } else { 0 });
+		begin_request.extend_from_slice(&[0u8; 5]);
+
+		let has_stdin = self.role != Role::Authorizer;
+		let has_data = self.role == Role::Filter;
+
+		let mut request = Request::new(&record(StdReqType::BeginRequest, &begin_request), Arc::new(SharedWriter::new(writer)), None, self.response_config, false)
+			.expect("The begin request record is valid.");
+
+		let mut records = Vec::new();
+		for (name, value) in &self.params {
+			let mut content = Vec::with_capacity(name.len() + value.len() + 8);
+			write_length(&mut content, name.len());
+			write_length(&mut content, value.len());
+			content.extend_from_slice(name);
+			content.extend_from_slice(value);
+			records.push(record(StdReqType::Params, &content));
+		}
+		records.push(record(StdReqType::Params, &[]));
+
+		if has_data {
+			records.extend(self.data.chunks(MAX_CONTENT_LENGTH).map(|chunk| record(StdReqType::Data, chunk)));
+			records.push(record(StdReqType::Data, &[]));
+		}
+
+		if has_stdin {
+			records.extend(self.stdin.chunks(MAX_CONTENT_LENGTH).map(|chunk| record(StdReqType::StdIn, chunk)));
+			records.push(record(StdReqType::StdIn, &[]));
+		}
+
+		for record in records {
+			request.update(&record).expect("The records are valid.");
+		}
+
+		request
+	}
+}
+
+/// Captures the records written by a [`Request`] built via the
+/// [`RequestBuilder`].
+///
+/// All clones of an instance share the same buffer. The methods of this struct
+/// decode the captured records to make inspecting the output easy.
+#[derive(Clone, Default, Debug)]
+pub struct CapturedOutput {
+	buffer: Arc<std::sync::Mutex<Vec<u8>>>
+}
+
+impl CapturedOutput {
+	/// Returns the raw bytes of all captured records.
+	pub fn bytes(&self) -> Vec<u8> {
+		self.buffer.lock().unwrap().clone()
+	}
+
+	/// Returns the type, request id and content of every captured record.
+	pub fn records(&self) -> Vec<(u8, u16, Vec<u8>)> {
+		let buffer = self.buffer.lock().unwrap();
+		let mut records = Vec::new();
+		let mut rest = &buffer[..];
+
+		while rest.len() >= RECORD_HEADER_SIZE {
+			let content_length = u16::from_be_bytes([rest[4], rest[5]]) as usize;
+			let record_length = RECORD_HEADER_SIZE + content_length + rest[6] as usize;
+			if rest.len() < record_length {
+				break;
+			}
+
+			records.push((rest[1], u16::from_be_bytes([rest[2], rest[3]]), rest[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + content_length].to_vec()));
+			rest = &rest[record_length..];
+		}
+
+		records
+	}
+
+	/// Returns the concatenated content of all `StdOut` records.
+	pub fn stdout(&self) -> Vec<u8> {
+		self.stream(StdRespType::StdOut)
+	}
+
+	/// Returns the concatenated content of all `StdErr` records.
+	pub fn stderr(&self) -> Vec<u8> {
+		self.stream(StdRespType::StdErr)
+	}
+
+	/// Returns the application status and the protocol status of the
+	/// `EndRequest` record, if one was captured.
+	pub fn end_request(&self) -> Option<(u32, u8)> {
+		self.records().into_iter()
+			.find(|(record_type, _, content)| *record_type == u8::from(StdRespType::EndRequest) && content.len() >= 5)
+			.map(|(_, _, content)| (u32::from_be_bytes([content[0], content[1], content[2], content[3]]), content[4]))
+	}
+
+	fn stream(&self, record_type: StdRespType) -> Vec<u8> {
+		self.records().into_iter()
+			.filter(|(rt, _, _)| *rt == u8::from(record_type))
+			.flat_map(|(_, _, content)| content)
+			.collect()
+	}
+}
+
+impl AsyncWrite for CapturedOutput {
+	fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, std::io::Error>> {
+		self.buffer.lock().unwrap().extend_from_slice(buf);
+		Poll::Ready(Ok(buf.len()))
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+		Poll::Ready(Ok(()))
+	}
+}
+
+impl Request<CapturedOutput> {
+	/// Returns a [`RequestBuilder`] to construct a request in memory.
+	///
+	/// This is useful to unit test request processors without having to run
+	/// a web-server. See [`RequestBuilder`] for an example.
+	pub fn test_builder() -> RequestBuilder {
+		RequestBuilder::new()
+	}
+}
+
+impl Requests<ReadHalf<DuplexStream>, WriteHalf<DuplexStream>> {
+	/// Creates a `Requests` instance that is connected to an in memory client.
+	///
+	/// The returned [`DuplexStream`] is the side of the web-server. The FastCGI
+	/// records written into it are received by the returned `Requests`
+	/// instance and the responses can be read from it. `max_buf_size` is the
+	/// number of bytes each direction buffers before the writer has to wait
+	/// for the reader. See [`tokio::io::duplex`].
+	///
+	/// The instance announces one connection and one concurrent request. All
+	/// builder methods of [`Requests`] can be applied to the returned
+	/// instance. Dropping the client side ends the connection.
+	///
+	/// ## Example
+	///
+	/// ```rust
+	/// # use tokio::io::{AsyncReadExt, AsyncWriteExt};
+	/// # use tokio_fastcgi::{Requests, RequestResult};
+	/// # #[tokio::main]
+	/// # async fn main() {
+	/// let (mut requests, mut client) = Requests::new_duplex(64 * 1024);
+	///
+	/// // BeginRequest (Responder), empty Params and empty StdIn for request 1
+	/// client.write_all(&[1, 1, 0, 1, 0, 8, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await.unwrap();
+	/// client.write_all(&[1, 4, 0, 1, 0, 0, 0, 0]).await.unwrap();
+	/// client.write_all(&[1, 5, 0, 1, 0, 0, 0, 0]).await.unwrap();
+	///
+	/// while let Some(request) = requests.next().await.unwrap() {
+	///   request.process(|request| async move {
+	///     request.get_stdout().write(b"Hello").await.unwrap();
+	///     RequestResult::Complete(0)
+	///   }).await.unwrap();
+	/// }
+	/// drop(requests);
+	///
+	/// let mut response = Vec::new();
+	/// client.read_to_end(&mut response).await.unwrap();
+	/// assert_eq!(&response[..13], &[1, 6, 0, 1, 0, 5, 0, 0, b'H', b'e', b'l', b'l', b'o']);
+	/// # }
+	/// ```
+	pub fn new_duplex(max_buf_size: usize) -> (Self, DuplexStream) {
+		let (client, server) = tokio::io::duplex(max_buf_size);
+
+		(Self::from_split_socket(tokio::io::split(server), 1, 1), client)
+	}
+}
@@ -0,0 +1,101 @@
+//! This source file verifies the `Client` against the server side of this
+//! library.
+//!
+//! Both sides are connected via an in memory connection.
+use std::time::Duration;
+use tokio_fastcgi::{Client, Error, Requests, RequestResult};
+
+#[tokio::test]
+async fn get_values() {
+	let (mut requests, connection) = Requests::new_duplex(64 * 1024);
+	let server = tokio::spawn(async move { requests.next().await.map(|request| request.is_none()) });
+
+	let mut client = Client::from_split_socket(tokio::io::split(connection));
+
+	let values = client.get_values(&["FCGI_MAX_CONNS", "FCGI_MAX_REQS", "FCGI_MPXS_CONNS", "FCGI_UNKNOWN"]).await.unwrap();
+	assert_eq!(values.len(), 3);
+	assert_eq!(values["FCGI_MAX_CONNS"], b"1");
+	assert_eq!(values["FCGI_MAX_REQS"], b"1");
+	assert_eq!(values["FCGI_MPXS_CONNS"], b"1");
+
+	// The connection stays usable for further queries.
+	let values = client.get_values(&["FCGI_MPXS_CONNS"]).await.unwrap();
+	assert_eq!(values.len(), 1);
+
+	// Closing the connection ends the request loop of the server.
+	drop(client);
+	assert!(server.await.unwrap().unwrap());
+}
+
+#[tokio::test(start_paused = true)]
+async fn multiplexed_requests() {
+	let (connection, server) = tokio::io::duplex(64 * 1024);
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 3);
+
+	// Every request is answered with its name after a delay that is given in milliseconds.
+	let server = tokio::spawn(async move {
+		while let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+			request.spawn_processor(|request| async move {
+				let delay = request.get_str_param("DELAY").unwrap().parse().unwrap();
+				tokio::time::sleep(Duration::from_millis(delay)).await;
+
+				let name = request.get_param("NAME").unwrap().clone();
+				request.get_stdout().write(&name).await.unwrap();
+
+				RequestResult::Complete(delay as u32)
+			});
+		}
+	});
+
+	let mut client = Client::from_split_socket(tokio::io::split(connection));
+	client.get_values(&["FCGI_MAX_REQS"]).await.unwrap();
+
+	// The first request finishes last.
+	let mut sent = Vec::new();
+	for (name, delay) in [("first", "30"), ("second", "20"), ("third", "10")] {
+		let request_id = client.send_request(&[("NAME", name.as_bytes()), ("DELAY", delay.as_bytes())], &[]).await.unwrap();
+		sent.push((request_id, name));
+	}
+
+	// All ids are distinct.
+	let mut ids: Vec<u16> = sent.iter().map(|(request_id, _)| *request_id).collect();
+	ids.sort();
+	ids.dedup();
+	assert_eq!(ids.len(), 3);
+
+	// The application advertised a maximum of three requests.
+	assert!(matches!(client.send_request(&[], &[]).await, Err(Error::RequestIdsExhausted)));
+
+	// The responses are routed to the requests they belong to.
+	let mut received = Vec::new();
+	while let Some(response) = client.next_response().await.unwrap() {
+		let name = sent.iter().find(|(request_id, _)| *request_id == response.request_id).unwrap().1;
+		assert_eq!(response.stdout, name.as_bytes());
+		assert_eq!(response.protocol_status, 0);
+		received.push(response.request_id);
+	}
+	assert_eq!(received, vec![sent[2].0, sent[1].0, sent[0].0]);
+
+	// Ids of finished requests are reused.
+	let request_id = client.send_request(&[("NAME", b"fourth"), ("DELAY", b"0")], &[]).await.unwrap();
+	assert!(ids.contains(&request_id));
+	assert_eq!(client.next_response().await.unwrap().unwrap().stdout, b"fourth");
+
+	drop(client);
+	server.await.unwrap();
+}
+
+#[tokio::test]
+async fn failed_send_releases_id() {
+	let (connection, server) = tokio::io::duplex(64 * 1024);
+	drop(server);
+
+	let mut client = Client::from_split_socket(tokio::io::split(connection));
+
+	// The request can not be sent. Its id does not stay in flight.
+	assert!(matches!(client.send_request(&[], &[]).await, Err(Error::IoError(_))));
+	assert!(client.next_response().await.unwrap().is_none());
+
+	// The only id is free again.
+	assert!(matches!(client.send_request(&[], &[]).await, Err(Error::IoError(_))));
+}
@@ -7,13 +7,13 @@
 //! The tests implement the data structures of the FastCGI protocol without
 //! using the structures and enums from `lib.rs` to make sure errors are not
 //! canceled out by the same error within the test suite.
-use tokio_fastcgi::{Request, RequestResult, Role};
+use tokio_fastcgi::{Error, Request, RequestResult, Requests, Role};
 use tokio_test::io::{Builder, Mock};
 use std::sync::Arc;
 use std::time::Duration;
 use std::io::Read;
 use std::convert::From;
-use tokio::io::AsyncWrite;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
 use async_trait::async_trait;
 
 pub enum RecordType {
@@ -48,15 +48,39 @@ pub fn create_record(request_type: RecordType, request_id: u8, padding: u8, data
 	let mut record = vec![0x01, request_type as u8, 0x00, request_id, (content_length >> 8 & 0xFF) as u8, (content_length & 0xFF) as u8, padding, 0x00];
 
 	record.extend_from_slice(data);
-	record.extend_from_slice(&*vec![0u8; padding as usize]);
+	record.extend_from_slice(&vec![0u8; padding as usize]);
 
 	record
 }
 
+/// Reads one fastcgi-record and returns its type, request id and content.
+///
+/// If the stream is closed before a complete record could be read `None` is
+/// returned.
+pub async fn read_record<R: AsyncRead + Unpin>(rd: &mut R) -> Option<(u8, u16, Vec<u8>)> {
+	let mut header = [0u8; 8];
+	rd.read_exact(&mut header).await.ok()?;
+	assert_eq!(header[0], 1, "Invalid record version.");
+
+	let request_id = u16::from_be_bytes([header[2], header[3]]);
+	let mut content = vec![0u8; u16::from_be_bytes([header[4], header[5]]) as usize];
+	let mut padding = vec![0u8; header[6] as usize];
+	rd.read_exact(&mut content).await.ok()?;
+	rd.read_exact(&mut padding).await.ok()?;
+
+	Some((header[1], request_id, content))
+}
+
 #[async_trait]
 pub trait TestCase {
 	fn get_input() -> Mock;
 	fn get_output() -> Mock;
+
+	/// Configures the connection the test runs on.
+	fn configure<R: AsyncRead + Unpin + Send, W: AsyncWrite + Unpin + Send>(requests: Requests<R, W>) -> Requests<R, W> {
+		requests
+	}
+
 	async fn processor<W: AsyncWrite + Unpin + Send>(request: Arc<Request<W>>) -> RequestResult;
 }
 
@@ -83,17 +107,23 @@ impl TestCase for TestParamsInOut {
 	}
 
 	async fn processor<W: AsyncWrite + Unpin + Send>(request: Arc<Request<W>>) -> RequestResult {
+		// The reserved bytes of the BeginRequest record are zero.
+		assert_eq!(request.begin_reserved(), [0u8; 5]);
+
+		// BeginRequest, two Params and two StdIn records were received.
+		assert_eq!(request.record_count(), 5);
+
 		// Check the parameters
 		let sp = request.get_param("SERVER_PORT");
 		assert!(sp.is_some());
-		assert_eq!(sp.unwrap(), &[b'8', b'0']);
+		assert_eq!(sp.unwrap(), b"80");
 		let sp = request.get_str_param("SERVER_PORT");
 		assert!(sp.is_some());
 		assert_eq!(sp.unwrap(), "80");
 
 		let tst = request.get_param("TEST");
 		assert!(tst.is_some());
-		assert_eq!(tst.unwrap(), &[b'Y', b'E', b'S']);
+		assert_eq!(tst.unwrap(), b"YES");
 		let tst = request.get_str_param("TEST");
 		assert!(tst.is_some());
 		assert_eq!(tst.unwrap(), "YES");
@@ -112,9 +142,13 @@ impl TestCase for TestParamsInOut {
 		assert_eq!(params[0].0, "noutf8");
 		assert_eq!(params[0].1, &[b'N', b'O', 0xF0]);
 		assert_eq!(params[1].0, "server_port");
-		assert_eq!(params[1].1, &[b'8', b'0']);
+		assert_eq!(params[1].1, b"80");
 		assert_eq!(params[2].0, "test");
-		assert_eq!(params[2].1, &[b'Y', b'E', b'S']);
+		assert_eq!(params[2].1, b"YES");
+
+		// Test the ordered params iterator
+		let params: Vec<(&str, &[u8])> = request.params_iter_ordered().unwrap().collect();
+		assert_eq!(params, vec![("server_port", &b"80"[..]), ("test", &b"YES"[..]), ("noutf8", &[b'N', b'O', 0xF0][..])]);
 
 		// Test the string params iterator
 		let mut params: Vec<(&str, Option<&str>)> = request.str_params_iter().unwrap().collect();
@@ -127,6 +161,20 @@ impl TestCase for TestParamsInOut {
 		assert_eq!(params[2].0, "test");
 		assert_eq!(params[2].1, Some("YES"));
 
+		// Test the params view
+		let params = request.params();
+		assert_eq!(params.len(), 3);
+		assert!(!params.is_empty());
+		assert!(params.contains("SERVER_PORT"));
+		assert!(params.contains("test"));
+		assert!(!params.contains("SERVER_DUMMY"));
+		assert_eq!(params.get("Server_Port"), Some(&b"80"[..]));
+		assert_eq!(params.get_str("TEST"), Some("YES"));
+		assert_eq!(params.get("NOUTF8"), Some(&[b'N', b'O', 0xF0][..]));
+		assert_eq!(params.get_str("NOUTF8"), None);
+		assert_eq!(params.get("SERVER_DUMMY"), None);
+		assert_eq!(params.iter().collect::<Vec<_>>(), vec![("server_port", &b"80"[..]), ("test", &b"YES"[..]), ("noutf8", &[b'N', b'O', 0xF0][..])]);
+
 		// Check if stdin is valid
 		let mut stdin = [0u8; 100];
 		assert!(request.get_stdin().read_exact(&mut stdin).is_ok());
@@ -198,6 +246,7 @@ impl TestCase for TestRoleFilter {
 
 	async fn processor<W: AsyncWrite + Unpin + Send>(request: Arc<Request<W>>) -> RequestResult {
 		assert_eq!(request.role, Role::Filter);
+		assert_eq!(request.begin_reserved(), [0u8; 5]);
 
 		// Check the parameters
 		let last_mod = request.get_param("FCGI_DATA_LAST_MOD");
@@ -217,6 +266,77 @@ impl TestCase for TestRoleFilter {
 	}
 }
 
+pub struct TestRoleFilterSplitData {}
+
+#[async_trait]
+impl TestCase for TestRoleFilterSplitData {
+	fn get_input() -> Mock {
+		// The second data record arrives in three reads. The first one ends within the header.
+		let second_data = create_record(RecordType::Data, 0x01, 0x02, b"_IS_DATA");
+
+		Builder::new()
+			.read(&create_record(RecordType::BeginRequest, 0x01, 0x00, &[ 0x00, RecordRole::Filter as u8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]))
+			.read(&create_record(RecordType::Params, 0x01, 0x00, b"\x10\x02FCGI_DATA_LENGTH12"))
+			.read(&create_record(RecordType::Params, 0x01, 0x00, &[]))
+			.read(&create_record(RecordType::Data, 0x01, 0x00, b"THIS"))
+			.read(&second_data[..5])
+			.read(&second_data[5..12])
+			.read(&second_data[12..])
+			.read(&create_record(RecordType::StdIn, 0x01, 0x00, &[]))
+			.read(&create_record(RecordType::Data, 0x01, 0x00, &[]))
+			.build()
+	}
+
+	fn get_output() -> Mock {
+		Builder::new()
+			.write(&[ 1u8, RecordType::StdOut as u8, 0, 1, 0, 12, 0, 0])
+			.write(b"THIS_IS_DATA")
+			.write(&[ 1u8, RecordType::StdOut as u8, 0, 1, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::StdErr as u8, 0, 1, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::EndRequest as u8, 0, 1, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+			.build()
+	}
+
+	async fn processor<W: AsyncWrite + Unpin + Send>(request: Arc<Request<W>>) -> RequestResult {
+		assert_eq!(request.role, Role::Filter);
+		assert_eq!(request.get_str_param("FCGI_DATA_LENGTH"), Some("12"));
+
+		let mut data = Vec::new();
+		assert!(request.get_data().read_to_end(&mut data).is_ok());
+		assert_eq!(data, b"THIS_IS_DATA");
+
+		// Echo the reassembled data
+		request.get_stdout().write(&data).await.unwrap();
+		RequestResult::Complete(0x00)
+	}
+}
+
+pub struct TestRoleFilterTakeData {}
+
+#[async_trait]
+impl TestCase for TestRoleFilterTakeData {
+	fn get_input() -> Mock {
+		TestRoleFilter::get_input()
+	}
+
+	fn get_output() -> Mock {
+		TestRoleFilter::get_output()
+	}
+
+	async fn processor<W: AsyncWrite + Unpin + Send>(request: Arc<Request<W>>) -> RequestResult {
+		assert_eq!(request.take_data(), Some(b"THIS_IS_DATA".to_vec()));
+
+		// The data can only be taken once.
+		assert_eq!(request.take_data(), None);
+
+		let mut data = Vec::new();
+		assert!(request.get_data().read_to_end(&mut data).is_ok());
+		assert!(data.is_empty());
+
+		RequestResult::Complete(0x00)
+	}
+}
+
 pub struct TestAbortRequest {}
 
 #[async_trait]
@@ -292,9 +412,8 @@ impl TestCase for TestUnknownRoleReturn {
 	}
 
 	fn get_output() -> Mock {
+		// The request is rejected without output. Only the EndRequest record is sent.
 		Builder::new()
-			.write(&[ 1u8, RecordType::StdOut as u8, 0, 1, 0, 0, 0, 0 ])
-			.write(&[ 1u8, RecordType::StdErr as u8, 0, 1, 0, 0, 0, 0])
 			.write(&[ 1u8, RecordType::EndRequest as u8, 0, 1, 0, 8, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0])
 			.build()
 	}
@@ -423,4 +542,474 @@ impl TestCase for TestKeepConnection {
 		request.get_stderr().write(&[b'X', idx.as_bytes()[0] - b'1' + b'A']).await.unwrap();
 		RequestResult::Complete(0x11223344 * idx.parse().unwrap_or(0))
 	}
-}
\ No newline at end of file
+}
+
+pub struct TestRespondWith {}
+
+#[async_trait]
+impl TestCase for TestRespondWith {
+	fn get_input() -> Mock {
+		let mut builder = Builder::new();
+		for request_id in 1..=4 {
+			builder
+				.read(&create_record(RecordType::BeginRequest, request_id, 0x00, &[ 0x00, RecordRole::Responder as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00]))
+				.read(&create_record(RecordType::Params, request_id, 0x00, &[]))
+				.read(&create_record(RecordType::StdIn, request_id, 0x00, &[]));
+		}
+
+		builder.build()
+	}
+
+	fn get_output() -> Mock {
+		let mut builder = Builder::new();
+		for request_id in 1..=4 {
+			match request_id {
+				/*Buffered responses get a Content-Length*/1 => builder.write(&create_record(RecordType::StdOut, request_id, 0x00, b"Status: 503 Service Unavailable\r\nContent-Type: text/plain\r\nRetry-After: 10\r\nContent-Length: 15\r\n\r\nTry again later")),
+				/*Content-Length is not duplicated*/2 => builder.write(&create_record(RecordType::StdOut, request_id, 0x00, b"Status: 200 OK\r\ncontent-length: 5\r\n\r\nHello")),
+				/*JSON is buffered, too*/3 => builder.write(&create_record(RecordType::StdOut, request_id, 0x00, b"Status: 201 Created\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n{}")),
+				/*Streamed responses have no Content-Length*/_ => builder
+					.write(&create_record(RecordType::StdOut, request_id, 0x00, b"Status: 200 OK\r\n\r\n"))
+					.write(&create_record(RecordType::StdOut, request_id, 0x00, b"Hello"))
+			};
+
+			builder
+				.write(&create_record(RecordType::StdOut, request_id, 0x00, &[]))
+				.write(&create_record(RecordType::StdErr, request_id, 0x00, &[]))
+				.write(&create_record(RecordType::EndRequest, request_id, 0x00, &[0, 0, 0, (request_id == 1) as u8, 0, 0, 0, 0]));
+		}
+
+		builder.build()
+	}
+
+	async fn processor<W: AsyncWrite + Unpin + Send>(request: Arc<Request<W>>) -> RequestResult {
+		match request.get_request_id() {
+			1 => {
+				let result = request.respond_with(503, &[("Content-Type", "text/plain"), ("Retry-After", "10")], b"Try again later").await.unwrap();
+				assert_eq!(result, RequestResult::Complete(1));
+
+				result
+			},
+			2 => request.respond_with(200, &[("content-length", "5")], b"Hello").await.unwrap(),
+			3 => request.respond_json(201, "{}").await.unwrap(),
+			_ => {
+				let mut stdout = request.get_stdout();
+				stdout.write(b"Status: 200 OK\r\n\r\n").await.unwrap();
+				stdout.write(b"Hello").await.unwrap();
+				RequestResult::Complete(0)
+			}
+		}
+	}
+}
+
+pub struct TestRejectTooLarge {}
+
+#[async_trait]
+impl TestCase for TestRejectTooLarge {
+	fn get_input() -> Mock {
+		Builder::new()
+			.read(&create_record(RecordType::BeginRequest, 0x01, 0x00, &[ 0x00, RecordRole::Responder as u8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]))
+			.read(&create_record(RecordType::Params, 0x01, 0x00, b"\x0E\x07CONTENT_LENGTH1000000"))
+			.read(&create_record(RecordType::Params, 0x01, 0x00, &[]))
+			.read(&create_record(RecordType::StdIn, 0x01, 0x00, &(0..100u8).collect::<Vec<u8>>()[..] ))
+			.read(&create_record(RecordType::StdIn, 0x01, 0x00, &[]))
+			.build()
+	}
+
+	fn get_output() -> Mock {
+		Builder::new()
+			.write(&create_record(RecordType::StdOut, 0x01, 0x00, b"Status: 413 Payload Too Large\r\nContent-Length: 0\r\n\r\n"))
+			.write(&[ 1u8, RecordType::StdOut as u8, 0, 1, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::StdErr as u8, 0, 1, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::EndRequest as u8, 0, 1, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+			.build()
+	}
+
+	async fn processor<W: AsyncWrite + Unpin + Send>(request: Arc<Request<W>>) -> RequestResult {
+		assert_eq!(request.content_length(), Some(1000000));
+
+		// Within the limit nothing is sent.
+		assert!(request.reject_if_too_large(1000000).await.unwrap().is_none());
+
+		if let Some(result) = request.reject_if_too_large(1024).await.unwrap() {
+			// The body must not have been touched.
+			let mut stdin = Vec::new();
+			assert_eq!(request.get_stdin().read_to_end(&mut stdin).unwrap(), 100);
+
+			return result;
+		}
+
+		unreachable!("The request should have been rejected.");
+	}
+}
+
+pub struct TestTrickledStdIn {}
+
+#[async_trait]
+impl TestCase for TestTrickledStdIn {
+	fn get_input() -> Mock {
+		Builder::new()
+			.read(&create_record(RecordType::BeginRequest, 0x01, 0x00, &[ 0x00, RecordRole::Responder as u8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]))
+			.read(&create_record(RecordType::Params, 0x01, 0x00, &[]))
+			/*Body in three parts*/.read(&create_record(RecordType::StdIn, 0x01, 0x00, b"THIS_"))
+			/*Random Delay*/.wait(Duration::from_millis(50))
+			/*Record torn apart*/.read(&create_record(RecordType::StdIn, 0x01, 0x02, b"IS_")[..5])
+			/*Random Delay*/.wait(Duration::from_millis(50))
+			/*Record torn apart*/.read(&create_record(RecordType::StdIn, 0x01, 0x02, b"IS_")[5..])
+			/*Random Delay*/.wait(Duration::from_millis(50))
+			.read(&create_record(RecordType::StdIn, 0x01, 0x00, b"STDIN"))
+			/*Random Delay*/.wait(Duration::from_millis(50))
+			.read(&create_record(RecordType::StdIn, 0x01, 0x00, &[]))
+			.build()
+	}
+
+	fn get_output() -> Mock {
+		Builder::new()
+			.write(&create_record(RecordType::StdOut, 0x01, 0x00, b"THIS_IS_STDIN"))
+			.write(&[ 1u8, RecordType::StdOut as u8, 0, 1, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::StdErr as u8, 0, 1, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::EndRequest as u8, 0, 1, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+			.build()
+	}
+
+	async fn processor<W: AsyncWrite + Unpin + Send>(request: Arc<Request<W>>) -> RequestResult {
+		// The complete body is available as soon as the processor runs.
+		request.wait_stdin_complete().await;
+		let mut stdin = Vec::new();
+		request.get_stdin().read_to_end(&mut stdin).unwrap();
+
+		request.get_stdout().write(&stdin).await.unwrap();
+		RequestResult::Complete(0)
+	}
+}
+
+pub struct TestLongParams {}
+
+impl TestLongParams {
+	/// Returns the encoded name-value pairs. Names and values of more than 127
+	/// bytes use the 4-byte length encoding.
+	fn params() -> Vec<u8> {
+		let mut params = Vec::new();
+
+		// 1-byte name length, 4-byte value length
+		params.extend_from_slice(b"\x0B\x80\x00\x00\xC8HTTP_COOKIE");
+		params.extend_from_slice(&[b'C'; 200]);
+
+		// 4-byte name length, 1-byte value length
+		params.extend_from_slice(b"\x80\x00\x00\x82\x01");
+		params.extend_from_slice(&[b'N'; 130]);
+		params.push(b'1');
+
+		// Longest value with a 1-byte length and shortest value with a 4-byte length
+		params.extend_from_slice(b"\x05\x7FEDGE1");
+		params.extend_from_slice(&[b'7'; 127]);
+		params.extend_from_slice(b"\x05\x80\x00\x00\x80EDGE2");
+		params.extend_from_slice(&[b'8'; 128]);
+
+		params
+	}
+}
+
+#[async_trait]
+impl TestCase for TestLongParams {
+	fn get_input() -> Mock {
+		Builder::new()
+			.read(&create_record(RecordType::BeginRequest, 0x01, 0x00, &[ 0x00, RecordRole::Responder as u8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]))
+			.read(&create_record(RecordType::Params, 0x01, 0x03, &Self::params()))
+			.read(&create_record(RecordType::Params, 0x01, 0x00, &[]))
+			.read(&create_record(RecordType::StdIn, 0x01, 0x00, &[]))
+			.build()
+	}
+
+	fn get_output() -> Mock {
+		Builder::new()
+			.write(&[ 1u8, RecordType::StdOut as u8, 0, 1, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::StdErr as u8, 0, 1, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::EndRequest as u8, 0, 1, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+			.build()
+	}
+
+	async fn processor<W: AsyncWrite + Unpin + Send>(request: Arc<Request<W>>) -> RequestResult {
+		assert_eq!(request.get_param("HTTP_COOKIE").unwrap(), &[b'C'; 200]);
+		assert_eq!(request.get_param(&"N".repeat(130)).unwrap(), b"1");
+		assert_eq!(request.get_param("EDGE1").unwrap(), &[b'7'; 127]);
+		assert_eq!(request.get_param("EDGE2").unwrap(), &[b'8'; 128]);
+		assert_eq!(request.params_iter().unwrap().count(), 4);
+
+		RequestResult::Complete(0)
+	}
+}
+
+pub struct TestAbortByApplication {}
+
+#[async_trait]
+impl TestCase for TestAbortByApplication {
+	fn get_input() -> Mock {
+		Builder::new()
+			.read(&create_record(RecordType::BeginRequest, 0x01, 0x00, &[ 0x00, RecordRole::Responder as u8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]))
+			.read(&create_record(RecordType::Params, 0x01, 0x00, &[]))
+			.read(&create_record(RecordType::StdIn, 0x01, 0x00, &[]))
+			.build()
+	}
+
+	fn get_output() -> Mock {
+		Builder::new()
+			.write(&create_record(RecordType::StdOut, 0x01, 0x00, b"PARTIAL"))
+			.write(&[ 1u8, RecordType::StdOut as u8, 0, 1, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::StdErr as u8, 0, 1, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::EndRequest as u8, 0, 1, 0, 8, 0, 0, 0, 0, 0, 42, 0, 0, 0, 0])
+			.build()
+	}
+
+	async fn processor<W: AsyncWrite + Unpin + Send>(request: Arc<Request<W>>) -> RequestResult {
+		let mut stdout = request.get_stdout();
+		stdout.write(b"PARTIAL").await.unwrap();
+
+		request.abort(RequestResult::Complete(42)).await.unwrap();
+
+		// The request is ended. No more output is accepted.
+		assert!(matches!(stdout.write(b"MORE").await, Err(Error::StreamAlreadyClosed)));
+		assert!(matches!(request.get_stderr().write(b"MORE").await, Err(Error::StreamAlreadyClosed)));
+		assert!(matches!(request.abort(RequestResult::Complete(0)).await, Err(Error::StreamAlreadyClosed)));
+
+		// The result is discarded.
+		RequestResult::Complete(0)
+	}
+}
+
+pub struct TestDataForResponder {}
+
+#[async_trait]
+impl TestCase for TestDataForResponder {
+	fn get_input() -> Mock {
+		Builder::new()
+			.read(&create_record(RecordType::BeginRequest, 0x01, 0x00, &[ 0x00, RecordRole::Responder as u8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]))
+			.read(&create_record(RecordType::Params, 0x01, 0x00, &[]))
+			/*Not valid for responders*/.read(&create_record(RecordType::Data, 0x01, 0x00, b"IGNORED"))
+			.read(&create_record(RecordType::StdIn, 0x01, 0x00, b"STDIN"))
+			/*Not valid for responders*/.read(&create_record(RecordType::Data, 0x01, 0x00, &[]))
+			.read(&create_record(RecordType::StdIn, 0x01, 0x00, &[]))
+			.build()
+	}
+
+	fn get_output() -> Mock {
+		Builder::new()
+			.write(&create_record(RecordType::StdOut, 0x01, 0x00, b"STDIN"))
+			.write(&[ 1u8, RecordType::StdOut as u8, 0, 1, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::StdErr as u8, 0, 1, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::EndRequest as u8, 0, 1, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+			.build()
+	}
+
+	async fn processor<W: AsyncWrite + Unpin + Send>(request: Arc<Request<W>>) -> RequestResult {
+		// The data records were dropped.
+		let mut data = Vec::new();
+		request.get_data().read_to_end(&mut data).unwrap();
+		assert!(data.is_empty());
+
+		let mut stdin = Vec::new();
+		request.get_stdin().read_to_end(&mut stdin).unwrap();
+
+		request.get_stdout().write(&stdin).await.unwrap();
+		RequestResult::Complete(0)
+	}
+}
+
+pub struct TestDefaultContentType {}
+
+#[async_trait]
+impl TestCase for TestDefaultContentType {
+	fn get_input() -> Mock {
+		let mut builder = Builder::new();
+		for request_id in 1..=4 {
+			builder
+				.read(&create_record(RecordType::BeginRequest, request_id, 0x00, &[ 0x00, RecordRole::Responder as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00]))
+				.read(&create_record(RecordType::Params, request_id, 0x00, &[]))
+				.read(&create_record(RecordType::StdIn, request_id, 0x00, &[]));
+		}
+
+		builder.build()
+	}
+
+	fn get_output() -> Mock {
+		let mut builder = Builder::new();
+		builder
+			/*Default added*/.write(&create_record(RecordType::StdOut, 0x01, 0x00, b"Status: 200 OK\r\nCache-Control: no-cache\r\nContent-Length: 9\r\nContent-Type: text/html; charset=utf-8\r\n\r\n<p>Hi</p>"))
+			.write(&[ 1u8, RecordType::StdOut as u8, 0, 1, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::StdErr as u8, 0, 1, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::EndRequest as u8, 0, 1, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+			/*Explicit Content-Type kept*/.write(&create_record(RecordType::StdOut, 0x02, 0x00, b"Status: 200 OK\r\ncontent-type: application/json\r\nContent-Length: 2\r\n\r\n{}"))
+			.write(&[ 1u8, RecordType::StdOut as u8, 0, 2, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::StdErr as u8, 0, 2, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::EndRequest as u8, 0, 2, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+			/*Headers in pieces*/.write(&create_record(RecordType::StdOut, 0x03, 0x00, b"X-One: 1\r\nX-Tw"))
+			.write(&create_record(RecordType::StdOut, 0x03, 0x00, b"o: 2\r\n"))
+			/*Final CRLF split*/.write(&create_record(RecordType::StdOut, 0x03, 0x00, b"Content-Type: text/html; charset=utf-8\r\n\r\nContent-Type: body\r\n"))
+			.write(&[ 1u8, RecordType::StdOut as u8, 0, 3, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::StdErr as u8, 0, 3, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::EndRequest as u8, 0, 3, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+			/*Held back CR*/.write(&create_record(RecordType::StdOut, 0x04, 0x00, b"Status: 204 No Content\r\n"))
+			.write(&create_record(RecordType::StdOut, 0x04, 0x00, b"\r"))
+			.write(&[ 1u8, RecordType::StdOut as u8, 0, 4, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::StdErr as u8, 0, 4, 0, 0, 0, 0])
+			.write(&[ 1u8, RecordType::EndRequest as u8, 0, 4, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+		builder.build()
+	}
+
+	fn configure<R: AsyncRead + Unpin + Send, W: AsyncWrite + Unpin + Send>(requests: Requests<R, W>) -> Requests<R, W> {
+		requests.default_content_type("text/html; charset=utf-8")
+	}
+
+	async fn processor<W: AsyncWrite + Unpin + Send>(request: Arc<Request<W>>) -> RequestResult {
+		let mut stdout = request.get_stdout();
+
+		match request.get_request_id() {
+			1 => request.respond_with(200, &[("Cache-Control", "no-cache")], b"<p>Hi</p>").await.unwrap(),
+			2 => request.respond_with(200, &[("content-type", "application/json")], b"{}").await.unwrap(),
+			3 => {
+				// The body is never touched.
+				stdout.write(b"X-One: 1\r\nX-Tw").await.unwrap();
+				stdout.write(b"o: 2\r\n\r").await.unwrap();
+				stdout.write(b"\nContent-Type: body\r\n").await.unwrap();
+				RequestResult::Complete(0)
+			},
+			_ => {
+				// The CR that was held back is sent when the stream ends.
+				stdout.write(b"Status: 204 No Content\r\n\r").await.unwrap();
+				RequestResult::Complete(0)
+			}
+		}
+	}
+}
+
+pub struct TestStreamTerminators {}
+
+#[async_trait]
+impl TestCase for TestStreamTerminators {
+	fn get_input() -> Mock {
+		let mut builder = Builder::new();
+		for request_id in 1..=8 {
+			builder
+				.read(&create_record(RecordType::BeginRequest, request_id, 0x00, &[ 0x00, RecordRole::Responder as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00]))
+				.read(&create_record(RecordType::Params, request_id, 0x00, &[]))
+				.read(&create_record(RecordType::StdIn, request_id, 0x00, &[]));
+		}
+
+		builder.build()
+	}
+
+	fn get_output() -> Mock {
+		let mut builder = Builder::new();
+		builder
+			/*Complete always terminates the streams*/.write(&create_record(RecordType::StdOut, 0x01, 0x00, &[]))
+			.write(&create_record(RecordType::StdErr, 0x01, 0x00, &[]))
+			.write(&create_record(RecordType::EndRequest, 0x01, 0x00, &[0, 0, 0, 0, 0, 0, 0, 0]))
+			.write(&create_record(RecordType::StdOut, 0x02, 0x00, b"X"))
+			.write(&create_record(RecordType::StdOut, 0x02, 0x00, &[]))
+			.write(&create_record(RecordType::StdErr, 0x02, 0x00, &[]))
+			.write(&create_record(RecordType::EndRequest, 0x02, 0x00, &[0, 0, 0, 1, 0, 0, 0, 0]))
+			/*Rejections without output only send EndRequest*/.write(&create_record(RecordType::EndRequest, 0x03, 0x00, &[0, 0, 0, 0, 3, 0, 0, 0]))
+			.write(&create_record(RecordType::EndRequest, 0x04, 0x00, &[0, 0, 0, 0, 2, 0, 0, 0]))
+			/*Rejections with output terminate the streams*/.write(&create_record(RecordType::StdOut, 0x05, 0x00, b"X"))
+			.write(&create_record(RecordType::StdOut, 0x05, 0x00, &[]))
+			.write(&create_record(RecordType::StdErr, 0x05, 0x00, &[]))
+			.write(&create_record(RecordType::EndRequest, 0x05, 0x00, &[0, 0, 0, 0, 3, 0, 0, 0]))
+			.write(&create_record(RecordType::StdOut, 0x06, 0x00, b"X"))
+			.write(&create_record(RecordType::StdOut, 0x06, 0x00, &[]))
+			.write(&create_record(RecordType::StdErr, 0x06, 0x00, &[]))
+			.write(&create_record(RecordType::EndRequest, 0x06, 0x00, &[0, 0, 0, 0, 2, 0, 0, 0]))
+			/*Output on StdErr counts as output*/.write(&create_record(RecordType::StdErr, 0x07, 0x00, b"Busy"))
+			.write(&create_record(RecordType::StdOut, 0x07, 0x00, &[]))
+			.write(&create_record(RecordType::StdErr, 0x07, 0x00, &[]))
+			.write(&create_record(RecordType::EndRequest, 0x07, 0x00, &[0, 0, 0, 0, 2, 0, 0, 0]))
+			/*Status discarded*/.write(&create_record(RecordType::EndRequest, 0x08, 0x00, &[0, 0, 0, 0, 2, 0, 0, 0]));
+
+		builder.build()
+	}
+
+	async fn processor<W: AsyncWrite + Unpin + Send>(request: Arc<Request<W>>) -> RequestResult {
+		let (result, output): (RequestResult, &[u8]) = match request.get_request_id() {
+			1 => (RequestResult::Complete(0), b""),
+			2 => (RequestResult::Complete(1), b"X"),
+			3 => (RequestResult::UnknownRole, b""),
+			4 => (RequestResult::Overloaded, b""),
+			5 => (RequestResult::UnknownRole, b"X"),
+			6 => (RequestResult::Overloaded, b"X"),
+			7 => {
+				request.get_stderr().write(b"Busy").await.unwrap();
+				(RequestResult::Overloaded, b"")
+			},
+			_ => {
+				// A status set by a rejecting processor is discarded.
+				request.set_status(503);
+				(RequestResult::Overloaded, b"")
+			}
+		};
+
+		request.get_stdout().write(output).await.unwrap();
+		result
+	}
+}
+
+pub struct TestSetStatus {}
+
+#[async_trait]
+impl TestCase for TestSetStatus {
+	fn get_input() -> Mock {
+		let mut builder = Builder::new();
+		for request_id in 1..=4 {
+			builder
+				.read(&create_record(RecordType::BeginRequest, request_id, 0x00, &[ 0x00, RecordRole::Responder as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00]))
+				.read(&create_record(RecordType::Params, request_id, 0x00, &[]))
+				.read(&create_record(RecordType::StdIn, request_id, 0x00, &[]));
+		}
+
+		builder.build()
+	}
+
+	fn get_output() -> Mock {
+		let mut builder = Builder::new();
+		for request_id in 1..=4 {
+			match request_id {
+				/*Status precedes the first write*/1 => builder
+					.write(&create_record(RecordType::StdOut, request_id, 0x00, b"Status: 410 Gone\r\nContent-Type: text/plain\r\n\r\n"))
+					.write(&create_record(RecordType::StdOut, request_id, 0x00, b"Gone")),
+				/*Status sent when the request is finished*/2 => builder.write(&create_record(RecordType::StdOut, request_id, 0x00, b"Status: 204 No Content\r\n\r\n")),
+				/*respond_with replaces the status*/3 => builder.write(&create_record(RecordType::StdOut, request_id, 0x00, b"Status: 200 OK\r\nContent-Length: 2\r\n\r\nOK")),
+				/*Nothing inserted without a status*/_ => &mut builder
+			};
+
+			builder
+				.write(&create_record(RecordType::StdOut, request_id, 0x00, &[]))
+				.write(&create_record(RecordType::StdErr, request_id, 0x00, &[]))
+				.write(&create_record(RecordType::EndRequest, request_id, 0x00, &[0, 0, 0, 0, 0, 0, 0, 0]));
+		}
+
+		builder.build()
+	}
+
+	async fn processor<W: AsyncWrite + Unpin + Send>(request: Arc<Request<W>>) -> RequestResult {
+		match request.get_request_id() {
+			1 => {
+				request.set_status(404);
+				request.set_status(410);
+
+				let mut stdout = request.get_stdout();
+				stdout.write(b"Content-Type: text/plain\r\n\r\n").await.unwrap();
+				request.set_status(500);
+				stdout.write(b"Gone").await.unwrap();
+
+				RequestResult::Complete(0)
+			},
+			2 => {
+				request.set_status(204);
+				RequestResult::Complete(0)
+			},
+			3 => {
+				request.set_status(500);
+				request.respond_with(200, &[], b"OK").await.unwrap()
+			},
+			_ => RequestResult::Complete(0)
+		}
+	}
+}
@@ -0,0 +1,282 @@
+//! This source file verifies that requests can be processed concurrently.
+//!
+//! The future returned by `process` is spawned as a separate task for every
+//! request. All tasks share the write half of the connection and write their
+//! output in small chunks to provoke as much interleaving as possible. The
+//! output is parsed record by record to make sure no record got torn apart.
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, WriteHalf};
+use tokio::sync::{Barrier, Semaphore};
+use tokio_fastcgi::{Request, RequestResult, Requests};
+
+#[allow(dead_code)]
+mod commons;
+use crate::commons::*;
+
+/// Number of requests that are multiplexed over the connection.
+const REQUEST_COUNT: u8 = 50;
+
+/// Number of chunks every request writes to StdOut.
+const CHUNK_COUNT: usize = 20;
+
+/// Parses the records returned by the FastCGI server into a map that contains
+/// the StdOut data and the EndRequest body for every request id.
+fn parse_output(mut output: &[u8]) -> HashMap<u16, (Vec<u8>, Vec<u8>)> {
+	let mut requests: HashMap<u16, (Vec<u8>, Vec<u8>)> = HashMap::new();
+
+	while !output.is_empty() {
+		assert!(output.len() >= 8, "Truncated record header.");
+		assert_eq!(output[0], 1, "Invalid record version. The output stream is corrupted.");
+
+		let record_type = output[1];
+		let request_id = u16::from_be_bytes([output[2], output[3]]);
+		let content_length = u16::from_be_bytes([output[4], output[5]]) as usize;
+		let padding_length = output[6] as usize;
+
+		assert!(output.len() >= 8 + content_length + padding_length, "Truncated record content.");
+		let content = &output[8..8 + content_length];
+
+		let entry = requests.entry(request_id).or_default();
+		if record_type == RecordType::StdOut as u8 {
+			assert!(entry.1.is_empty(), "StdOut record after EndRequest.");
+			entry.0.extend_from_slice(content);
+		} else if record_type == RecordType::EndRequest as u8 {
+			assert!(entry.1.is_empty(), "Duplicate EndRequest record.");
+			entry.1.extend_from_slice(content);
+		} else {
+			assert_eq!(record_type, RecordType::StdErr as u8, "Unexpected record type.");
+		}
+
+		output = &output[8 + content_length + padding_length..];
+	}
+
+	requests
+}
+
+/// Returns the output every request is expected to produce.
+fn expected_output(idx: &str) -> Vec<u8> {
+	(0..CHUNK_COUNT).flat_map(|chunk| format!("[{}:{}]", idx, chunk).into_bytes()).collect()
+}
+
+async fn processor(request: Arc<Request<WriteHalf<DuplexStream>>>) -> RequestResult {
+	let idx = request.get_str_param("IDX").unwrap().to_string();
+
+	for chunk in 0..CHUNK_COUNT {
+		request.get_stdout().write(format!("[{}:{}]", idx, chunk).as_bytes()).await.unwrap();
+		tokio::task::yield_now().await;
+	}
+
+	RequestResult::Complete(idx.parse().unwrap())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_writes() {
+	let (mut client, server) = tokio::io::duplex(1024);
+
+	let server = tokio::spawn(async move {
+		let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, REQUEST_COUNT);
+		let mut processors = Vec::new();
+
+		while let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+			processors.push(tokio::spawn(request.process(processor)));
+		}
+
+		for processor in processors {
+			processor.await.unwrap().expect("Error while processing.");
+		}
+	});
+
+	// Interleave the records of all requests to make them complete at roughly the same time.
+	let mut input = Vec::new();
+	for id in 1..=REQUEST_COUNT {
+		input.extend(create_record(RecordType::BeginRequest, id, 0, &[ 0x00, RecordRole::Responder as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00]));
+	}
+	for id in 1..=REQUEST_COUNT {
+		let idx = id.to_string();
+		let mut params = vec![3, idx.len() as u8];
+		params.extend_from_slice(b"IDX");
+		params.extend_from_slice(idx.as_bytes());
+		input.extend(create_record(RecordType::Params, id, 0, &params));
+		input.extend(create_record(RecordType::Params, id, 0, &[]));
+	}
+	for id in 1..=REQUEST_COUNT {
+		input.extend(create_record(RecordType::StdIn, id, 0, &[]));
+	}
+
+	let (mut rd, mut wr) = tokio::io::split(&mut client);
+	let writer = async move {
+		wr.write_all(&input).await.unwrap();
+		wr.shutdown().await.unwrap();
+	};
+	let reader = async move {
+		let mut output = Vec::new();
+		rd.read_to_end(&mut output).await.unwrap();
+		output
+	};
+	let (_, output) = tokio::join!(writer, reader);
+
+	server.await.unwrap();
+
+	let responses = parse_output(&output);
+	assert_eq!(responses.len(), REQUEST_COUNT as usize);
+
+	for id in 1..=REQUEST_COUNT {
+		let (stdout, end_request) = &responses[&(id as u16)];
+		assert_eq!(stdout, &expected_output(&id.to_string()));
+		assert_eq!(end_request, &[0, 0, 0, id, 0, 0, 0, 0]);
+	}
+}
+
+#[tokio::test]
+async fn spawn_processor() {
+	// Both requests are complete before the first one is processed.
+	let mut input = Vec::new();
+	for id in 1..=2 {
+		input.extend(create_record(RecordType::BeginRequest, id, 0, &[ 0x00, RecordRole::Responder as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00]));
+		input.extend(create_record(RecordType::Params, id, 0, &[3, 1, b'I', b'D', b'X', b'0' + id]));
+		input.extend(create_record(RecordType::Params, id, 0, &[]));
+		input.extend(create_record(RecordType::StdIn, id, 0, &[]));
+	}
+
+	let (mut requests, mut client) = Requests::new_duplex(64 * 1024);
+	client.write_all(&input).await.unwrap();
+	client.shutdown().await.unwrap();
+
+	// Every processor waits for the other one. They can only finish if they overlap.
+	let barrier = Arc::new(Barrier::new(2));
+	let mut processors = Vec::new();
+	while let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+		let barrier = barrier.clone();
+		processors.push(request.spawn_processor(|request| async move {
+			let idx = request.get_str_param("IDX").unwrap().to_string();
+
+			request.get_stdout().write(format!("before {};", idx).as_bytes()).await.unwrap();
+			barrier.wait().await;
+			request.get_stdout().write(format!("after {};", idx).as_bytes()).await.unwrap();
+
+			RequestResult::Complete(idx.parse().unwrap())
+		}));
+	}
+
+	for processor in processors {
+		processor.await.unwrap().expect("Error while processing.");
+	}
+	drop(requests);
+
+	let mut output = Vec::new();
+	client.read_to_end(&mut output).await.unwrap();
+
+	let responses = parse_output(&output);
+	assert_eq!(responses.len(), 2);
+	assert_eq!(responses[&1], (b"before 1;after 1;".to_vec(), vec![0, 0, 0, 1, 0, 0, 0, 0]));
+	assert_eq!(responses[&2], (b"before 2;after 2;".to_vec(), vec![0, 0, 0, 2, 0, 0, 0, 0]));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn serve_concurrent() {
+	const SERVED_COUNT: u8 = 8;
+
+	let begin_request = |id: u8| {
+		let mut input = create_record(RecordType::BeginRequest, id, 0, &[ 0x00, RecordRole::Responder as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00]);
+		input.extend(create_record(RecordType::Params, id, 0, &[3, 1, b'I', b'D', b'X', b'0' + id]));
+		input.extend(create_record(RecordType::Params, id, 0, &[]));
+		input.extend(create_record(RecordType::StdIn, id, 0, &[]));
+		input
+	};
+
+	let (mut client, server) = tokio::io::duplex(64 * 1024);
+	client.write_all(&(1..=SERVED_COUNT).flat_map(begin_request).collect::<Vec<u8>>()).await.unwrap();
+
+	let started = Arc::new(AtomicUsize::new(0));
+	let release = Arc::new(Semaphore::new(0));
+
+	let server = tokio::spawn({
+		let started = started.clone();
+		let release = release.clone();
+		async move {
+			let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, SERVED_COUNT);
+			requests.serve_concurrent(move |request| async move {
+				started.fetch_add(1, Ordering::SeqCst);
+				release.acquire().await.unwrap().forget();
+
+				let idx = request.get_str_param("IDX").unwrap().to_string();
+				request.get_stdout().write(format!("done {};", idx).as_bytes()).await.unwrap();
+				RequestResult::Complete(idx.parse().unwrap())
+			}, 2).await
+		}
+	});
+
+	// The requests exceeding the limit are rejected right away instead of being buffered until a slot is free.
+	for id in 3..=SERVED_COUNT {
+		assert_eq!(read_record(&mut client).await, Some((RecordType::EndRequest as u8, id as u16, vec![0, 0, 0, 0, 2, 0, 0, 0])));
+	}
+	assert!(started.load(Ordering::SeqCst) <= 2);
+
+	// The slots are freed as soon as the processors have finished.
+	release.add_permits(2);
+	let mut ended = Vec::new();
+	while ended.len() < 2 {
+		let (record_type, request_id, content) = read_record(&mut client).await.unwrap();
+		if record_type == RecordType::EndRequest as u8 {
+			assert_eq!(content, vec![0, 0, 0, request_id as u8, 0, 0, 0, 0]);
+			ended.push(request_id);
+		}
+	}
+
+	// A rejected request can be started again.
+	release.add_permits(1);
+	client.write_all(&begin_request(3)).await.unwrap();
+	client.shutdown().await.unwrap();
+
+	server.await.unwrap().expect("Connection failed.");
+
+	let mut output = Vec::new();
+	client.read_to_end(&mut output).await.unwrap();
+
+	let responses = parse_output(&output);
+	assert_eq!(responses.len(), 1);
+	assert_eq!(responses[&3], (b"done 3;".to_vec(), vec![0, 0, 0, 3, 0, 0, 0, 0]));
+	assert_eq!(started.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn serve_concurrent_abort_at_limit() {
+	let mut input = Vec::new();
+	for id in 1..=2 {
+		input.extend(create_record(RecordType::BeginRequest, id, 0, &[ 0x00, RecordRole::Responder as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00]));
+		input.extend(create_record(RecordType::Params, id, 0, &[]));
+		input.extend(create_record(RecordType::StdIn, id, 0, &[]));
+	}
+
+	let (mut client, server) = tokio::io::duplex(64 * 1024);
+	client.write_all(&input).await.unwrap();
+
+	let server = tokio::spawn(async move {
+		let mut requests = Requests::from_split_socket(tokio::io::split(server), 2, 2);
+		requests.serve_concurrent(|request| async move {
+			assert_eq!(request.get_request_id(), 1);
+
+			// The limit is reached while this request runs. Its abort is received anyway.
+			tokio::time::timeout(Duration::from_secs(5), request.aborted()).await.expect("The abort was not received.");
+			RequestResult::Complete(1)
+		}, 1).await
+	});
+
+	// Abort the first request after it was started.
+	tokio::time::sleep(Duration::from_millis(100)).await;
+	client.write_all(&create_record(RecordType::AbortRequest, 1, 0, &[])).await.unwrap();
+	client.shutdown().await.unwrap();
+
+	server.await.unwrap().expect("Connection failed.");
+
+	let mut output = Vec::new();
+	client.read_to_end(&mut output).await.unwrap();
+
+	// The request started at the limit was rejected. The abort of the running one was received anyway.
+	let responses = parse_output(&output);
+	assert_eq!(responses[&1], (Vec::new(), vec![0, 0, 0, 1, 0, 0, 0, 0]));
+	assert_eq!(responses[&2], (Vec::new(), vec![0, 0, 0, 0, 2, 0, 0, 0]));
+}
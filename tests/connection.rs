@@ -0,0 +1,1015 @@
+//! This source file verifies the connection management of the `Requests`
+//! struct.
+//!
+//! The tests run the FastCGI implementation on an in memory connection.
+//! Contrary to the mockup streams used by `integration.rs` this allows the
+//! tests to check which records were not consumed by the implementation.
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio_fastcgi::{ConnectionLimiter, ConnectionStats, Error, Phase, Request, RequestResult, RequestSummary, Requests, Role, SlowRequest};
+
+#[allow(dead_code)]
+mod commons;
+use crate::commons::*;
+
+/// Returns the records of a responder request without parameters and body.
+fn simple_request(request_id: u8, flags: u8) -> Vec<u8> {
+	let mut input = create_record(RecordType::BeginRequest, request_id, 0x00, &[ 0x00, RecordRole::Responder as u8, flags, 0x00, 0x00, 0x00, 0x00, 0x00]);
+	input.extend(create_record(RecordType::Params, request_id, 0x00, &[]));
+	input.extend(create_record(RecordType::StdIn, request_id, 0x00, &[]));
+
+	input
+}
+
+/// Creates an in memory connection and sends `input` via its client side.
+async fn connect(input: &[u8]) -> (DuplexStream, DuplexStream) {
+	let (mut client, server) = tokio::io::duplex(64 * 1024);
+	client.write_all(input).await.unwrap();
+
+	(client, server)
+}
+
+/// Checks that the next records received by the client terminate the request
+/// with the given id.
+async fn expect_complete(client: &mut DuplexStream, request_id: u16) {
+	assert_eq!(read_record(client).await, Some((RecordType::StdOut as u8, request_id, Vec::new())));
+	assert_eq!(read_record(client).await, Some((RecordType::StdErr as u8, request_id, Vec::new())));
+	assert_eq!(read_record(client).await, Some((RecordType::EndRequest as u8, request_id, vec![0, 0, 0, 0, 0, 0, 0, 0])));
+}
+
+#[tokio::test]
+async fn max_requests_per_connection() {
+	let mut input = simple_request(1, RecordFlags::KeepConn as u8);
+	input.extend(simple_request(2, RecordFlags::KeepConn as u8));
+	let (mut client, server) = connect(&input).await;
+
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 1).max_requests_per_connection(1);
+
+	let mut served = 0;
+	while let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+		served += 1;
+		request.process(|_request| async move { RequestResult::Complete(0) }).await.unwrap();
+	}
+	assert_eq!(served, 1);
+
+	// Close the connection. Only the first request must have been answered.
+	drop(requests);
+
+	expect_complete(&mut client, 1).await;
+	assert_eq!(read_record(&mut client).await, None);
+}
+
+#[tokio::test]
+async fn max_requests_per_connection_interleaved() {
+	// The second request is started before the first one is complete.
+	let mut input = create_record(RecordType::BeginRequest, 1, 0x00, &[ 0x00, RecordRole::Responder as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00]);
+	input.extend(create_record(RecordType::BeginRequest, 2, 0x00, &[ 0x00, RecordRole::Responder as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00]));
+	input.extend(create_record(RecordType::Params, 2, 0x00, b"\x03\x01IDX2"));
+	input.extend(create_record(RecordType::Params, 1, 0x00, &[]));
+	input.extend(create_record(RecordType::Params, 2, 0x00, &[]));
+	input.extend(create_record(RecordType::StdIn, 2, 0x00, &[]));
+	input.extend(create_record(RecordType::StdIn, 1, 0x00, &[]));
+	let (mut client, server) = connect(&input).await;
+
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 2).max_requests_per_connection(1);
+
+	let mut served = Vec::new();
+	while let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+		served.push(request.get_request_id());
+		request.process(|_request| async move { RequestResult::Complete(0) }).await.unwrap();
+	}
+	assert_eq!(served, vec![1]);
+
+	drop(requests);
+
+	// The second request is rejected as soon as it begins.
+	assert_eq!(read_record(&mut client).await, Some((RecordType::EndRequest as u8, 2, vec![0, 0, 0, 0, 2, 0, 0, 0])));
+	expect_complete(&mut client, 1).await;
+	assert_eq!(read_record(&mut client).await, None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn stream_stdin() {
+	let (requests, mut client) = Requests::new_duplex(64 * 1024);
+	let mut requests = requests.stream_stdin(true);
+
+	// The request is returned as soon as its parameters are complete.
+	client.write_all(&create_record(RecordType::BeginRequest, 1, 0x00, &[ 0x00, RecordRole::Responder as u8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00])).await.unwrap();
+	client.write_all(&create_record(RecordType::Params, 1, 0x00, &[])).await.unwrap();
+
+	let request = requests.next().await.expect("Request could not be constructed.").unwrap();
+	assert_eq!(request.phase(), Phase::Processing);
+
+	// The processor reads the body before it was sent.
+	let start = tokio::time::Instant::now();
+	let processor = request.spawn_processor(move |request| async move {
+		let mut reader = request.stdin_reader();
+
+		let mut first = [0u8; 16];
+		let length = reader.read(&mut first).await.unwrap();
+		assert_eq!(&first[..length], b"First part,");
+		assert_eq!(start.elapsed(), Duration::from_secs(1));
+
+		let mut rest = Vec::new();
+		reader.read_to_end(&mut rest).await.unwrap();
+		assert_eq!(rest, b" second part");
+		assert_eq!(start.elapsed(), Duration::from_secs(3));
+
+		RequestResult::Complete(0)
+	});
+
+	// The body is read by the connection while the request is processed.
+	let next = tokio::spawn(async move {
+		let request = requests.next().await.expect("Connection could not be read.");
+		(requests, request.is_none())
+	});
+
+	tokio::time::sleep(Duration::from_secs(1)).await;
+	client.write_all(&create_record(RecordType::StdIn, 1, 0x00, b"First part,")).await.unwrap();
+	tokio::time::sleep(Duration::from_secs(1)).await;
+	client.write_all(&create_record(RecordType::StdIn, 1, 0x00, b" second part")).await.unwrap();
+	tokio::time::sleep(Duration::from_secs(1)).await;
+	client.write_all(&create_record(RecordType::StdIn, 1, 0x00, &[])).await.unwrap();
+
+	processor.await.unwrap().unwrap();
+	expect_complete(&mut client, 1).await;
+
+	// Without FCGI_KEEP_CONN the connection ends as soon as the body was received.
+	let (requests, closed) = next.await.unwrap();
+	assert!(closed);
+	assert!(requests.is_finished());
+}
+
+#[tokio::test(start_paused = true)]
+async fn stream_stdin_incomplete() {
+	let (requests, mut client) = Requests::new_duplex(64 * 1024);
+	let mut requests = requests.stream_stdin(true);
+
+	for request_id in 1..=2 {
+		client.write_all(&create_record(RecordType::BeginRequest, request_id, 0x00, &[ 0x00, RecordRole::Responder as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00])).await.unwrap();
+		client.write_all(&create_record(RecordType::Params, request_id, 0x00, &[])).await.unwrap();
+	}
+
+	// Request 1 reads the body while it is received.
+	let request = requests.next().await.expect("Request could not be constructed.").unwrap();
+	let processor1 = request.spawn_processor(|request| async move {
+		let mut body = Vec::new();
+		let err = request.stdin_reader().read_to_end(&mut body).await.unwrap_err();
+		assert_eq!(err.kind(), std::io::ErrorKind::ConnectionAborted);
+		assert_eq!(body, b"Aborted");
+
+		RequestResult::Complete(1)
+	});
+
+	// Request 2 waits for the complete body and reads it afterwards.
+	let request = requests.next().await.expect("Request could not be constructed.").unwrap();
+	let processor2 = request.spawn_processor(|request| async move {
+		let mut body = Vec::new();
+		assert_eq!(request.get_stdin().read_to_end(&mut body).unwrap_err().kind(), std::io::ErrorKind::WouldBlock);
+
+		request.wait_stdin_complete().await;
+		let err = request.get_stdin().read_to_end(&mut body).unwrap_err();
+		assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+		assert_eq!(body, b"Cut off");
+
+		RequestResult::Complete(2)
+	});
+
+	let next = tokio::spawn(async move { requests.next().await.map(|request| request.is_none()) });
+
+	// Request 1 is aborted, the connection ends in the middle of the body of request 2.
+	client.write_all(&create_record(RecordType::StdIn, 1, 0x00, b"Aborted")).await.unwrap();
+	client.write_all(&create_record(RecordType::StdIn, 2, 0x00, b"Cut off")).await.unwrap();
+	client.write_all(&create_record(RecordType::AbortRequest, 1, 0x00, &[])).await.unwrap();
+	processor1.await.unwrap().unwrap();
+
+	client.shutdown().await.unwrap();
+	processor2.await.unwrap().unwrap();
+	assert!(next.await.unwrap().unwrap());
+
+	assert_eq!(read_record(&mut client).await, Some((RecordType::StdOut as u8, 1, Vec::new())));
+	assert_eq!(read_record(&mut client).await, Some((RecordType::StdErr as u8, 1, Vec::new())));
+	assert_eq!(read_record(&mut client).await, Some((RecordType::EndRequest as u8, 1, vec![0, 0, 0, 1, 0, 0, 0, 0])));
+}
+
+#[tokio::test]
+async fn stream_stdin_max_body_size() {
+	let (requests, mut client) = Requests::new_duplex(64 * 1024);
+	let mut requests = requests.stream_stdin(true).max_body_size(8);
+
+	client.write_all(&create_record(RecordType::BeginRequest, 1, 0x00, &[ 0x00, RecordRole::Responder as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00])).await.unwrap();
+	client.write_all(&create_record(RecordType::Params, 1, 0x00, &[])).await.unwrap();
+
+	let request = requests.next().await.expect("Request could not be constructed.").unwrap();
+	let processor = request.spawn_processor(|request| async move {
+		let mut body = Vec::new();
+		let err = request.stdin_reader().read_to_end(&mut body).await.unwrap_err();
+		assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+		assert_eq!(body, b"12345");
+
+		RequestResult::Complete(1)
+	});
+
+	// The limit is checked before the data is passed to the processor.
+	client.write_all(&create_record(RecordType::StdIn, 1, 0x00, b"12345")).await.unwrap();
+	client.write_all(&create_record(RecordType::StdIn, 1, 0x00, b"6789")).await.unwrap();
+
+	match requests.next().await {
+		Err(Error::BodySizeExceeded(1)) => (),
+		Err(err) => panic!("Unexpected error: {}", err),
+		Ok(_) => panic!("Request returned despite its oversized body.")
+	}
+	processor.await.unwrap().unwrap();
+	assert_eq!(read_record(&mut client).await, Some((RecordType::StdOut as u8, 1, Vec::new())));
+	assert_eq!(read_record(&mut client).await, Some((RecordType::StdErr as u8, 1, Vec::new())));
+	assert_eq!(read_record(&mut client).await, Some((RecordType::EndRequest as u8, 1, vec![0, 0, 0, 1, 0, 0, 0, 0])));
+
+	// Late records of the request are ignored.
+	client.write_all(&create_record(RecordType::StdIn, 1, 0x00, b"0")).await.unwrap();
+	client.shutdown().await.unwrap();
+	assert!(requests.next().await.expect("Connection could not be read.").is_none());
+}
+
+#[tokio::test]
+async fn pipelined_without_keep_conn() {
+	// The web-server starts a second request after one without FCGI_KEEP_CONN.
+	let mut input = simple_request(1, 0x00);
+	input.extend(simple_request(2, 0x00));
+	let (mut client, server) = connect(&input).await;
+
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 2);
+
+	let mut served = Vec::new();
+	while let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+		served.push(request.get_request_id());
+		request.process(|_request| async move { RequestResult::Complete(0) }).await.unwrap();
+	}
+	assert_eq!(served, vec![1]);
+
+	// The connection is closed after the first request. The second one is never read.
+	drop(requests);
+	expect_complete(&mut client, 1).await;
+	assert_eq!(read_record(&mut client).await, None);
+
+	// The second request is started and completed before the first one.
+	let mut input = create_record(RecordType::BeginRequest, 1, 0x00, &[ 0x00, RecordRole::Responder as u8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+	input.extend(simple_request(2, 0x00));
+	input.extend(create_record(RecordType::Params, 1, 0x00, &[]));
+	input.extend(create_record(RecordType::StdIn, 1, 0x00, &[]));
+	let (mut client, server) = connect(&input).await;
+
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 2);
+
+	let mut served = Vec::new();
+	while let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+		served.push(request.get_request_id());
+		request.process(|_request| async move { RequestResult::Complete(0) }).await.unwrap();
+	}
+	assert_eq!(served, vec![1]);
+
+	// The second request is rejected with FCGI_CANT_MPX_CONN as soon as it begins.
+	drop(requests);
+	assert_eq!(read_record(&mut client).await, Some((RecordType::EndRequest as u8, 2, vec![0, 0, 0, 0, 1, 0, 0, 0])));
+	expect_complete(&mut client, 1).await;
+	assert_eq!(read_record(&mut client).await, None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn response_stream() {
+	let (mut client, server) = connect(&simple_request(1, 0x00)).await;
+
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 1);
+	let request = requests.next().await.expect("Request could not be constructed.").unwrap();
+
+	let start = tokio::time::Instant::now();
+	let server = tokio::spawn(async move {
+		request.process(|request| async move {
+			let mut stream = request.stream(200, &[("Content-Type", "text/event-stream")]).await.unwrap();
+			for event in 0..3 {
+				tokio::time::sleep(Duration::from_secs(1)).await;
+				stream.write(format!("id: {}\n", event).as_bytes());
+				stream.write(b"data: tick\n\n");
+				stream.flush().await.unwrap();
+			}
+
+			stream.finish().await.unwrap()
+		}).await.unwrap();
+	});
+
+	// The headers are delivered right away.
+	assert_eq!(read_record(&mut client).await, Some((RecordType::StdOut as u8, 1, b"Status: 200 OK\r\nContent-Type: text/event-stream\r\n\r\n".to_vec())));
+	assert_eq!(start.elapsed(), Duration::ZERO);
+
+	// Every event arrives as one record as soon as it is flushed.
+	for event in 0..3 {
+		assert_eq!(read_record(&mut client).await, Some((RecordType::StdOut as u8, 1, format!("id: {}\ndata: tick\n\n", event).into_bytes())));
+		assert_eq!(start.elapsed(), Duration::from_secs(event + 1));
+	}
+
+	server.await.unwrap();
+	expect_complete(&mut client, 1).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn abort_while_processing() {
+	let mut input = simple_request(0, RecordFlags::KeepConn as u8);
+	input.extend(simple_request(1, RecordFlags::KeepConn as u8));
+	let (mut client, server) = connect(&input).await;
+
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 2);
+
+	// Request 0 takes a while and is not aborted.
+	let request = requests.next().await.expect("Request could not be constructed.").unwrap();
+	let processor0 = request.spawn_processor(|request| async move {
+		tokio::time::sleep(Duration::from_secs(5)).await;
+		assert!(!request.is_aborted());
+		request.get_stdout().write(b"0").await.unwrap();
+
+		RequestResult::Complete(0)
+	});
+
+	// Request 1 waits for work that never finishes but is aborted.
+	let request = requests.next().await.expect("Request could not be constructed.").unwrap();
+	let processor1 = request.spawn_processor(|request| async move {
+		assert!(!request.is_aborted());
+		tokio::select! {
+			_ = tokio::time::sleep(Duration::from_secs(60)) => unreachable!("Request was not aborted."),
+			_ = request.aborted() => ()
+		}
+		assert!(request.is_aborted());
+
+		// Waiting again returns right away.
+		request.aborted().await;
+		RequestResult::Complete(7)
+	});
+
+	// The connection keeps reading records while the requests are processed.
+	let next = tokio::spawn(async move { requests.next().await.map(|request| request.is_none()) });
+
+	let start = tokio::time::Instant::now();
+	tokio::time::sleep(Duration::from_secs(1)).await;
+	client.write_all(&create_record(RecordType::AbortRequest, 1, 0x00, &[])).await.unwrap();
+
+	// Only request 1 is ended early. Its processor sends the EndRequest record.
+	processor1.await.unwrap().unwrap();
+	assert_eq!(start.elapsed(), Duration::from_secs(1));
+	assert_eq!(read_record(&mut client).await, Some((RecordType::StdOut as u8, 1, Vec::new())));
+	assert_eq!(read_record(&mut client).await, Some((RecordType::StdErr as u8, 1, Vec::new())));
+	assert_eq!(read_record(&mut client).await, Some((RecordType::EndRequest as u8, 1, vec![0, 0, 0, 7, 0, 0, 0, 0])));
+
+	processor0.await.unwrap().unwrap();
+	assert_eq!(start.elapsed(), Duration::from_secs(5));
+	assert_eq!(read_record(&mut client).await, Some((RecordType::StdOut as u8, 0, b"0".to_vec())));
+	expect_complete(&mut client, 0).await;
+
+	drop(client);
+	assert!(next.await.unwrap().unwrap());
+}
+
+#[tokio::test]
+async fn into_inner() {
+	let input = simple_request(1, RecordFlags::KeepConn as u8);
+	let (mut client, server) = connect(&input).await;
+
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 1);
+
+	let request = requests.next().await.expect("Request could not be constructed.").unwrap();
+
+	// The connection can not be reclaimed while a request is in flight.
+	let mut requests = match requests.into_inner() {
+		Ok(_) => panic!("Connection reclaimed while a request is in flight."),
+		Err(requests) => *requests
+	};
+
+	request.process(|_request| async move { RequestResult::Complete(0) }).await.unwrap();
+
+	// Signal the end of the connection and wait for the request loop to end.
+	client.shutdown().await.unwrap();
+	assert!(requests.next().await.expect("Request could not be constructed.").is_none());
+
+	let (reader, mut writer) = match requests.into_inner() {
+		Ok(parts) => parts,
+		Err(_) => panic!("Connection could not be reclaimed.")
+	};
+
+	// The reclaimed writer is still connected to the client.
+	writer.write_all(b"RAW").await.unwrap();
+	drop((reader, writer));
+
+	expect_complete(&mut client, 1).await;
+	let mut raw = Vec::new();
+	client.read_to_end(&mut raw).await.unwrap();
+	assert_eq!(raw, b"RAW");
+}
+
+#[tokio::test(start_paused = true)]
+async fn stalled_record() {
+	// The header announces 65535 bytes of content but only a fraction of it is sent.
+	let mut input = create_record(RecordType::BeginRequest, 1, 0x00, &[ 0x00, RecordRole::Responder as u8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+	input.extend_from_slice(&[1, RecordType::Params as u8, 0, 1, 0xFF, 0xFF, 0, 0]);
+	input.extend_from_slice(b"\x03\x01IDX1");
+	let (_client, server) = connect(&input).await;
+
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 1).record_timeout(Duration::from_secs(10));
+
+	match requests.next().await {
+		Err(Error::IoError(err)) => assert_eq!(err.kind(), std::io::ErrorKind::TimedOut),
+		Err(err) => panic!("Unexpected error: {}", err),
+		Ok(_) => panic!("Stalled record was accepted.")
+	}
+}
+
+#[tokio::test(start_paused = true)]
+async fn idle_connection_without_record_timeout() {
+	let (mut client, server) = connect(&[]).await;
+
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 1).record_timeout(Duration::from_secs(10));
+
+	// Waiting for the next record is not limited by the record timeout.
+	let next = tokio::spawn(async move { requests.next().await.map(|request| request.is_some()) });
+	tokio::time::sleep(Duration::from_secs(60)).await;
+	assert!(!next.is_finished());
+
+	client.write_all(&simple_request(1, 0x00)).await.unwrap();
+	assert!(next.await.unwrap().unwrap());
+}
+
+#[tokio::test(start_paused = true)]
+async fn idle_timeout() {
+	let (_client, server) = connect(&[]).await;
+
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 1).idle_timeout(Duration::from_secs(10));
+
+	let start = tokio::time::Instant::now();
+	assert!(requests.next().await.expect("Idle connection returned an error.").is_none());
+	assert_eq!(start.elapsed(), Duration::from_secs(10));
+}
+
+#[tokio::test(start_paused = true)]
+async fn idle_timeout_reset_by_management_records() {
+	let (mut client, server) = connect(&[]).await;
+
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 1).idle_timeout(Duration::from_secs(10));
+	let next = tokio::spawn(async move { requests.next().await.map(|request| request.map(|request| request.get_request_id())) });
+
+	// Probe the connection every 5 seconds for 30 seconds. The connection must stay open.
+	for _ in 0..6 {
+		tokio::time::sleep(Duration::from_secs(5)).await;
+		client.write_all(&create_record(RecordType::GetValues, 0, 0x00, b"\x0E\x00FCGI_MAX_CONNS")).await.unwrap();
+
+		let (record_type, request_id, _) = read_record(&mut client).await.unwrap();
+		assert_eq!((record_type, request_id), (RecordType::GetValuesResult as u8, 0));
+	}
+
+	client.write_all(&simple_request(1, 0x00)).await.unwrap();
+	assert_eq!(next.await.unwrap().unwrap(), Some(1));
+}
+
+#[tokio::test(start_paused = true)]
+async fn next_timeout() {
+	let (mut client, server) = connect(&[]).await;
+
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 1);
+
+	// Nothing arrives. The call returns after the timeout but the connection stays open.
+	let start = tokio::time::Instant::now();
+	assert!(requests.next_timeout(Duration::from_secs(5)).await.expect("Waiting for a request failed.").is_none());
+	assert_eq!(start.elapsed(), Duration::from_secs(5));
+	assert!(!requests.is_finished());
+
+	// A partially received request is kept while the next call times out.
+	client.write_all(&create_record(RecordType::BeginRequest, 1, 0x00, &[ 0x00, RecordRole::Responder as u8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00])).await.unwrap();
+	client.write_all(&create_record(RecordType::Params, 1, 0x00, b"\x0e\x03REQUEST_METHODGET")).await.unwrap();
+	assert!(requests.next_timeout(Duration::from_secs(5)).await.expect("Waiting for a request failed.").is_none());
+	assert!(!requests.is_finished());
+
+	client.write_all(&create_record(RecordType::Params, 1, 0x00, &[])).await.unwrap();
+	client.write_all(&create_record(RecordType::StdIn, 1, 0x00, &[])).await.unwrap();
+	let request = requests.next_timeout(Duration::from_secs(5)).await.expect("Waiting for a request failed.").expect("Request was not received.");
+	assert_eq!(request.get_str_param("request_method"), Some("GET"));
+	request.process(|_request| async move { RequestResult::Complete(0) }).await.unwrap();
+
+	// The request did not keep the connection. It is finished now.
+	assert!(requests.is_finished());
+	assert!(requests.next_timeout(Duration::from_secs(5)).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn single_request_abort_and_errors() {
+	// A single request without keep connection is aborted by the web-server.
+	let mut input = create_record(RecordType::BeginRequest, 1, 0x00, &[ 0x00, RecordRole::Responder as u8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+	input.extend(create_record(RecordType::Params, 1, 0x00, &[]));
+	input.extend(create_record(RecordType::AbortRequest, 1, 0x00, &[]));
+	// Records for a request that was never started are a sequence error.
+	input.extend(create_record(RecordType::Params, 2, 0x00, &[]));
+	let (mut client, server) = connect(&input).await;
+
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 1);
+
+	assert!(matches!(requests.next().await, Err(Error::SequenceError)));
+	assert_eq!(read_record(&mut client).await, Some((RecordType::EndRequest as u8, 1, vec![0, 0, 0, 0, 0, 0, 0, 0])));
+
+	// The aborted request was removed. The connection can be reclaimed.
+	assert!(requests.into_inner().is_ok());
+}
+
+#[tokio::test]
+async fn connection_stats() {
+	// A connection that is reused by the web-server
+	let mut input = simple_request(1, RecordFlags::KeepConn as u8);
+	input.extend(simple_request(1, RecordFlags::KeepConn as u8));
+	input.extend(simple_request(1, 0x00));
+	let (_client, server) = connect(&input).await;
+
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 1);
+	assert_eq!(requests.connection_stats(), ConnectionStats::default());
+
+	while let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+		request.process(|_request| async move { RequestResult::Complete(0) }).await.unwrap();
+	}
+
+	let stats = requests.connection_stats();
+	assert_eq!(stats, ConnectionStats { requests: 3, keep_conn_requests: 2 });
+	assert!(stats.is_reused());
+
+	// A connection for a single request
+	let (_client, server) = connect(&simple_request(1, 0x00)).await;
+
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 1);
+	while let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+		request.process(|_request| async move { RequestResult::Complete(0) }).await.unwrap();
+	}
+
+	let stats = requests.connection_stats();
+	assert_eq!(stats, ConnectionStats { requests: 1, keep_conn_requests: 0 });
+	assert!(!stats.is_reused());
+}
+
+#[tokio::test]
+async fn drain_stdin() {
+	// The body of the first request is only partially read by the processor.
+	let mut input = create_record(RecordType::BeginRequest, 1, 0x00, &[ 0x00, RecordRole::Responder as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00]);
+	input.extend(create_record(RecordType::Params, 1, 0x00, b"\x0E\x04REQUEST_METHODPOST"));
+	input.extend(create_record(RecordType::Params, 1, 0x00, &[]));
+	input.extend(create_record(RecordType::StdIn, 1, 0x00, b"First part,"));
+	input.extend(create_record(RecordType::StdIn, 1, 0x00, b" second part"));
+	input.extend(create_record(RecordType::StdIn, 1, 0x00, &[]));
+	input.extend(simple_request(2, 0x00));
+	let (mut client, server) = connect(&input).await;
+
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 1);
+
+	let mut served = Vec::new();
+	while let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+		served.push(request.get_request_id());
+		request.process(|request| async move {
+			if request.get_str_param("REQUEST_METHOD") == Some("POST") {
+				let mut first = [0u8; 5];
+				request.get_stdin().read_exact(&mut first).unwrap();
+				assert_eq!(request.drain_stdin().await, 18);
+				assert_eq!(request.get_stdin().read(&mut first).unwrap(), 0);
+
+				request.respond_with(405, &[], &[]).await.unwrap()
+			} else {
+				RequestResult::Complete(0)
+			}
+		}).await.unwrap();
+	}
+	assert_eq!(served, vec![1, 2]);
+
+	drop(requests);
+
+	let (record_type, request_id, content) = read_record(&mut client).await.unwrap();
+	assert_eq!((record_type, request_id), (RecordType::StdOut as u8, 1));
+	assert!(content.starts_with(b"Status: 405 Method Not Allowed\r\n"));
+	expect_complete(&mut client, 1).await;
+	expect_complete(&mut client, 2).await;
+	assert_eq!(read_record(&mut client).await, None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn writer_desync() {
+	// The web-server does not read. A big record can only be written partially.
+	let (mut client, server) = tokio::io::duplex(16);
+	let request = Request::test_builder().build_with_writer(server);
+
+	let result = request.process(|request| async move {
+		let mut stdout = request.get_stdout();
+		assert!(tokio::time::timeout(Duration::from_secs(1), stdout.write(&[b'X'; 100])).await.is_err());
+
+		// The next record would be interpreted as content of the torn record.
+		assert!(matches!(stdout.write(b"More").await, Err(Error::WriterDesync)));
+
+		RequestResult::Complete(0)
+	}).await;
+	assert!(matches!(result, Err(Error::WriterDesync)));
+
+	// Only the beginning of the first record was sent.
+	let mut output = Vec::new();
+	client.read_to_end(&mut output).await.unwrap();
+	assert_eq!(output.len(), 16);
+	assert_eq!(&output[..8], &[1, RecordType::StdOut as u8, 0, 1, 0, 100, 0, 0]);
+}
+
+#[tokio::test]
+async fn unknown_roles() {
+	let mut input = simple_request(1, RecordFlags::KeepConn as u8);
+	input.extend(create_record(RecordType::BeginRequest, 2, 0x00, &[ 0x00, 0x2A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]));
+	input.extend(create_record(RecordType::Params, 2, 0x00, &[]));
+	input.extend(create_record(RecordType::StdIn, 2, 0x00, &[]));
+
+	// By default unknown roles end the connection.
+	let (_client, server) = connect(&input).await;
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 1);
+
+	let request = requests.next().await.expect("Request could not be constructed.").unwrap();
+	assert_eq!(request.try_role(), Ok(Role::Responder));
+	request.process(|_request| async move { RequestResult::Complete(0) }).await.unwrap();
+	assert!(matches!(requests.next().await, Err(Error::InvalidRoleNumber)));
+
+	// If unknown roles are allowed, the processor decides.
+	let (mut client, server) = connect(&input).await;
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 1).allow_unknown_roles(true);
+
+	let mut roles = Vec::new();
+	while let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+		roles.push(request.try_role());
+		request.process(|request| async move {
+			match request.try_role() {
+				Ok(_) => RequestResult::Complete(0),
+				Err(_) => RequestResult::UnknownRole
+			}
+		}).await.unwrap();
+	}
+	assert_eq!(roles, vec![Ok(Role::Responder), Err(42)]);
+
+	drop(requests);
+
+	expect_complete(&mut client, 1).await;
+	assert_eq!(read_record(&mut client).await, Some((RecordType::EndRequest as u8, 2, vec![0, 0, 0, 0, 3, 0, 0, 0])));
+	assert_eq!(read_record(&mut client).await, None);
+}
+
+#[tokio::test]
+async fn tcp_nodelay() {
+	let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+	let address = listener.local_addr().unwrap();
+
+	for nodelay in [true, false] {
+		let _client = tokio::net::TcpStream::connect(address).await.unwrap();
+		let (stream, _) = listener.accept().await.unwrap();
+
+		let requests = Requests::from_tcp_stream(stream, 1, 1, nodelay).unwrap();
+
+		let (reader, writer) = requests.into_inner().unwrap_or_else(|_| panic!("Connection could not be reclaimed."));
+		assert_eq!(reader.reunite(writer).unwrap().nodelay().unwrap(), nodelay);
+	}
+}
+
+#[tokio::test(start_paused = true)]
+async fn phases() {
+	let (mut requests, mut client) = Requests::new_duplex(64 * 1024);
+
+	// Waits until all records sent so far were consumed.
+	async fn consume(requests: &mut Requests<tokio::io::ReadHalf<DuplexStream>, tokio::io::WriteHalf<DuplexStream>>) {
+		assert!(tokio::time::timeout(Duration::from_secs(1), requests.next()).await.is_err());
+	}
+
+	client.write_all(&create_record(RecordType::BeginRequest, 1, 0x00, &[ 0x00, RecordRole::Filter as u8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00])).await.unwrap();
+	consume(&mut requests).await;
+	assert_eq!(requests.pending_requests(), vec![(1, Phase::AwaitingParams)]);
+
+	client.write_all(&create_record(RecordType::Params, 1, 0x00, b"\x03\x01IDX1")).await.unwrap();
+	client.write_all(&create_record(RecordType::Params, 1, 0x00, &[])).await.unwrap();
+	consume(&mut requests).await;
+	assert_eq!(requests.pending_requests(), vec![(1, Phase::AwaitingStdin)]);
+
+	client.write_all(&create_record(RecordType::StdIn, 1, 0x00, &[])).await.unwrap();
+	consume(&mut requests).await;
+	assert_eq!(requests.pending_requests(), vec![(1, Phase::AwaitingData)]);
+
+	client.write_all(&create_record(RecordType::Data, 1, 0x00, &[])).await.unwrap();
+	let request = requests.next().await.expect("Request could not be constructed.").unwrap();
+	assert!(requests.pending_requests().is_empty());
+	assert_eq!(request.phase(), Phase::Processing);
+
+	request.process(|request| async move {
+		let _stdin = request.get_stdin();
+		assert_eq!(request.phase(), Phase::Processing);
+		drop(_stdin);
+
+		request.abort(RequestResult::Complete(0)).await.unwrap();
+		assert_eq!(request.phase(), Phase::Complete);
+
+		RequestResult::Complete(0)
+	}).await.unwrap();
+
+	// The response fills the buffer of the connection. Ending the request blocks until the web-server reads it.
+	let (mut requests, mut client) = Requests::new_duplex(64);
+	client.write_all(&simple_request(1, 0x00)).await.unwrap();
+
+	let request = requests.next().await.expect("Request could not be constructed.").unwrap();
+	let processor = request.spawn_processor(|request| async move {
+		request.get_stdout().write(&[b'x'; 56]).await.unwrap();
+		assert_eq!(request.phase(), Phase::Processing);
+	});
+
+	tokio::time::sleep(Duration::from_secs(1)).await;
+	assert_eq!(requests.processing_requests(), vec![(1, Phase::Flushing)]);
+
+	assert_eq!(read_record(&mut client).await, Some((RecordType::StdOut as u8, 1, vec![b'x'; 56])));
+	expect_complete(&mut client, 1).await;
+	processor.await.unwrap().unwrap();
+	assert!(requests.processing_requests().is_empty());
+}
+
+#[tokio::test]
+async fn connection_limiter() {
+	let limiter = ConnectionLimiter::new(1);
+
+	// The first connection occupies the only slot.
+	let (_first_client, server) = connect(&[]).await;
+	let first = Requests::from_split_socket(tokio::io::split(server), 1, 1).connection_limiter(&limiter);
+	assert_eq!(limiter.active_connections(), 1);
+
+	// Excess connections reject their first request and get closed.
+	for _ in 0..2 {
+		let mut input = simple_request(1, RecordFlags::KeepConn as u8);
+		input.extend(simple_request(2, RecordFlags::KeepConn as u8));
+		let (mut client, server) = connect(&input).await;
+
+		let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 1).connection_limiter(&limiter);
+		assert!(requests.next().await.expect("Request could not be rejected.").is_none());
+		assert!(requests.next().await.expect("Connection could not be closed.").is_none());
+		drop(requests);
+
+		assert_eq!(read_record(&mut client).await, Some((RecordType::EndRequest as u8, 1, vec![0, 0, 0, 0, 2, 0, 0, 0])));
+		assert_eq!(read_record(&mut client).await, None);
+	}
+	assert_eq!(limiter.active_connections(), 1);
+
+	// Dropping the first connection frees the slot.
+	drop(first);
+	assert_eq!(limiter.active_connections(), 0);
+
+	let (mut client, server) = connect(&simple_request(1, 0x00)).await;
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 1).connection_limiter(&limiter);
+	let request = requests.next().await.expect("Request could not be constructed.").unwrap();
+	request.process(|_request| async move { RequestResult::Complete(0) }).await.unwrap();
+	drop(requests);
+
+	expect_complete(&mut client, 1).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn expect_continue() {
+	let (mut requests, mut client) = Requests::new_duplex(64 * 1024);
+
+	// The client waits for permission before the body is sent.
+	client.write_all(&create_record(RecordType::BeginRequest, 1, 0x00, &[ 0x00, RecordRole::Responder as u8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00])).await.unwrap();
+	client.write_all(&create_record(RecordType::Params, 1, 0x00, b"\x0b\x0cHTTP_EXPECT100-continue")).await.unwrap();
+	client.write_all(&create_record(RecordType::Params, 1, 0x00, &[])).await.unwrap();
+
+	// The request is not returned without the body, but the params were consumed.
+	assert!(tokio::time::timeout(Duration::from_secs(1), requests.next()).await.is_err());
+	assert_eq!(requests.pending_requests(), vec![(1, Phase::AwaitingStdin)]);
+
+	// The body is pulled through as soon as the web-server forwards it.
+	client.write_all(&create_record(RecordType::StdIn, 1, 0x00, b"BODY")).await.unwrap();
+	client.write_all(&create_record(RecordType::StdIn, 1, 0x00, &[])).await.unwrap();
+
+	let request = requests.next().await.expect("Request could not be constructed.").unwrap();
+	assert!(request.expects_continue());
+
+	let mut body = Vec::new();
+	request.get_stdin().read_to_end(&mut body).unwrap();
+	assert_eq!(body, b"BODY");
+}
+
+#[tokio::test]
+async fn accepted_roles() {
+	let mut input = create_record(RecordType::BeginRequest, 1, 0x00, &[ 0x00, RecordRole::Authorizer as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00]);
+	input.extend(create_record(RecordType::Params, 1, 0x00, b"\x03\x01IDX1"));
+	input.extend(create_record(RecordType::Params, 1, 0x00, &[]));
+	input.extend(simple_request(2, 0x00));
+	let (mut client, server) = connect(&input).await;
+
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 1).accepted_roles(&[Role::Responder]);
+
+	// Only the responder reaches the processor.
+	let mut served = Vec::new();
+	while let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+		served.push(request.get_request_id());
+		request.process(|_request| async move { RequestResult::Complete(0) }).await.unwrap();
+	}
+	assert_eq!(served, vec![2]);
+	drop(requests);
+
+	// The authorizer is answered with FCGI_UNKNOWN_ROLE as soon as it begins.
+	assert_eq!(read_record(&mut client).await, Some((RecordType::EndRequest as u8, 1, vec![0, 0, 0, 0, 3, 0, 0, 0])));
+	expect_complete(&mut client, 2).await;
+	assert_eq!(read_record(&mut client).await, None);
+}
+
+#[tokio::test]
+async fn on_begin() {
+	let mut input = create_record(RecordType::BeginRequest, 1, 0x00, &[ 0x00, RecordRole::Filter as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00]);
+	input.extend(create_record(RecordType::Params, 1, 0x00, b"\x03\x01IDX1"));
+	let (mut client, server) = connect(&input).await;
+
+	let begun = Arc::new(std::sync::Mutex::new(Vec::new()));
+	let hook_begun = begun.clone();
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 1)
+		.on_begin(move |info| {
+			let reject = info.role == Some(Role::Filter);
+			hook_begun.lock().unwrap().push((info.request_id, info.role_number, info.keep_connection));
+			reject.then_some(RequestResult::UnknownRole)
+		});
+
+	// The filter is ended as soon as it begins. Its parameters are not buffered.
+	let next = tokio::spawn(async move {
+		let request = requests.next().await.expect("Request could not be constructed.").expect("Request was not received.");
+		(requests, request.get_request_id())
+	});
+	assert_eq!(read_record(&mut client).await, Some((RecordType::EndRequest as u8, 1, vec![0, 0, 0, 0, 3, 0, 0, 0])));
+
+	// Its remaining records are skipped. The next request is served normally.
+	client.write_all(&create_record(RecordType::Params, 1, 0x00, &[])).await.unwrap();
+	client.write_all(&create_record(RecordType::StdIn, 1, 0x00, b"Body")).await.unwrap();
+	client.write_all(&create_record(RecordType::Data, 1, 0x00, b"Data")).await.unwrap();
+	client.write_all(&simple_request(2, 0x00)).await.unwrap();
+
+	let (requests, request_id) = next.await.unwrap();
+	assert_eq!(request_id, 2);
+	assert!(requests.pending_requests().is_empty());
+	assert_eq!(requests.connection_stats().requests, 1);
+
+	assert_eq!(*begun.lock().unwrap(), vec![(1, 3, true), (2, 1, false)]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn stdin_read_timeout() {
+	let (requests, mut client) = Requests::new_duplex(64 * 1024);
+	let mut requests = requests.record_timeout(Duration::from_secs(1)).stdin_read_timeout(Duration::from_secs(5));
+
+	// The body is announced but only sent in tiny records. Every record arrives well within the record timeout.
+	client.write_all(&create_record(RecordType::BeginRequest, 1, 0x00, &[ 0x00, RecordRole::Responder as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00])).await.unwrap();
+	client.write_all(&create_record(RecordType::Params, 1, 0x00, b"\x0e\x03CONTENT_LENGTH100")).await.unwrap();
+	client.write_all(&create_record(RecordType::Params, 1, 0x00, &[])).await.unwrap();
+
+	let (mut client_read, mut client_write) = tokio::io::split(client);
+	let drip = async {
+		loop {
+			tokio::time::sleep(Duration::from_millis(500)).await;
+			client_write.write_all(&create_record(RecordType::StdIn, 1, 0x00, b"x")).await.unwrap();
+		}
+	};
+
+	let start = tokio::time::Instant::now();
+	let result = tokio::select! {
+		result = requests.next() => result,
+		_ = drip => unreachable!()
+	};
+
+	match result {
+		Err(Error::StdinTimeout(1)) => assert_eq!(start.elapsed(), Duration::from_secs(5)),
+		Err(err) => panic!("Unexpected error: {}", err),
+		Ok(_) => panic!("Request returned without its body.")
+	}
+	assert!(requests.pending_requests().is_empty());
+	assert_eq!(read_record(&mut client_read).await, Some((RecordType::EndRequest as u8, 1, vec![0, 0, 0, 1, 0, 0, 0, 0])));
+
+	// Late records of the ended request are ignored. The connection stays usable.
+	client_write.write_all(&create_record(RecordType::StdIn, 1, 0x00, b"x")).await.unwrap();
+	client_write.write_all(&simple_request(2, 0x00)).await.unwrap();
+
+	let request = requests.next().await.expect("Request could not be constructed.").unwrap();
+	assert_eq!(request.get_request_id(), 2);
+}
+
+#[tokio::test]
+async fn max_body_size() {
+	let (mut client, server) = tokio::io::duplex(64 * 1024);
+	let mut requests = Requests::from_split_socket(tokio::io::split(server), 1, 10).max_body_size(8);
+
+	// Request 1 stays within the limit. The body of request 2 exceeds it.
+	for request_id in 1..=2 {
+		client.write_all(&create_record(RecordType::BeginRequest, request_id, 0x00, &[ 0x00, RecordRole::Responder as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00])).await.unwrap();
+		client.write_all(&create_record(RecordType::Params, request_id, 0x00, &[])).await.unwrap();
+	}
+	client.write_all(&create_record(RecordType::StdIn, 1, 0x00, b"abcd")).await.unwrap();
+	client.write_all(&create_record(RecordType::StdIn, 2, 0x00, b"12345")).await.unwrap();
+	client.write_all(&create_record(RecordType::StdIn, 2, 0x00, b"6789")).await.unwrap();
+
+	match requests.next().await {
+		Err(Error::BodySizeExceeded(2)) => (),
+		Err(err) => panic!("Unexpected error: {}", err),
+		Ok(_) => panic!("Request returned despite its oversized body.")
+	}
+	assert_eq!(read_record(&mut client).await, Some((RecordType::EndRequest as u8, 2, vec![0, 0, 0, 0, 2, 0, 0, 0])));
+	assert_eq!(requests.pending_requests().len(), 1);
+
+	// Late records of the ended request are ignored. Request 1 is not affected.
+	client.write_all(&create_record(RecordType::StdIn, 2, 0x00, b"0")).await.unwrap();
+	client.write_all(&create_record(RecordType::StdIn, 1, 0x00, b"efgh")).await.unwrap();
+	client.write_all(&create_record(RecordType::StdIn, 1, 0x00, &[])).await.unwrap();
+
+	let request = requests.next().await.expect("Request could not be constructed.").unwrap();
+	assert_eq!(request.get_request_id(), 1);
+
+	let mut body = Vec::new();
+	request.get_stdin().read_to_end(&mut body).unwrap();
+	assert_eq!(body, b"abcdefgh");
+}
+
+#[tokio::test(start_paused = true)]
+async fn slow_requests() {
+	let slow_requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+	let (requests, mut client) = Requests::new_duplex(64 * 1024);
+	let reported = slow_requests.clone();
+	let mut requests = requests
+		.slow_request_threshold(Duration::from_secs(1))
+		.on_slow_request(move |request| reported.lock().unwrap().push(request.clone()));
+
+	for (request_id, delay) in [(1u8, 500), (2u8, 1500)] {
+		client.write_all(&create_record(RecordType::BeginRequest, request_id, 0x00, &[ 0x00, RecordRole::Responder as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00])).await.unwrap();
+		client.write_all(&create_record(RecordType::Params, request_id, 0x00, b"\x0e\x03REQUEST_METHODGET\x0b\x05REQUEST_URI/slow")).await.unwrap();
+		client.write_all(&create_record(RecordType::Params, request_id, 0x00, &[])).await.unwrap();
+		client.write_all(&create_record(RecordType::StdIn, request_id, 0x00, &[])).await.unwrap();
+
+		let request = requests.next().await.expect("Request could not be constructed.").unwrap();
+		request.process(|_request| async move {
+			tokio::time::sleep(Duration::from_millis(delay)).await;
+		}).await.unwrap();
+	}
+
+	// Only the second request exceeded the threshold.
+	assert_eq!(*slow_requests.lock().unwrap(), vec![SlowRequest {
+		request_id: 2,
+		method: Some(String::from("GET")),
+		uri: Some(String::from("/slow")),
+		elapsed: Duration::from_millis(1500)
+	}]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn on_complete() {
+	let summaries = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+	let (requests, mut client) = Requests::new_duplex(64 * 1024);
+	let reported = summaries.clone();
+	let mut requests = requests.on_complete(move |summary| reported.lock().unwrap().push(summary.clone()));
+
+	for request_id in 1..=2 {
+		client.write_all(&create_record(RecordType::BeginRequest, request_id, 0x00, &[ 0x00, RecordRole::Responder as u8, RecordFlags::KeepConn as u8, 0x00, 0x00, 0x00, 0x00, 0x00])).await.unwrap();
+		client.write_all(&create_record(RecordType::Params, request_id, 0x00, b"\x0e\x03REQUEST_METHODGET\x0b\x04REQUEST_URI/log")).await.unwrap();
+		client.write_all(&create_record(RecordType::Params, request_id, 0x00, &[])).await.unwrap();
+		client.write_all(&create_record(RecordType::StdIn, request_id, 0x00, &[])).await.unwrap();
+
+		let request = requests.next().await.expect("Request could not be constructed.").unwrap();
+		request.process(|request| async move {
+			tokio::time::sleep(Duration::from_millis(500)).await;
+			if request.get_request_id() == 1 {
+				request.respond_with(404, &[("Content-Type", "text/plain")], b"Missing").await.unwrap()
+			} else {
+				request.get_stdout().write(b"Content-Type: text/plain\r\n\r\nHi").await.unwrap();
+				RequestResult::Complete(3)
+			}
+		}).await.unwrap();
+
+		// The hook was called after the request was ended.
+		assert_eq!(summaries.lock().unwrap().len(), request_id as usize);
+		let mut end_request = None;
+		while let Some((record_type, _, content)) = read_record(&mut client).await {
+			if record_type == RecordType::EndRequest as u8 {
+				end_request = Some(content);
+				break;
+			}
+		}
+		assert!(end_request.is_some());
+	}
+
+	assert_eq!(*summaries.lock().unwrap(), vec![
+		RequestSummary {
+			request_id: 1,
+			method: Some(String::from("GET")),
+			uri: Some(String::from("/log")),
+			status: Some(404),
+			result: RequestResult::Complete(0),
+			bytes_out: b"Status: 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: 7\r\n\r\nMissing".len() as u64,
+			elapsed: Duration::from_millis(500)
+		},
+		RequestSummary {
+			request_id: 2,
+			method: Some(String::from("GET")),
+			uri: Some(String::from("/log")),
+			status: None,
+			result: RequestResult::Complete(3),
+			bytes_out: b"Content-Type: text/plain\r\n\r\nHi".len() as u64,
+			elapsed: Duration::from_millis(500)
+		}
+	]);
+}
@@ -5,13 +5,14 @@
 //! The tests are declared within commons.rs because they are the same as
 //! the server tests. That way a test can be used directly on the API
 //! and via the network to properly test the FastCGI implementation.
+#[allow(dead_code)]
 mod commons;
 use tokio_fastcgi::Requests;
 
 use crate::commons::*;
 
 pub async fn run_test<T: TestCase>() {
-	let mut requests = Requests::new(T::get_input(), T::get_output(), 5, 10);
+	let mut requests = T::configure(Requests::new(T::get_input(), T::get_output(), 5, 10));
 	while let Some(request) = requests.next().await.expect("Request could not be constructed.") {
 		request.process(T::processor).await.expect("Error while processing.");
 	}
@@ -32,6 +33,16 @@ async fn role_filter() {
 	run_test::<TestRoleFilter>().await;
 }
 
+#[tokio::test]
+async fn role_filter_split_data() {
+	run_test::<TestRoleFilterSplitData>().await;
+}
+
+#[tokio::test]
+async fn role_filter_take_data() {
+	run_test::<TestRoleFilterTakeData>().await;
+}
+
 #[tokio::test]
 async fn abort_request() {
 	run_test::<TestAbortRequest>().await;
@@ -66,4 +77,49 @@ async fn get_values() {
 #[tokio::test]
 async fn keep_connection() {
 	run_test::<TestKeepConnection>().await;
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn respond_with() {
+	run_test::<TestRespondWith>().await;
+}
+
+#[tokio::test]
+async fn reject_too_large() {
+	run_test::<TestRejectTooLarge>().await;
+}
+
+#[tokio::test]
+async fn trickled_stdin() {
+	run_test::<TestTrickledStdIn>().await;
+}
+
+#[tokio::test]
+async fn long_params() {
+	run_test::<TestLongParams>().await;
+}
+
+#[tokio::test]
+async fn abort_by_application() {
+	run_test::<TestAbortByApplication>().await;
+}
+
+#[tokio::test]
+async fn data_for_responder() {
+	run_test::<TestDataForResponder>().await;
+}
+
+#[tokio::test]
+async fn default_content_type() {
+	run_test::<TestDefaultContentType>().await;
+}
+
+#[tokio::test]
+async fn stream_terminators() {
+	run_test::<TestStreamTerminators>().await;
+}
+
+#[tokio::test]
+async fn set_status() {
+	run_test::<TestSetStatus>().await;
+}
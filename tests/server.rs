@@ -24,6 +24,7 @@ use std::sync::Mutex;
 use std::sync::mpsc::sync_channel;
 use once_cell::sync::Lazy;
 
+#[allow(dead_code)]
 mod commons;
 use crate::commons::*;
 
@@ -67,7 +68,7 @@ fn network_test<T: TestCase>() {
 						let done_sender = done_sender.clone();
 
 						tokio::spawn(async move {
-							let mut requests = Requests::from_split_socket(socket.0.split(), 5, 10);
+							let mut requests = T::configure(Requests::from_split_socket(socket.0.split(), 5, 10));
 
 							while let Some(request) = requests.next().await.expect("Request could not be constructed.") {
 								request.process(T::processor).await.expect("Error while processing.");
@@ -106,13 +107,16 @@ fn network_test<T: TestCase>() {
 			let mut output = T::get_output();
 			let mut buffer: Vec<u8> = Vec::new();
 			loop {
+				// Read before checking for the done message. Otherwise a server that
+				// is faster than the client would make us skip its output.
+				buffer.clear();
+				s.read_to_end(&mut buffer).unwrap();
+				output.write_all(&buffer).await.unwrap();
+
 				if let Ok(revc_count) = done_receiver.try_recv() {
 					assert_eq!(revc_count, 1, "More than one connection was encountered.");
 					break;
 				}
-
-				s.read_to_end(&mut buffer).unwrap();
-				output.write_all(&buffer).await.unwrap();
 			}
 		}
 	});
@@ -156,6 +160,16 @@ fn role_filter() {
 	run_network_test::<TestRoleFilter>();
 }
 
+#[test]
+fn role_filter_split_data() {
+	run_network_test::<TestRoleFilterSplitData>();
+}
+
+#[test]
+fn role_filter_take_data() {
+	run_network_test::<TestRoleFilterTakeData>();
+}
+
 #[test]
 fn abort_request() {
 	run_network_test::<TestAbortRequest>();
@@ -184,3 +198,48 @@ fn get_values() {
 fn keep_connection() {
 	run_network_test::<TestKeepConnection>();
 }
+
+#[test]
+fn respond_with() {
+	run_network_test::<TestRespondWith>();
+}
+
+#[test]
+fn reject_too_large() {
+	run_network_test::<TestRejectTooLarge>();
+}
+
+#[test]
+fn trickled_stdin() {
+	run_network_test::<TestTrickledStdIn>();
+}
+
+#[test]
+fn long_params() {
+	run_network_test::<TestLongParams>();
+}
+
+#[test]
+fn abort_by_application() {
+	run_network_test::<TestAbortByApplication>();
+}
+
+#[test]
+fn data_for_responder() {
+	run_network_test::<TestDataForResponder>();
+}
+
+#[test]
+fn default_content_type() {
+	run_network_test::<TestDefaultContentType>();
+}
+
+#[test]
+fn stream_terminators() {
+	run_network_test::<TestStreamTerminators>();
+}
+
+#[test]
+fn set_status() {
+	run_network_test::<TestSetStatus>();
+}
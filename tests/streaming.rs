@@ -0,0 +1,139 @@
+//! This source file verifies that output written to `StdOut` is delivered
+//! to the web-server as soon as the output stream is flushed, even if the
+//! processor is still running.
+//!
+//! The write half of the connection is wrapped into a `BufWriter`. Without
+//! flushing, the records would stay within its buffer and the tests would
+//! time out waiting for them.
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, BufWriter, DuplexStream, ReadHalf, WriteHalf};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio_fastcgi::{Request, RequestResult, Requests};
+
+#[allow(dead_code)]
+mod commons;
+use crate::commons::*;
+
+/// Maximum time to wait for a record from the FastCGI server.
+const RECORD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Type of the writer used by the FastCGI server.
+type ServerWriter = BufWriter<WriteHalf<DuplexStream>>;
+
+/// Starts a FastCGI server on an in memory connection that serves exactly one
+/// request with the passed processor. Returns the server task and the client
+/// side of the connection.
+fn start_server<C, F>(processor: C) -> (JoinHandle<()>, DuplexStream)
+where
+	C: FnOnce(Arc<Request<ServerWriter>>) -> F + Send + 'static,
+	F: Future<Output = RequestResult> + Send
+{
+	let (client, server) = tokio::io::duplex(1024);
+
+	let server = tokio::spawn(async move {
+		let (rd, wr) = tokio::io::split(server);
+		let mut requests = Requests::new(rd, BufWriter::new(wr), 1, 1);
+
+		if let Some(request) = requests.next().await.expect("Request could not be constructed.") {
+			request.process(processor).await.expect("Error while processing.");
+		}
+	});
+
+	(server, client)
+}
+
+/// Sends a responder request without any parameters or input.
+async fn send_request(client: &mut DuplexStream) {
+	let mut input = create_record(RecordType::BeginRequest, 0x01, 0x00, &[ 0x00, RecordRole::Responder as u8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+	input.extend(create_record(RecordType::Params, 0x01, 0x00, &[]));
+	input.extend(create_record(RecordType::StdIn, 0x01, 0x00, &[]));
+
+	client.write_all(&input).await.unwrap();
+}
+
+/// Reads the next record and fails if it does not arrive in time.
+async fn expect_record(rd: &mut ReadHalf<&mut DuplexStream>) -> (u8, u16, Vec<u8>) {
+	timeout(RECORD_TIMEOUT, read_record(rd)).await
+		.expect("Record was not delivered.")
+		.expect("Connection closed unexpectedly.")
+}
+
+/// Reads the records that terminate a request and returns the content of the
+/// `EndRequest` record.
+async fn expect_end(rd: &mut ReadHalf<&mut DuplexStream>) -> Vec<u8> {
+	assert_eq!(expect_record(rd).await, (RecordType::StdOut as u8, 1, Vec::new()));
+	assert_eq!(expect_record(rd).await, (RecordType::StdErr as u8, 1, Vec::new()));
+
+	let (record_type, request_id, content) = expect_record(rd).await;
+	assert_eq!(record_type, RecordType::EndRequest as u8);
+	assert_eq!(request_id, 1);
+
+	content
+}
+
+#[tokio::test]
+async fn flushed_writes_are_delivered() {
+	let (ack_tx, mut ack_rx) = mpsc::channel(1);
+
+	let (server, mut client) = start_server(|request| async move {
+		let mut stdout = request.get_stdout();
+
+		for event in 0..3 {
+			stdout.write(format!("data: event {}\n\n", event).as_bytes()).await.unwrap();
+			stdout.flush().await.unwrap();
+
+			// Wait for the client to receive the event before producing the next one.
+			ack_rx.recv().await.unwrap();
+		}
+
+		RequestResult::Complete(0)
+	});
+
+	send_request(&mut client).await;
+
+	let (mut rd, _wr) = tokio::io::split(&mut client);
+	for event in 0..3 {
+		assert_eq!(expect_record(&mut rd).await, (RecordType::StdOut as u8, 1, format!("data: event {}\n\n", event).into_bytes()));
+		ack_tx.send(()).await.unwrap();
+	}
+
+	assert_eq!(expect_end(&mut rd).await, &[0, 0, 0, 0, 0, 0, 0, 0]);
+
+	server.await.unwrap();
+}
+
+#[tokio::test]
+async fn headers_are_delivered_before_the_body() {
+	let (release_tx, release_rx) = tokio::sync::oneshot::channel();
+
+	let (server, mut client) = start_server(|request| async move {
+		let mut stdout = request.get_stdout();
+
+		stdout.write(b"Status: 200 OK\r\nContent-Type: text/plain\r\n\r\n").await.unwrap();
+		stdout.flush().await.unwrap();
+
+		// Simulate a long computation of the body. It does not finish before the
+		// client has seen the headers.
+		release_rx.await.unwrap();
+		tokio::time::sleep(Duration::from_millis(10)).await;
+
+		stdout.write(b"Slow body").await.unwrap();
+
+		RequestResult::Complete(0)
+	});
+
+	send_request(&mut client).await;
+
+	let (mut rd, _wr) = tokio::io::split(&mut client);
+	assert_eq!(expect_record(&mut rd).await, (RecordType::StdOut as u8, 1, b"Status: 200 OK\r\nContent-Type: text/plain\r\n\r\n".to_vec()));
+	release_tx.send(()).unwrap();
+
+	assert_eq!(expect_record(&mut rd).await, (RecordType::StdOut as u8, 1, b"Slow body".to_vec()));
+	assert_eq!(expect_end(&mut rd).await, &[0, 0, 0, 0, 0, 0, 0, 0]);
+
+	server.await.unwrap();
+}